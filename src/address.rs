@@ -0,0 +1,80 @@
+use std::fmt::{Display, Error};
+
+/// Lump address, it survives a save then a reload
+/// because it only depends on the names and the order
+///
+/// - `NAME` is the first lump named `NAME`
+/// - `NAME#n` is the lump named `NAME` after `n` others (`THINGS#1` is the second one)
+/// - `MAP01/THINGS` and `MAP01/THINGS#n` only look within the map `MAP01`
+///
+/// The removed lumps are skipped
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LumpAddress {
+    /// Map containing the lump
+    pub map: Option<String>,
+    /// Lump name
+    pub name: String,
+    /// Amount of lumps with the same name before it
+    pub n: usize,
+}
+
+impl LumpAddress {
+    pub fn new(name: &str) -> Self {
+        Self {
+            map: None,
+            name: name.to_string(),
+            n: 0,
+        }
+    }
+
+    /// Parse an address, `None` if it is malformed
+    pub fn parse(value: &str) -> Option<Self> {
+        let (map, value) = match value.split_once('/') {
+            Some((map, value)) => (Some(map.to_string()), value),
+            None => (None, value),
+        };
+        let (name, n) = match value.split_once('#') {
+            Some((name, n)) => (name, n.parse().ok()?),
+            None => (value, 0),
+        };
+
+        if name.is_empty() || map.as_ref().is_some_and(|m| m.is_empty()) {
+            return None;
+        }
+
+        Some(Self {
+            map,
+            name: name.to_string(),
+            n,
+        })
+    }
+
+    /// Find the lump among `entries`, following the directory order
+    ///
+    /// An entry is the lump directory index, its name and its map
+    pub fn find<'a, I>(&self, entries: I) -> Option<usize>
+    where
+        I: IntoIterator<Item = (usize, &'a str, Option<&'a str>)>,
+    {
+        entries
+            .into_iter()
+            .filter(|(_, name, map)| {
+                *name == self.name && (self.map.is_none() || self.map.as_deref() == *map)
+            })
+            .nth(self.n)
+            .map(|(index, _, _)| index)
+    }
+}
+
+impl Display for LumpAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), Error> {
+        if let Some(map) = &self.map {
+            write!(f, "{}/", map)?;
+        }
+
+        match self.n {
+            0 => write!(f, "{}", self.name),
+            n => write!(f, "{}#{}", self.name, n),
+        }
+    }
+}
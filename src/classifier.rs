@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+
+use crate::{
+    dir::MAP_LUMPS,
+    lump::LumpKind,
+    lumps::{flat::FLAT_SIZE, music::mus::MUS_MAGIC},
+    namespace::Namespace,
+};
+
+/// PNG magic bytes
+pub const PNG_MAGIC: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+/// MIDI magic bytes
+pub const MIDI_MAGIC: [u8; 4] = [0x4d, 0x54, 0x68, 0x64];
+/// DMX digital sound format
+pub const DMX_FORMAT: u16 = 3;
+
+/// Names shared by the id Tech 1 games
+const NAMES_COMMON: &[(&str, LumpKind)] = &[
+    ("PLAYPAL", LumpKind::Palette),
+    ("COLORMAP", LumpKind::Colormap),
+    ("TEXTURE1", LumpKind::Texture),
+    ("TEXTURE2", LumpKind::Texture),
+    ("PNAMES", LumpKind::PNames),
+    ("GENMIDI", LumpKind::Genmidi),
+    ("MAP??", LumpKind::Map),
+    ("E?M?", LumpKind::Map),
+];
+
+/// DOOM, DOOM 2, Final DOOM, Chex Quest names
+const NAMES_DOOM: &[(&str, LumpKind)] = &[
+    ("ENDOOM", LumpKind::Endoom),
+    ("TITLEPIC", LumpKind::Patch),
+    ("INTERPIC", LumpKind::Patch),
+    ("CREDIT", LumpKind::Patch),
+    ("HELP", LumpKind::Patch),
+    ("HELP?", LumpKind::Patch),
+    ("VICTORY2", LumpKind::Patch),
+    ("ENDPIC", LumpKind::Patch),
+    ("BOSSBACK", LumpKind::Patch),
+    ("PFUB?", LumpKind::Patch),
+    ("END?", LumpKind::Patch),
+    ("ST*", LumpKind::Patch),
+    ("M_*", LumpKind::Patch),
+    ("WI*", LumpKind::Patch),
+    ("CWILV*", LumpKind::Patch),
+    ("AMMNUM?", LumpKind::Patch),
+    ("BRDR_*", LumpKind::Patch),
+    ("DS*", LumpKind::Sound),
+    ("DP*", LumpKind::Sound),
+    ("D_*", LumpKind::Music),
+];
+
+/// Heretic names
+const NAMES_HERETIC: &[(&str, LumpKind)] = &[
+    ("ENDTEXT", LumpKind::Endoom),
+    ("MUS_*", LumpKind::Music),
+    ("FONTA*", LumpKind::Patch),
+    ("FONTB*", LumpKind::Patch),
+    ("SMALLIN?", LumpKind::Patch),
+    ("BARBACK", LumpKind::Patch),
+    ("STATBAR", LumpKind::Patch),
+    ("LTFACE", LumpKind::Patch),
+    ("RTFACE", LumpKind::Patch),
+    ("INVBAR", LumpKind::Patch),
+    ("LIFEBAR", LumpKind::Patch),
+    ("CHAIN", LumpKind::Patch),
+    ("M_*", LumpKind::Patch),
+    ("IN_*", LumpKind::Patch),
+];
+
+/// Hexen names
+const NAMES_HEXEN: &[(&str, LumpKind)] = &[
+    ("FONTA*", LumpKind::Patch),
+    ("FONTB*", LumpKind::Patch),
+    ("H2BAR", LumpKind::Patch),
+    ("H2TOP", LumpKind::Patch),
+    ("STATBAR", LumpKind::Patch),
+    ("INVBAR", LumpKind::Patch),
+    ("M_*", LumpKind::Patch),
+];
+
+/// Strife names
+const NAMES_STRIFE: &[(&str, LumpKind)] = &[
+    ("ENDSTRF", LumpKind::Endoom),
+    ("TITLEPIC", LumpKind::Patch),
+    ("HELP?", LumpKind::Patch),
+    ("STBAR*", LumpKind::Patch),
+    ("M_*", LumpKind::Patch),
+    ("DS*", LumpKind::Sound),
+    ("D_*", LumpKind::Music),
+];
+
+/// How sure a classification is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Confidence {
+    /// Weak hint, like a size
+    Low,
+    /// Plausible content
+    Medium,
+    /// Known name, namespace or magic bytes
+    High,
+    /// Marker or caller decision
+    Certain,
+}
+
+/// A lump kind with its confidence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Classification {
+    /// Lump kind
+    pub kind: LumpKind,
+    /// How sure the classification is
+    pub confidence: Confidence,
+}
+
+impl Classification {
+    pub fn new(kind: LumpKind, confidence: Confidence) -> Self {
+        Self { kind, confidence }
+    }
+}
+
+impl Default for Classification {
+    fn default() -> Self {
+        Self::new(LumpKind::Unknown, Confidence::Low)
+    }
+}
+
+/// The informations known about a lump when it is classified
+pub struct ClassifyInput<'a> {
+    /// Lump name
+    pub name: &'a str,
+    /// Lump size in bytes
+    pub size: usize,
+    /// Enclosing namespace
+    pub namespace: Option<&'a Namespace>,
+    /// Lump content, `None` if it has not been fetched (streaming)
+    pub buffer: Option<&'a [u8]>,
+}
+
+/// A classification rule
+pub trait Classify {
+    /// Returns a classification if the rule recognizes the lump
+    fn classify(&self, input: &ClassifyInput) -> Option<Classification>;
+}
+
+/// Supported games, used to select the known names tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Game {
+    Doom,
+    Heretic,
+    Hexen,
+    Strife,
+}
+
+impl Game {
+    /// Known names table
+    fn names(&self) -> &'static [(&'static str, LumpKind)] {
+        match self {
+            Self::Doom => NAMES_DOOM,
+            Self::Heretic => NAMES_HERETIC,
+            Self::Hexen => NAMES_HEXEN,
+            Self::Strife => NAMES_STRIFE,
+        }
+    }
+}
+
+/// Lumps classifier
+///
+/// The caller overrides win, then the most confident result between
+/// the custom rules, the content sniffing, the namespace and the known names,
+/// the first one winning a tie (the magic bytes beat the namespace)
+#[derive(Default)]
+pub struct LumpClassifier {
+    /// Names table selection, every table is used if `None`
+    game: Option<Game>,
+    /// Caller decisions by lump name
+    overrides: HashMap<String, LumpKind>,
+    /// Caller rules, tried before the default ones
+    rules: Vec<Box<dyn Classify>>,
+}
+
+impl LumpClassifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only use the known names of `game`
+    pub fn set_game(&mut self, game: Option<Game>) {
+        self.game = game;
+    }
+
+    /// Force the kind of the lumps named `name`
+    pub fn set_override(&mut self, name: &str, kind: LumpKind) {
+        self.overrides.insert(name.to_string(), kind);
+    }
+
+    /// Add a caller rule
+    pub fn push_rule(&mut self, rule: Box<dyn Classify>) {
+        self.rules.push(rule);
+    }
+
+    /// Classify a lump, `Unknown` with a low confidence if nothing matched
+    pub fn classify(&self, input: &ClassifyInput) -> Classification {
+        if let Some(kind) = self.overrides.get(input.name) {
+            return Classification::new(*kind, Confidence::Certain);
+        }
+
+        let mut best: Option<Classification> = None;
+        let results = self.rules.iter().map(|rule| rule.classify(input)).chain([
+            by_content(input),
+            by_namespace(input),
+            self.by_name(input),
+        ]);
+
+        for result in results.flatten() {
+            if best.is_none_or(|b| result.confidence > b.confidence) {
+                best = Some(result);
+            }
+        }
+
+        best.unwrap_or_default()
+    }
+
+    /// Known names tables lookup
+    ///
+    /// The patterns (`ST*`, `END?`) only apply outside the namespaces
+    fn by_name(&self, input: &ClassifyInput) -> Option<Classification> {
+        if MAP_LUMPS.contains(&input.name) {
+            return Some(Classification::new(LumpKind::Map, Confidence::High));
+        }
+
+        let tables: Vec<&[(&str, LumpKind)]> = match self.game {
+            Some(game) => vec![NAMES_COMMON, game.names()],
+            None => vec![
+                NAMES_COMMON,
+                NAMES_DOOM,
+                NAMES_HERETIC,
+                NAMES_HEXEN,
+                NAMES_STRIFE,
+            ],
+        };
+
+        tables
+            .iter()
+            .flat_map(|table| table.iter())
+            .filter(|(pattern, _)| input.namespace.is_none() || !pattern.contains(['*', '?']))
+            .find(|(pattern, _)| glob_match(pattern, input.name))
+            .map(|(_, kind)| Classification::new(*kind, Confidence::High))
+    }
+}
+
+/// Namespace lookup
+fn by_namespace(input: &ClassifyInput) -> Option<Classification> {
+    if input.size == 0 {
+        return None;
+    }
+
+    let kind = match input.namespace? {
+        Namespace::Sprites | Namespace::Patches => LumpKind::Patch,
+        Namespace::Flats => LumpKind::Flat,
+        Namespace::Colormaps => LumpKind::Colormap,
+        _ => return None,
+    };
+
+    Some(Classification::new(kind, Confidence::High))
+}
+
+/// Content sniffing
+fn by_content(input: &ClassifyInput) -> Option<Classification> {
+    let buffer = input.buffer?;
+
+    if buffer.starts_with(&PNG_MAGIC) {
+        return Some(Classification::new(LumpKind::Png, Confidence::High));
+    }
+
+    if buffer.starts_with(&MUS_MAGIC) || buffer.starts_with(&MIDI_MAGIC) {
+        return Some(Classification::new(LumpKind::Music, Confidence::High));
+    }
+
+    if is_dmx_sound(buffer) {
+        return Some(Classification::new(LumpKind::Sound, Confidence::Medium));
+    }
+
+    if is_patch(buffer) {
+        return Some(Classification::new(LumpKind::Patch, Confidence::Medium));
+    }
+
+    if buffer.len() == FLAT_SIZE {
+        return Some(Classification::new(LumpKind::Flat, Confidence::Low));
+    }
+
+    None
+}
+
+/// Check the DMX sound header (format, rate and samples amount)
+pub fn is_dmx_sound(buffer: &[u8]) -> bool {
+    if buffer.len() < 8 {
+        return false;
+    }
+
+    let format = u16::from_le_bytes([buffer[0], buffer[1]]);
+    let rate = u16::from_le_bytes([buffer[2], buffer[3]]);
+    let samples = u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]) as usize;
+
+    format == DMX_FORMAT && (4000..=48000).contains(&rate) && samples + 8 <= buffer.len()
+}
+
+/// Check the DOOM picture header and the columns bounds
+pub fn is_patch(buffer: &[u8]) -> bool {
+    if buffer.len() < 8 {
+        return false;
+    }
+
+    let width = u16::from_le_bytes([buffer[0], buffer[1]]) as usize;
+    let height = u16::from_le_bytes([buffer[2], buffer[3]]) as usize;
+    let columns = 8 + width * 4;
+
+    if width == 0 || height == 0 || width > 4096 || height > 4096 || columns > buffer.len() {
+        return false;
+    }
+
+    for i in 0..width {
+        let pos = 8 + i * 4;
+        let mut offset = u32::from_le_bytes(buffer[pos..pos + 4].try_into().unwrap()) as usize;
+
+        if offset < columns {
+            return false;
+        }
+
+        // Walk the posts until the column end
+        loop {
+            match buffer.get(offset) {
+                Some(0xff) => break,
+                Some(_) => match buffer.get(offset + 1) {
+                    Some(length) => offset += *length as usize + 4,
+                    None => return false,
+                },
+                None => return false,
+            }
+        }
+    }
+
+    true
+}
+
+/// Match `name` with a glob pattern (`*` and `?`)
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    glob_match_bytes(pattern.as_bytes(), name.as_bytes())
+}
+
+fn glob_match_bytes(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_match_bytes(&pattern[1..], name)
+                || (!name.is_empty() && glob_match_bytes(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob_match_bytes(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_match_bytes(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
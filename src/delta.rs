@@ -0,0 +1,764 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::{Display, Error},
+    hash::{Hash, Hasher},
+};
+
+use crate::{error::WadError, jaguar::COMPRESSED_FLAG, lump::LumpInfo};
+
+/// Patch file magic
+pub const PATCH_MAGIC: &[u8; 4] = b"WDPT";
+/// Patch format version
+pub const PATCH_VERSION: u8 = 1;
+/// Smallest match used by the byte level deltas
+const DELTA_BLOCK: usize = 8;
+
+/// Byte level delta operation, relative to the source lump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Copy `len` bytes from the source lump at `offset`
+    Copy { offset: u32, len: u32 },
+    /// Insert bytes
+    Insert(Vec<u8>),
+}
+
+/// New content of a lump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Payload {
+    /// Raw content
+    Data(Vec<u8>),
+    /// Delta against the source lump
+    Delta(Vec<DeltaOp>),
+}
+
+/// Operation building a target directory entry
+///
+/// `source` is an entry index within the source directory,
+/// `pos` is the entry offset within the target file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DirOp {
+    /// Same name and content as a source entry
+    Copy { pos: i32, source: u32 },
+    /// Content of a source entry with another name
+    Rename {
+        pos: i32,
+        source: u32,
+        name: [u8; 8],
+    },
+    /// Same name as a source entry with another content
+    Replace {
+        pos: i32,
+        source: u32,
+        payload: Payload,
+    },
+    /// New entry
+    Add {
+        pos: i32,
+        name: [u8; 8],
+        payload: Payload,
+    },
+}
+
+/// Binary patch turning a WAD file into another one
+///
+/// The directory is rebuilt entry by entry, only the new
+/// content is stored and the result is verified with CRC32 checksums
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WadPatch {
+    /// Source file checksum
+    pub source_crc: u32,
+    /// Target file checksum
+    pub target_crc: u32,
+    /// Target file size
+    pub target_size: u32,
+    /// Target header magic
+    pub kind: [u8; 4],
+    /// Target directory offset
+    pub dir_pos: i32,
+    /// Target directory entries, in order
+    pub ops: Vec<DirOp>,
+    /// Target bytes outside the header, the directory and the lumps
+    pub extras: Vec<(u32, Vec<u8>)>,
+    /// Target directory sizes differing from the stored content length
+    /// (Jaguar compressed lumps), by entry index
+    pub sizes: Vec<(u32, i32)>,
+}
+
+/// Compute the CRC32 (IEEE) of `bytes`
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+
+    for (i, value) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+
+        *value = c;
+    }
+
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Raw directory entry, the name keeps its flags
+struct Entry {
+    pos: i32,
+    size: i32,
+    name: [u8; 8],
+    /// Stored content length, the compressed lumps end
+    /// where the next data starts
+    len: usize,
+}
+
+impl Entry {
+    fn to_bytes(&self) -> Vec<u8> {
+        [
+            &self.pos.to_le_bytes()[..],
+            &self.size.to_le_bytes(),
+            &self.name,
+        ]
+        .concat()
+    }
+}
+
+/// Parse the header and the directory of a WAD file
+fn directory(bytes: &[u8]) -> Result<(i32, Vec<Entry>), WadError> {
+    if bytes.len() < 12 {
+        return Err(WadError::Load("The file size is too small."));
+    }
+
+    let num_lumps = i32::from_le_bytes(bytes[4..8].try_into().unwrap()).max(0) as usize;
+    let dir_pos = i32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    let start = dir_pos.max(0) as usize;
+    let dir = num_lumps
+        .checked_mul(16)
+        .and_then(|len| bytes.get(start..start.checked_add(len)?))
+        .ok_or(WadError::Parse(String::from("Invalid directory")))?;
+
+    let mut ret: Vec<Entry> = dir
+        .chunks(16)
+        .map(|chunk| {
+            let pos = i32::from_le_bytes(chunk[0..4].try_into().unwrap());
+            let size = i32::from_le_bytes(chunk[4..8].try_into().unwrap());
+
+            Entry {
+                pos,
+                size,
+                name: chunk[8..16].try_into().unwrap(),
+                len: size.max(0) as usize,
+            }
+        })
+        .collect();
+    let starts: Vec<usize> = ret
+        .iter()
+        .map(|entry| entry.pos.max(0) as usize)
+        .chain([start, bytes.len()])
+        .collect();
+
+    for entry in ret.iter_mut() {
+        if entry.name[0] & COMPRESSED_FLAG != 0 {
+            let pos = entry.pos.max(0) as usize;
+
+            entry.len = starts
+                .iter()
+                .filter(|start| **start > pos)
+                .min()
+                .map_or(0, |end| end - pos);
+        }
+    }
+
+    Ok((dir_pos, ret))
+}
+
+/// Get the stored content of a directory entry
+fn content<'a>(bytes: &'a [u8], entry: &Entry) -> Result<&'a [u8], WadError> {
+    let pos = entry.pos.max(0) as usize;
+
+    bytes.get(pos..pos + entry.len).ok_or(WadError::InvalidLump)
+}
+
+/// Hash a lump content
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Compute a byte level delta turning `src` into `dst`
+pub fn delta(src: &[u8], dst: &[u8]) -> Vec<DeltaOp> {
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+
+    for i in 0..src.len().saturating_sub(DELTA_BLOCK - 1) {
+        index.entry(&src[i..i + DELTA_BLOCK]).or_insert(i);
+    }
+
+    let mut ret = Vec::new();
+    let mut insert = Vec::new();
+    let mut i = 0;
+
+    while i < dst.len() {
+        let found = dst
+            .get(i..i + DELTA_BLOCK)
+            .and_then(|block| index.get(block));
+
+        let offset = match found {
+            Some(value) => *value,
+            None => {
+                insert.push(dst[i]);
+                i += 1;
+                continue;
+            }
+        };
+
+        let len = src[offset..]
+            .iter()
+            .zip(dst[i..].iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        if !insert.is_empty() {
+            ret.push(DeltaOp::Insert(std::mem::take(&mut insert)));
+        }
+
+        ret.push(DeltaOp::Copy {
+            offset: offset as u32,
+            len: len as u32,
+        });
+        i += len;
+    }
+
+    if !insert.is_empty() {
+        ret.push(DeltaOp::Insert(insert));
+    }
+
+    ret
+}
+
+/// Apply a byte level delta on `src`
+pub fn apply_delta(src: &[u8], ops: &[DeltaOp]) -> Result<Vec<u8>, WadError> {
+    let mut ret = Vec::new();
+
+    for op in ops {
+        match op {
+            DeltaOp::Copy { offset, len } => {
+                let start = *offset as usize;
+                let bytes = src
+                    .get(start..start + *len as usize)
+                    .ok_or(WadError::InvalidOperation)?;
+
+                ret.extend_from_slice(bytes);
+            }
+            DeltaOp::Insert(bytes) => ret.extend_from_slice(bytes),
+        }
+    }
+
+    Ok(ret)
+}
+
+impl Payload {
+    /// Build the smallest payload turning `src` into `dst`
+    fn new(src: Option<&[u8]>, dst: &[u8], use_delta: bool) -> Self {
+        let data = Self::Data(dst.to_vec());
+
+        match src {
+            Some(src) if use_delta => {
+                let ops = Self::Delta(delta(src, dst));
+
+                if ops.encoded_len() < data.encoded_len() {
+                    ops
+                } else {
+                    data
+                }
+            }
+            _ => data,
+        }
+    }
+
+    /// Size of the payload once encoded
+    fn encoded_len(&self) -> usize {
+        match self {
+            Self::Data(bytes) => 5 + bytes.len(),
+            Self::Delta(ops) => ops.iter().fold(5, |acc, op| match op {
+                DeltaOp::Copy { .. } => acc + 9,
+                DeltaOp::Insert(bytes) => acc + 5 + bytes.len(),
+            }),
+        }
+    }
+
+    /// Get the lump content
+    fn resolve(&self, src: &[u8]) -> Result<Vec<u8>, WadError> {
+        match self {
+            Self::Data(bytes) => Ok(bytes.clone()),
+            Self::Delta(ops) => apply_delta(src, ops),
+        }
+    }
+}
+
+impl DirOp {
+    /// Get the target entry offset
+    pub fn pos(&self) -> i32 {
+        match self {
+            Self::Copy { pos, .. }
+            | Self::Rename { pos, .. }
+            | Self::Replace { pos, .. }
+            | Self::Add { pos, .. } => *pos,
+        }
+    }
+}
+
+impl WadPatch {
+    /// Build the patch turning the WAD file `source` into `target`
+    ///
+    /// If `use_delta` is true, the modified lumps are stored
+    /// as byte level deltas when it is smaller
+    pub fn new(source: &[u8], target: &[u8], use_delta: bool) -> Result<Self, WadError> {
+        let (_, source_dir) = directory(source)?;
+        let (dir_pos, target_dir) = directory(target)?;
+        let target_size = u32::try_from(target.len()).map_err(|_| WadError::InvalidOperation)?;
+
+        // Source entries by raw name and by content hash
+        let mut names: HashMap<[u8; 8], Vec<usize>> = HashMap::new();
+        let mut hashes: HashMap<u64, Vec<usize>> = HashMap::new();
+
+        for (i, entry) in source_dir.iter().enumerate() {
+            names.entry(entry.name).or_default().push(i);
+            hashes
+                .entry(hash(content(source, entry)?))
+                .or_default()
+                .push(i);
+        }
+
+        let mut ops = Vec::with_capacity(target_dir.len());
+        let mut covered = vec![false; target.len()];
+
+        covered[..12].iter_mut().for_each(|c| *c = true);
+
+        if let Some(dir) = covered.get_mut(dir_pos.max(0) as usize..) {
+            dir.iter_mut()
+                .take(target_dir.len() * 16)
+                .for_each(|c| *c = true);
+        }
+
+        let mut sizes = Vec::new();
+
+        for (i, entry) in target_dir.iter().enumerate() {
+            let bytes = content(target, entry)?;
+
+            if entry.size.max(0) as usize != bytes.len() {
+                sizes.push((i as u32, entry.size));
+            }
+
+            let pos = entry.pos;
+            let same = |i: &usize| content(source, &source_dir[*i]).ok() == Some(bytes);
+            let candidates = hashes.get(&hash(bytes));
+
+            // Same content, the same name is preferred
+            let by_name = names
+                .get(&entry.name)
+                .and_then(|list| list.iter().find(|i| same(i)));
+            let by_content = candidates.and_then(|list| list.iter().find(|i| same(i)));
+
+            let op = match (by_name, by_content, names.get(&entry.name)) {
+                (Some(i), _, _) => DirOp::Copy {
+                    pos,
+                    source: *i as u32,
+                },
+                (None, Some(i), _) if !bytes.is_empty() => DirOp::Rename {
+                    pos,
+                    source: *i as u32,
+                    name: entry.name,
+                },
+                (None, _, Some(list)) => DirOp::Replace {
+                    pos,
+                    source: list[0] as u32,
+                    payload: Payload::new(
+                        Some(content(source, &source_dir[list[0]])?),
+                        bytes,
+                        use_delta,
+                    ),
+                },
+                _ => DirOp::Add {
+                    pos,
+                    name: entry.name,
+                    payload: Payload::new(None, bytes, use_delta),
+                },
+            };
+
+            let start = pos.max(0) as usize;
+
+            covered[start..start + bytes.len()]
+                .iter_mut()
+                .for_each(|c| *c = true);
+            ops.push(op);
+        }
+
+        // Non zero bytes outside of everything (padding, leftovers)
+        let mut extras: Vec<(u32, Vec<u8>)> = Vec::new();
+
+        for (i, byte) in target.iter().enumerate() {
+            if covered[i] || *byte == 0 {
+                continue;
+            }
+
+            match extras.last_mut() {
+                Some((offset, bytes)) if *offset as usize + bytes.len() == i => bytes.push(*byte),
+                _ => extras.push((i as u32, vec![*byte])),
+            }
+        }
+
+        Ok(Self {
+            source_crc: crc32(source),
+            target_crc: crc32(target),
+            target_size,
+            kind: target[0..4].try_into().unwrap(),
+            dir_pos,
+            ops,
+            extras,
+            sizes,
+        })
+    }
+
+    /// Rebuild the target WAD file from `source`
+    ///
+    /// Both files are verified with their checksum
+    pub fn apply(&self, source: &[u8]) -> Result<Vec<u8>, WadError> {
+        if crc32(source) != self.source_crc {
+            return Err(WadError::Checksum("source"));
+        }
+
+        let (_, source_dir) = directory(source)?;
+        let src = |i: &u32| match source_dir.get(*i as usize) {
+            Some(entry) => content(source, entry).map(|bytes| (entry, bytes)),
+            None => Err(WadError::InvalidOperation),
+        };
+
+        let mut ret = vec![0x00; self.target_size as usize];
+        let mut dir = Vec::with_capacity(self.ops.len() * 16);
+        let sizes: HashMap<u32, i32> = self.sizes.iter().copied().collect();
+
+        for (i, op) in self.ops.iter().enumerate() {
+            let (name, bytes) = match op {
+                DirOp::Copy { source, .. } => {
+                    let (entry, bytes) = src(source)?;
+
+                    (entry.name, bytes.to_vec())
+                }
+                DirOp::Rename { source, name, .. } => (*name, src(source)?.1.to_vec()),
+                DirOp::Replace {
+                    source, payload, ..
+                } => {
+                    let (entry, bytes) = src(source)?;
+
+                    (entry.name, payload.resolve(bytes)?)
+                }
+                DirOp::Add { name, payload, .. } => (*name, payload.resolve(&[])?),
+            };
+
+            let pos = op.pos();
+            let start = pos.max(0) as usize;
+
+            ret.get_mut(start..start + bytes.len())
+                .ok_or(WadError::InvalidOperation)?
+                .copy_from_slice(&bytes);
+
+            let entry = Entry {
+                pos,
+                size: sizes
+                    .get(&(i as u32))
+                    .copied()
+                    .unwrap_or(bytes.len() as i32),
+                name,
+                len: bytes.len(),
+            };
+
+            dir.extend(entry.to_bytes());
+        }
+
+        let header = [
+            &self.kind[..],
+            &(self.ops.len() as i32).to_le_bytes(),
+            &self.dir_pos.to_le_bytes(),
+        ]
+        .concat();
+        let dir_start = self.dir_pos.max(0) as usize;
+
+        for (start, bytes) in [(0, header), (dir_start, dir)] {
+            ret.get_mut(start..start + bytes.len())
+                .ok_or(WadError::InvalidOperation)?
+                .copy_from_slice(&bytes);
+        }
+
+        for (offset, bytes) in self.extras.iter() {
+            let start = *offset as usize;
+
+            ret.get_mut(start..start + bytes.len())
+                .ok_or(WadError::InvalidOperation)?
+                .copy_from_slice(bytes);
+        }
+
+        if crc32(&ret) != self.target_crc {
+            return Err(WadError::Checksum("target"));
+        }
+
+        Ok(ret)
+    }
+
+    /// Encode the patch
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::new();
+
+        ret.extend_from_slice(PATCH_MAGIC);
+        ret.push(PATCH_VERSION);
+        ret.extend_from_slice(&self.source_crc.to_le_bytes());
+        ret.extend_from_slice(&self.target_crc.to_le_bytes());
+        ret.extend_from_slice(&self.target_size.to_le_bytes());
+        ret.extend_from_slice(&self.kind);
+        ret.extend_from_slice(&self.dir_pos.to_le_bytes());
+        ret.extend_from_slice(&(self.ops.len() as u32).to_le_bytes());
+
+        for op in self.ops.iter() {
+            let (tag, source, name, payload) = match op {
+                DirOp::Copy { source, .. } => (0, Some(source), None, None),
+                DirOp::Rename { source, name, .. } => (1, Some(source), Some(name), None),
+                DirOp::Replace {
+                    source, payload, ..
+                } => (2, Some(source), None, Some(payload)),
+                DirOp::Add { name, payload, .. } => (3, None, Some(name), Some(payload)),
+            };
+
+            ret.push(tag);
+            ret.extend_from_slice(&op.pos().to_le_bytes());
+
+            if let Some(source) = source {
+                ret.extend_from_slice(&source.to_le_bytes());
+            }
+
+            if let Some(name) = name {
+                ret.extend_from_slice(name);
+            }
+
+            match payload {
+                Some(Payload::Data(bytes)) => {
+                    ret.push(0);
+                    put_bytes(&mut ret, bytes);
+                }
+                Some(Payload::Delta(ops)) => {
+                    ret.push(1);
+                    ret.extend_from_slice(&(ops.len() as u32).to_le_bytes());
+
+                    for op in ops {
+                        match op {
+                            DeltaOp::Copy { offset, len } => {
+                                ret.push(0);
+                                ret.extend_from_slice(&offset.to_le_bytes());
+                                ret.extend_from_slice(&len.to_le_bytes());
+                            }
+                            DeltaOp::Insert(bytes) => {
+                                ret.push(1);
+                                put_bytes(&mut ret, bytes);
+                            }
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+
+        ret.extend_from_slice(&(self.extras.len() as u32).to_le_bytes());
+
+        for (offset, bytes) in self.extras.iter() {
+            ret.extend_from_slice(&offset.to_le_bytes());
+            put_bytes(&mut ret, bytes);
+        }
+
+        ret.extend_from_slice(&(self.sizes.len() as u32).to_le_bytes());
+
+        for (index, size) in self.sizes.iter() {
+            ret.extend_from_slice(&index.to_le_bytes());
+            ret.extend_from_slice(&size.to_le_bytes());
+        }
+
+        ret
+    }
+
+    /// Decode a patch
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WadError> {
+        let mut cursor = PatchCursor { bytes, pos: 0 };
+
+        if cursor.take(4)? != PATCH_MAGIC || cursor.u8()? != PATCH_VERSION {
+            return Err(WadError::Type("The file is not a supported WAD patch."));
+        }
+
+        let mut ret = Self {
+            source_crc: cursor.u32()?,
+            target_crc: cursor.u32()?,
+            target_size: cursor.u32()?,
+            kind: cursor.take(4)?.try_into().unwrap(),
+            dir_pos: cursor.u32()? as i32,
+            ..Default::default()
+        };
+
+        for _ in 0..cursor.u32()? {
+            let tag = cursor.u8()?;
+            let pos = cursor.u32()? as i32;
+
+            let op = match tag {
+                0 => DirOp::Copy {
+                    pos,
+                    source: cursor.u32()?,
+                },
+                1 => DirOp::Rename {
+                    pos,
+                    source: cursor.u32()?,
+                    name: cursor.name()?,
+                },
+                2 => DirOp::Replace {
+                    pos,
+                    source: cursor.u32()?,
+                    payload: cursor.payload()?,
+                },
+                3 => DirOp::Add {
+                    pos,
+                    name: cursor.name()?,
+                    payload: cursor.payload()?,
+                },
+                _ => return Err(WadError::Parse(String::from("Invalid patch operation"))),
+            };
+
+            ret.ops.push(op);
+        }
+
+        for _ in 0..cursor.u32()? {
+            let offset = cursor.u32()?;
+            let len = cursor.u32()? as usize;
+
+            ret.extras.push((offset, cursor.take(len)?.to_vec()));
+        }
+
+        for _ in 0..cursor.u32()? {
+            let index = cursor.u32()?;
+
+            ret.sizes.push((index, cursor.u32()? as i32));
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Push a length prefixed buffer
+fn put_bytes(dest: &mut Vec<u8>, bytes: &[u8]) {
+    dest.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    dest.extend_from_slice(bytes);
+}
+
+/// Used to decode a patch
+struct PatchCursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchCursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WadError> {
+        let ret = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or(WadError::Parse(String::from("Truncated patch")))?;
+
+        self.pos += len;
+
+        Ok(ret)
+    }
+
+    fn u8(&mut self) -> Result<u8, WadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, WadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn name(&mut self) -> Result<[u8; 8], WadError> {
+        Ok(self.take(8)?.try_into().unwrap())
+    }
+
+    fn payload(&mut self) -> Result<Payload, WadError> {
+        match self.u8()? {
+            0 => {
+                let len = self.u32()? as usize;
+
+                Ok(Payload::Data(self.take(len)?.to_vec()))
+            }
+            1 => {
+                let mut ops = Vec::new();
+
+                for _ in 0..self.u32()? {
+                    let op = match self.u8()? {
+                        0 => DeltaOp::Copy {
+                            offset: self.u32()?,
+                            len: self.u32()?,
+                        },
+                        _ => {
+                            let len = self.u32()? as usize;
+
+                            DeltaOp::Insert(self.take(len)?.to_vec())
+                        }
+                    };
+
+                    ops.push(op);
+                }
+
+                Ok(Payload::Delta(ops))
+            }
+            _ => Err(WadError::Parse(String::from("Invalid patch payload"))),
+        }
+    }
+}
+
+impl Display for DirOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), Error> {
+        let ascii = |name: &[u8; 8]| LumpInfo::from(&[&[0; 8][..], name].concat()[..]).name_ascii();
+
+        match self {
+            Self::Copy { source, .. } => write!(f, "= #{}", source),
+            Self::Rename { source, name, .. } => write!(f, "> #{} {}", source, ascii(name)),
+            Self::Replace {
+                source, payload, ..
+            } => match payload {
+                Payload::Data(bytes) => write!(f, "~ #{} {} bytes", source, bytes.len()),
+                Payload::Delta(ops) => write!(f, "~ #{} delta of {} ops", source, ops.len()),
+            },
+            Self::Add { name, payload, .. } => {
+                write!(
+                    f,
+                    "+ {} {} bytes",
+                    ascii(name),
+                    payload.encoded_len().saturating_sub(5)
+                )
+            }
+        }
+    }
+}
+
+impl Display for WadPatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), Error> {
+        writeln!(
+            f,
+            "Source CRC32: {:08x}, Target CRC32: {:08x}, Target size: {}",
+            self.source_crc, self.target_crc, self.target_size
+        )?;
+
+        for op in self.ops.iter() {
+            writeln!(f, "{}", op)?;
+        }
+
+        Ok(())
+    }
+}
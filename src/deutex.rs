@@ -0,0 +1,541 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Cursor,
+    path::Path,
+};
+
+use crate::{
+    classifier::MIDI_MAGIC,
+    error::WadError,
+    lump::{LumpAdd, LumpData, LumpInfo, LumpKind},
+    lumps::{
+        palette::{Palettes, PALETTE_SIZE},
+        patch::{DoomImage, DoomImageInfo},
+        sound::dmx_from_wav,
+        texture::{
+            build_pnames, build_textures, parse_pnames, parse_textures, TextureDef, TexturePatch,
+        },
+    },
+    models::lump::Lump,
+    namespace::{Marker, Namespace},
+    tree::{convert, pixels_from_png, read_err, write_err, TreeFormat},
+    wad::{Wad, WadKind},
+    writer::WadWriter,
+};
+
+/// DeuTex main file, within the tree root
+pub const WADINFO: &str = "wadinfo.txt";
+/// Transparent color of the DeuTex pictures without alpha
+pub const TRANSPARENT_KEY: (u8, u8, u8) = (0, 255, 255);
+/// Picture files extensions, in the lookup order
+const PICTURE_EXT: &[&str] = &["png", "bmp", "gif", "ppm", "lmp"];
+
+/// A `wadinfo.txt` section, in the DeuTex order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Section {
+    Levels,
+    Lumps,
+    Sounds,
+    Musics,
+    Textures,
+    Graphics,
+    Sprites,
+    Patches,
+    Flats,
+}
+
+impl Section {
+    /// Every section, in the DeuTex order
+    pub const ALL: [Self; 9] = [
+        Self::Levels,
+        Self::Lumps,
+        Self::Sounds,
+        Self::Musics,
+        Self::Textures,
+        Self::Graphics,
+        Self::Sprites,
+        Self::Patches,
+        Self::Flats,
+    ];
+
+    /// Section name, also used as folder name
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Levels => "levels",
+            Self::Lumps => "lumps",
+            Self::Sounds => "sounds",
+            Self::Musics => "musics",
+            Self::Textures => "textures",
+            Self::Graphics => "graphics",
+            Self::Sprites => "sprites",
+            Self::Patches => "patches",
+            Self::Flats => "flats",
+        }
+    }
+
+    /// Get a section from its name
+    pub fn from_name(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|section| section.name() == value.to_ascii_lowercase())
+    }
+
+    /// Namespace written around the section lumps
+    pub fn namespace(&self) -> Option<Namespace> {
+        match self {
+            Self::Sprites => Some(Namespace::Sprites),
+            Self::Patches => Some(Namespace::Patches),
+            Self::Flats => Some(Namespace::Flats),
+            _ => None,
+        }
+    }
+
+    /// Files extensions, in the lookup order
+    fn extensions(&self) -> &[&str] {
+        match self {
+            Self::Levels => &["wad"],
+            Self::Sounds => &["wav", "lmp"],
+            Self::Musics => &["mus", "mid", "lmp"],
+            Self::Textures => &["txt"],
+            Self::Graphics | Self::Sprites | Self::Patches | Self::Flats => PICTURE_EXT,
+            Self::Lumps => &["lmp"],
+        }
+    }
+}
+
+/// A `wadinfo.txt` entry
+struct Entry {
+    /// Lump name
+    name: String,
+    /// File name, without extension
+    file: String,
+    /// Picture offsets
+    offsets: Option<(i16, i16)>,
+}
+
+/// DeuTex file name of a lump, in lowercase with `\` replaced by `^`
+pub(crate) fn file_name(name: &str) -> String {
+    name.to_ascii_lowercase().replace('\\', "^")
+}
+
+/// Lump name of a DeuTex file name
+pub(crate) fn lump_name(file: &str) -> String {
+    file.to_ascii_uppercase().replace('^', "\\")
+}
+
+/// Parse a DeuTex textures file
+fn parse_texture_text(text: &str) -> Result<Vec<(String, TextureDef, Vec<String>)>, WadError> {
+    let mut ret: Vec<(String, TextureDef, Vec<String>)> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || WadError::Parse(format!("Invalid texture line {}", n + 1));
+        let number = |value: &str| value.parse::<i16>().map_err(|_| invalid());
+
+        match fields[..] {
+            [] => {}
+            [first, ..] if first.starts_with(';') || first.starts_with('#') => {}
+            ["*", patch, x, y] => {
+                let (_, texture, patches) = ret.last_mut().ok_or_else(invalid)?;
+
+                texture.patches.push(TexturePatch {
+                    origin_x: number(x)?,
+                    origin_y: number(y)?,
+                    step_dir: 1,
+                    ..Default::default()
+                });
+                patches.push(lump_name(patch));
+            }
+            [name, width, height] => {
+                let name = lump_name(name);
+                let texture = TextureDef {
+                    name: name.clone(),
+                    width: number(width)?,
+                    height: number(height)?,
+                    ..Default::default()
+                };
+
+                ret.push((name, texture, Vec::new()));
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Build a DeuTex textures file
+fn texture_text(name: &str, textures: &[TextureDef], pnames: &[String]) -> String {
+    let mut ret = format!("; {}\n", name);
+
+    for texture in textures {
+        ret.push_str(&format!(
+            "{:<8} {:>4} {:>4}\n",
+            texture.name, texture.width, texture.height
+        ));
+
+        for patch in texture.patches.iter() {
+            let patch_name = pnames
+                .get(patch.patch.max(0) as usize)
+                .cloned()
+                .unwrap_or_default();
+
+            ret.push_str(&format!(
+                "*   {:<8} {:>4} {:>4}\n",
+                patch_name, patch.origin_x, patch.origin_y
+            ));
+        }
+    }
+
+    ret
+}
+
+/// Extract `wad` as a DeuTex tree in `dir`
+///
+/// The maps are written as PWADs, the pictures and the sounds are converted
+/// when it is lossless, otherwise they are written raw (`.lmp`).
+/// The markers of the other namespaces are kept as empty lumps.
+pub fn extract(wad: &Wad, dir: &Path) -> Result<(), WadError> {
+    let wad_dir = wad.dir();
+    let namespaces = wad_dir.namespaces();
+    let maps = wad_dir.maps();
+    let pal = wad.palettes();
+    let mut sections: HashMap<Section, Vec<String>> = HashMap::new();
+    let mut used = HashSet::new();
+    let mut levels: Vec<(String, Vec<LumpData>)> = Vec::new();
+    let mut tables: Vec<LumpData> = Vec::new();
+    let mut pnames = None;
+
+    for (i, lump) in wad_dir.lumps.iter().enumerate() {
+        let data = lump.data();
+        let name = data.metadata.name_ascii();
+
+        if !data.metadata.state.is_alive() {
+            continue;
+        }
+
+        // Markers written by the build
+        if let Some(marker) = Marker::parse(&name) {
+            if Section::ALL
+                .iter()
+                .any(|s| s.namespace() == Some(marker.namespace.clone()))
+            {
+                continue;
+            }
+        }
+
+        let section = match (&namespaces[i], &maps[i]) {
+            (Some(Namespace::Sprites), _) => Section::Sprites,
+            (Some(Namespace::Patches), _) => Section::Patches,
+            (Some(Namespace::Flats), _) => Section::Flats,
+            (Some(_), _) => Section::Lumps,
+            (None, Some(map)) => {
+                match levels.last_mut() {
+                    Some((current, lumps)) if *current == *map && name != *map => lumps.push(data),
+                    _ => levels.push((map.clone(), vec![data])),
+                }
+
+                continue;
+            }
+            (None, None) => match name.as_str() {
+                "TEXTURE1" | "TEXTURE2" => {
+                    tables.push(data);
+                    continue;
+                }
+                "PNAMES" => {
+                    pnames = Some(data);
+                    continue;
+                }
+                _ => match data.kind {
+                    LumpKind::Music => Section::Musics,
+                    LumpKind::Sound => Section::Sounds,
+                    LumpKind::Patch => Section::Graphics,
+                    _ => Section::Lumps,
+                },
+            },
+        };
+
+        let converted = match section {
+            Section::Sounds | Section::Graphics | Section::Sprites => convert(&data, pal),
+            Section::Patches | Section::Flats => convert(&data, pal),
+            _ => None,
+        };
+        let ext = match (&converted, section) {
+            (Some(file), _) if file.format == TreeFormat::Wav => "wav",
+            (Some(_), _) => "png",
+            (None, Section::Musics) if data.buffer.starts_with(&MIDI_MAGIC) => "mid",
+            (None, Section::Musics) => "mus",
+            _ => "lmp",
+        };
+
+        // Duplicated names get another file name
+        let mut file = file_name(&name);
+        let mut n = 1;
+
+        while !used.insert((section, file.clone())) {
+            file = format!("{}~{}", file_name(&name), n);
+            n += 1;
+        }
+
+        let mut line = match file == file_name(&name) {
+            true => name.clone(),
+            false => format!("{} = {}", name, file),
+        };
+
+        if let Some((left, top)) = converted.as_ref().and_then(|file| file.offsets) {
+            line.push_str(&format!(" {} {}", left as i16, top as i16));
+        }
+
+        let content = match converted {
+            Some(value) => value.content,
+            None => data.buffer.to_vec(),
+        };
+        let folder = dir.join(section.name());
+
+        fs::create_dir_all(&folder).map_err(write_err)?;
+        fs::write(folder.join(format!("{}.{}", file, ext)), content).map_err(write_err)?;
+        sections.entry(section).or_default().push(line);
+    }
+
+    // Levels as PWADs
+    for (map, lumps) in levels.iter() {
+        let mut writer = WadWriter::new(Cursor::new(Vec::new()), WadKind::Pwad)?;
+
+        for data in lumps {
+            writer.write_lump(data.metadata.name, &data.buffer)?;
+        }
+
+        let folder = dir.join(Section::Levels.name());
+
+        fs::create_dir_all(&folder).map_err(write_err)?;
+        fs::write(
+            folder.join(format!("{}.wad", file_name(map))),
+            writer.finish()?.into_inner(),
+        )
+        .map_err(write_err)?;
+        sections
+            .entry(Section::Levels)
+            .or_default()
+            .push(map.clone());
+    }
+
+    // Textures with their patches names
+    let pnames = match &pnames {
+        Some(data) => parse_pnames(&data.buffer)?,
+        None => Vec::new(),
+    };
+
+    for data in tables.iter() {
+        let name = data.metadata.name_ascii();
+        let text = texture_text(&name, &parse_textures(&data.buffer)?, &pnames);
+        let folder = dir.join(Section::Textures.name());
+
+        fs::create_dir_all(&folder).map_err(write_err)?;
+        fs::write(folder.join(format!("{}.txt", file_name(&name))), text).map_err(write_err)?;
+        sections.entry(Section::Textures).or_default().push(name);
+    }
+
+    let mut wadinfo = String::from("# DeuTex wadinfo.txt, written by tinywad\n");
+
+    for section in Section::ALL {
+        if let Some(lines) = sections.get(&section) {
+            wadinfo.push_str(&format!("\n[{}]\n", section.name()));
+
+            for line in lines {
+                wadinfo.push_str(line);
+                wadinfo.push('\n');
+            }
+        }
+    }
+
+    fs::write(dir.join(WADINFO), wadinfo).map_err(write_err)
+}
+
+/// Parse a `wadinfo.txt`
+fn parse_wadinfo(text: &str) -> Result<Vec<(Section, Vec<Entry>)>, WadError> {
+    let mut ret: Vec<(Section, Vec<Entry>)> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let invalid = || WadError::Parse(format!("Invalid wadinfo line {}", n + 1));
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            ret.push((
+                Section::from_name(name.trim()).ok_or_else(invalid)?,
+                Vec::new(),
+            ));
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (name, file, rest) = match fields[..] {
+            [name, "=", file, ref rest @ ..] => (name, file.to_string(), rest),
+            [name, ref rest @ ..] => (name, file_name(name), rest),
+            _ => return Err(invalid()),
+        };
+        let offset = |value: &str| value.parse::<i16>().map_err(|_| invalid());
+        let offsets = match rest {
+            [] => None,
+            // Extra flags are ignored
+            [x, y, ..] => Some((offset(x)?, offset(y)?)),
+            _ => return Err(invalid()),
+        };
+        let (_, entries) = ret.last_mut().ok_or_else(invalid)?;
+
+        entries.push(Entry {
+            name: lump_name(name),
+            file,
+            offsets,
+        });
+    }
+
+    Ok(ret)
+}
+
+/// Find the file of an entry, with its extension
+fn find_file(dir: &Path, section: Section, file: &str) -> Result<(Vec<u8>, String), WadError> {
+    for ext in section.extensions() {
+        let path = dir.join(section.name()).join(format!("{}.{}", file, ext));
+
+        if path.is_file() {
+            return Ok((fs::read(path).map_err(read_err)?, ext.to_string()));
+        }
+    }
+
+    Err(WadError::Read(format!(
+        "Missing file {}/{}",
+        section.name(),
+        file
+    )))
+}
+
+/// Build a WAD file (PWAD) from the DeuTex tree `dir`
+///
+/// The pictures are converted with the tree PLAYPAL if any, otherwise
+/// with `palettes`. Their transparent color is either the alpha channel
+/// or `TRANSPARENT_KEY`, the missing offsets are 0.
+pub fn build(dir: &Path, palettes: Option<&Palettes>) -> Result<Vec<u8>, WadError> {
+    let text = fs::read_to_string(dir.join(WADINFO)).map_err(read_err)?;
+    let sections = parse_wadinfo(&text)?;
+    let mut writer = WadWriter::new(Cursor::new(Vec::new()), WadKind::Pwad)?;
+    let mut pal = palettes.cloned();
+
+    // The tree PLAYPAL wins
+    for (section, entries) in sections.iter() {
+        for entry in entries.iter() {
+            if *section != Section::Lumps || entry.name != "PLAYPAL" {
+                continue;
+            }
+
+            let (buffer, _) = find_file(dir, *section, &entry.file)?;
+            let size = (buffer.len() / PALETTE_SIZE * PALETTE_SIZE) as i32;
+            let mut value = Palettes::new();
+
+            value.set_data(LumpData {
+                buffer: buffer.into(),
+                metadata: LumpInfo::new(0, size, LumpAdd::name_bytes("PLAYPAL")),
+                ..Default::default()
+            });
+            value.parse()?;
+            pal = Some(value);
+        }
+    }
+
+    for (section, entries) in sections.iter() {
+        if let Some(namespace) = section.namespace() {
+            writer.write_marker(LumpAdd::name_bytes(&namespace.start_marker()));
+        }
+
+        match section {
+            Section::Levels => {
+                for entry in entries.iter() {
+                    let (buffer, _) = find_file(dir, *section, &entry.file)?;
+                    let mut level = Wad::new();
+
+                    level.load(buffer)?;
+
+                    // The first lump is the map marker
+                    for (i, lump) in level.lumps().enumerate() {
+                        let data = lump.data();
+                        let name = match i {
+                            0 => LumpAdd::name_bytes(&entry.name),
+                            _ => data.metadata.name,
+                        };
+
+                        writer.write_lump(name, &data.buffer)?;
+                    }
+                }
+            }
+            Section::Textures => {
+                let mut pnames: Vec<String> = Vec::new();
+                let mut indexes: HashMap<String, i16> = HashMap::new();
+
+                for entry in entries.iter() {
+                    let (buffer, _) = find_file(dir, *section, &entry.file)?;
+                    let text = String::from_utf8_lossy(&buffer);
+                    let mut textures = Vec::new();
+
+                    for (_, mut texture, patches) in parse_texture_text(&text)? {
+                        for (patch, name) in texture.patches.iter_mut().zip(patches) {
+                            patch.patch = *indexes.entry(name.clone()).or_insert_with(|| {
+                                pnames.push(name);
+                                pnames.len() as i16 - 1
+                            });
+                        }
+
+                        textures.push(texture);
+                    }
+
+                    writer
+                        .write_lump(LumpAdd::name_bytes(&entry.name), &build_textures(&textures))?;
+                }
+
+                writer.write_lump(LumpAdd::name_bytes("PNAMES"), &build_pnames(&pnames))?;
+            }
+            _ => {
+                for entry in entries.iter() {
+                    let (file, ext) = find_file(dir, *section, &entry.file)?;
+                    let buffer = match (section, ext.as_str()) {
+                        (_, "lmp" | "mus" | "mid") => file,
+                        (Section::Sounds, _) => dmx_from_wav(&file)?,
+                        (_, _) => {
+                            let pal = pal
+                                .as_ref()
+                                .ok_or(WadError::Parse(String::from("Missing palette")))?;
+                            let is_flat = *section == Section::Flats;
+                            let ((width, height), pixels) =
+                                pixels_from_png(&file, pal, !is_flat, Some(TRANSPARENT_KEY))?;
+
+                            if is_flat {
+                                pixels.iter().map(|p| p.unwrap_or_default()).collect()
+                            } else {
+                                let (left, top) = entry.offsets.unwrap_or_default();
+                                let info = DoomImageInfo {
+                                    width,
+                                    height,
+                                    left: left as u16,
+                                    top: top as u16,
+                                };
+
+                                DoomImage::encode(info, &pixels)?
+                            }
+                        }
+                    };
+
+                    writer.write_lump(LumpAdd::name_bytes(&entry.name), &buffer)?;
+                }
+            }
+        }
+
+        if let Some(namespace) = section.namespace() {
+            writer.write_marker(LumpAdd::name_bytes(&namespace.end_marker()));
+        }
+    }
+
+    Ok(writer.finish()?.into_inner())
+}
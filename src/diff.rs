@@ -0,0 +1,669 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::{Display, Error, Write},
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use crate::{
+    address::LumpAddress,
+    dir::maps_of,
+    lump::{LumpData, LumpKind},
+    lumps::{
+        flat::FLAT_SIZE,
+        palette::{Palettes, PALETTE_SIZE, PIXEL_SIZE},
+        patch::{DoomImage, DoomImageInfo},
+    },
+    wad::Wad,
+};
+
+extern crate image;
+
+/// Color of the changed pixels within a pixels diff
+const DIFF_COLOR: (u8, u8, u8, u8) = (255, 0, 0, 255);
+/// Alpha of the unchanged pixels within a pixels diff
+const DIFF_ALPHA: u8 = 64;
+
+/// Records of a map lump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordsDiff {
+    /// Records amount in the old lump
+    pub old: usize,
+    /// Records amount in the new lump
+    pub new: usize,
+    /// Indexes of the records changed on both sides
+    pub changed: Vec<usize>,
+}
+
+/// Type aware detail of a modified lump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChangeDetail {
+    /// Size in bytes
+    Size { old: i32, new: i32 },
+    /// Picture width and height
+    Dimensions { old: (u16, u16), new: (u16, u16) },
+    /// Picture left and top offsets
+    Offsets { old: (u16, u16), new: (u16, u16) },
+    /// Pixels, with a RGBA rendering of the difference
+    Pixels {
+        changed: usize,
+        width: usize,
+        height: usize,
+        rgba: Vec<u8>,
+    },
+    /// Palette entries
+    PaletteEntries { palette: usize, entries: Vec<usize> },
+    /// Map things
+    Things(RecordsDiff),
+    /// Map linedefs
+    Linedefs(RecordsDiff),
+}
+
+/// A lump difference, the indexes follow the directory order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LumpChange {
+    /// Only in the new WAD
+    Added { name: String, index: usize },
+    /// Only in the old WAD
+    Removed { name: String, index: usize },
+    /// Same content with another name
+    Renamed {
+        old: String,
+        new: String,
+        index: usize,
+    },
+    /// Same lump at another place, relatively to the other lumps
+    Moved {
+        name: String,
+        old: usize,
+        new: usize,
+    },
+    /// Same name with another content
+    Modified {
+        name: String,
+        index: usize,
+        details: Vec<ChangeDetail>,
+    },
+}
+
+/// Structural difference between two WADs
+#[derive(Clone, Debug, Default)]
+pub struct WadDiff {
+    /// Differences, in the new WAD order then the removed lumps
+    pub changes: Vec<LumpChange>,
+}
+
+/// A lump compared by the diff
+struct Entry {
+    data: LumpData,
+    hash: u64,
+    index: usize,
+    /// Map qualified address (`MAP01/THINGS`, `NAME#n`)
+    address: String,
+}
+
+impl Entry {
+    fn name(&self) -> String {
+        self.address.clone()
+    }
+}
+
+/// Get the alive lumps of `wad` with their content hash and address
+///
+/// The map lumps are addressed within their map, so adding
+/// or removing a map does not shift the other ones
+fn entries(wad: &Wad) -> Vec<Entry> {
+    let names: Vec<String> = wad
+        .lumps()
+        .map(|lump| lump.metadata().name_ascii())
+        .collect();
+    let maps = maps_of(&names);
+    let mut counts: HashMap<(Option<String>, String), usize> = HashMap::new();
+
+    wad.lumps()
+        .zip(names.into_iter().zip(maps))
+        .enumerate()
+        .map(|(index, (lump, (name, map)))| {
+            let data = lump.data();
+            let mut hasher = DefaultHasher::new();
+            // The map marker belongs to the map but is addressed by its own name
+            let map = map.filter(|map| *map != name);
+            let n = counts.entry((map.clone(), name.clone())).or_default();
+            let address = LumpAddress { map, name, n: *n };
+
+            *n += 1;
+            data.buffer.hash(&mut hasher);
+
+            Entry {
+                hash: hasher.finish(),
+                data,
+                index,
+                address: address.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Positions (within `values`) of a longest increasing subsequence
+fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    // `tails[k]` is the position ending the best subsequence of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; values.len()];
+
+    for (i, value) in values.iter().enumerate() {
+        let k = tails.partition_point(|t| values[*t] < *value);
+
+        previous[i] = k.checked_sub(1).map(|p| tails[p]);
+
+        match tails.get_mut(k) {
+            Some(tail) => *tail = i,
+            None => tails.push(i),
+        }
+    }
+
+    let mut ret = Vec::with_capacity(tails.len());
+    let mut current = tails.last().copied();
+
+    while let Some(i) = current {
+        ret.push(i);
+        current = previous[i];
+    }
+
+    ret.reverse();
+    ret
+}
+
+/// Compare the map records of `size` bytes
+fn records(old: &[u8], new: &[u8], size: usize) -> RecordsDiff {
+    let old_records: Vec<&[u8]> = old.chunks_exact(size).collect();
+    let new_records: Vec<&[u8]> = new.chunks_exact(size).collect();
+    let changed = old_records
+        .iter()
+        .zip(new_records.iter())
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(i, _)| i)
+        .collect();
+
+    RecordsDiff {
+        old: old_records.len(),
+        new: new_records.len(),
+        changed,
+    }
+}
+
+/// Render the pixels difference, the changed pixels are red
+/// and the unchanged ones are dimmed
+fn pixels(old: &[Option<u8>], new: &[Option<u8>], palettes: &Palettes) -> (usize, Vec<u8>) {
+    let palette = palettes.palette();
+    let mut changed = 0;
+    let mut rgba = Vec::with_capacity(new.len() * 4);
+
+    for (a, b) in old.iter().zip(new.iter()) {
+        let (r, g, b, alpha) = match (a == b, b) {
+            (false, _) => {
+                changed += 1;
+                DIFF_COLOR
+            }
+            (true, None) => (0, 0, 0, 0),
+            (true, Some(index)) => match &palette {
+                Some(colors) => {
+                    let (r, g, b, _) = colors[*index as usize].into();
+                    (r, g, b, DIFF_ALPHA)
+                }
+                None => (*index, *index, *index, DIFF_ALPHA),
+            },
+        };
+
+        rgba.extend_from_slice(&[r, g, b, alpha]);
+    }
+
+    (changed, rgba)
+}
+
+/// Type aware details of a modified lump
+fn details(old: &Entry, new: &Entry, palettes: &Palettes, hexen: bool) -> Vec<ChangeDetail> {
+    let mut ret = Vec::new();
+    let (a, b) = (&*old.data.buffer, &*new.data.buffer);
+
+    if old.data.metadata.size != new.data.metadata.size {
+        ret.push(ChangeDetail::Size {
+            old: old.data.metadata.size,
+            new: new.data.metadata.size,
+        });
+    }
+
+    match (old.data.kind, new.data.kind) {
+        (LumpKind::Patch, LumpKind::Patch) => {
+            let (info_a, info_b) = (DoomImageInfo::from(a), DoomImageInfo::from(b));
+            let dim = ((info_a.width, info_a.height), (info_b.width, info_b.height));
+            let offsets = ((info_a.left, info_a.top), (info_b.left, info_b.top));
+
+            if dim.0 != dim.1 {
+                ret.push(ChangeDetail::Dimensions {
+                    old: dim.0,
+                    new: dim.1,
+                });
+            }
+
+            if offsets.0 != offsets.1 {
+                ret.push(ChangeDetail::Offsets {
+                    old: offsets.0,
+                    new: offsets.1,
+                });
+            }
+
+            let image_a = DoomImage::new(palettes.clone(), old.data.clone());
+            let image_b = DoomImage::new(palettes.clone(), new.data.clone());
+
+            if let (true, Ok(pixels_a), Ok(pixels_b)) =
+                (dim.0 == dim.1, image_a.pixels(), image_b.pixels())
+            {
+                let (changed, rgba) = pixels(pixels_a, pixels_b, palettes);
+
+                ret.push(ChangeDetail::Pixels {
+                    changed,
+                    width: info_b.width as usize,
+                    height: info_b.height as usize,
+                    rgba,
+                });
+            }
+        }
+        (LumpKind::Flat, LumpKind::Flat) if a.len() == FLAT_SIZE && b.len() == FLAT_SIZE => {
+            let pixels_a: Vec<Option<u8>> = a.iter().map(|p| Some(*p)).collect();
+            let pixels_b: Vec<Option<u8>> = b.iter().map(|p| Some(*p)).collect();
+            let (changed, rgba) = pixels(&pixels_a, &pixels_b, palettes);
+
+            ret.push(ChangeDetail::Pixels {
+                changed,
+                width: 64,
+                height: 64,
+                rgba,
+            });
+        }
+        (LumpKind::Palette, LumpKind::Palette) => {
+            for (palette, (pal_a, pal_b)) in a
+                .chunks_exact(PALETTE_SIZE)
+                .zip(b.chunks_exact(PALETTE_SIZE))
+                .enumerate()
+            {
+                let entries: Vec<usize> = pal_a
+                    .chunks_exact(PIXEL_SIZE)
+                    .zip(pal_b.chunks_exact(PIXEL_SIZE))
+                    .enumerate()
+                    .filter(|(_, (x, y))| x != y)
+                    .map(|(i, _)| i)
+                    .collect();
+
+                if !entries.is_empty() {
+                    ret.push(ChangeDetail::PaletteEntries { palette, entries });
+                }
+            }
+        }
+        _ => match new.data.metadata.name_ascii().as_str() {
+            "THINGS" => {
+                let size = if hexen { 20 } else { 10 };
+                ret.push(ChangeDetail::Things(records(a, b, size)));
+            }
+            "LINEDEFS" => {
+                let size = if hexen { 16 } else { 14 };
+                ret.push(ChangeDetail::Linedefs(records(a, b, size)));
+            }
+            _ => {}
+        },
+    }
+
+    ret
+}
+
+impl WadDiff {
+    /// Compare `old` with `new`
+    ///
+    /// The lumps are matched by their address then by their content hash
+    pub fn new(old: &Wad, new: &Wad) -> Self {
+        let old_entries = entries(old);
+        let new_entries = entries(new);
+        let hexen = new.lump("BEHAVIOR").is_some();
+        let palettes = match new.palettes().palette() {
+            Some(_) => new.palettes(),
+            None => old.palettes(),
+        };
+
+        let old_names: HashMap<String, usize> = old_entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.name(), i))
+            .collect();
+        let new_names: HashMap<String, usize> = new_entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.name(), i))
+            .collect();
+
+        // Lumps only in the old WAD, by content hash
+        let mut removed: HashMap<u64, Vec<usize>> = HashMap::new();
+
+        for (i, entry) in old_entries.iter().enumerate() {
+            if !new_names.contains_key(&entry.name()) {
+                removed.entry(entry.hash).or_default().push(i);
+            }
+        }
+
+        // The common lumps out of the longest ordered sequence moved
+        let common: Vec<(usize, usize)> = old_entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| new_names.get(&entry.name()).map(|j| (i, *j)))
+            .collect();
+        let positions: Vec<usize> = common.iter().map(|(_, j)| *j).collect();
+        let mut moved = vec![true; common.len()];
+
+        for k in longest_increasing(&positions) {
+            moved[k] = false;
+        }
+
+        let moved: HashMap<usize, bool> = positions.iter().copied().zip(moved).collect();
+        let mut renamed_old = vec![false; old_entries.len()];
+        let mut changes = Vec::new();
+
+        for (j, entry) in new_entries.iter().enumerate() {
+            let name = entry.name();
+
+            let i = match old_names.get(&name) {
+                Some(value) => *value,
+                None => {
+                    // Same content, not renamed yet
+                    let source = removed.get_mut(&entry.hash).and_then(|candidates| {
+                        let k = candidates.iter().position(|i| {
+                            old_entries[*i].data.buffer[..] == entry.data.buffer[..]
+                        })?;
+
+                        Some(candidates.remove(k))
+                    });
+
+                    changes.push(match source {
+                        Some(i) => {
+                            renamed_old[i] = true;
+
+                            LumpChange::Renamed {
+                                old: old_entries[i].name(),
+                                new: name,
+                                index: entry.index,
+                            }
+                        }
+                        None => LumpChange::Added {
+                            name,
+                            index: entry.index,
+                        },
+                    });
+
+                    continue;
+                }
+            };
+
+            let old_entry = &old_entries[i];
+
+            if moved.get(&j).copied().unwrap_or_default() {
+                changes.push(LumpChange::Moved {
+                    name: name.clone(),
+                    old: old_entry.index,
+                    new: entry.index,
+                });
+            }
+
+            if old_entry.data.buffer[..] != entry.data.buffer[..] {
+                changes.push(LumpChange::Modified {
+                    name,
+                    index: entry.index,
+                    details: details(old_entry, entry, palettes, hexen),
+                });
+            }
+        }
+
+        for (i, entry) in old_entries.iter().enumerate() {
+            if !new_names.contains_key(&entry.name()) && !renamed_old[i] {
+                changes.push(LumpChange::Removed {
+                    name: entry.name(),
+                    index: entry.index,
+                });
+            }
+        }
+
+        Self { changes }
+    }
+
+    /// Returns true if the WADs have the same lumps
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Save the pixels differences as PNG files in the directory `dir`
+    pub fn save_pixels<P: AsRef<Path>>(&self, dir: P) {
+        for change in self.changes.iter() {
+            let (name, details) = match change {
+                LumpChange::Modified { name, details, .. } => (name, details),
+                _ => continue,
+            };
+
+            for detail in details {
+                if let ChangeDetail::Pixels {
+                    width,
+                    height,
+                    rgba,
+                    ..
+                } = detail
+                {
+                    let path = dir.as_ref().join(format!("{}.diff.png", name));
+
+                    image::save_buffer(
+                        path,
+                        rgba,
+                        *width as u32,
+                        *height as u32,
+                        image::ColorType::Rgba8,
+                    )
+                    .unwrap_or_default();
+                }
+            }
+        }
+    }
+
+    /// Get the differences as a JSON array
+    ///
+    /// The pixels renderings are omitted
+    pub fn to_json(&self) -> String {
+        let mut ret = String::from("[");
+
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                ret.push(',');
+            }
+
+            ret.push_str(&change.to_json());
+        }
+
+        ret.push(']');
+        ret
+    }
+}
+
+/// Escape `value` as a JSON string
+fn json_str(value: &str) -> String {
+    let mut ret = String::from("\"");
+
+    for c in value.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            c if c.is_control() => write!(ret, "\\u{:04x}", c as u32).unwrap_or_default(),
+            c => ret.push(c),
+        }
+    }
+
+    ret.push('"');
+    ret
+}
+
+/// Format a list as a JSON array
+fn json_list<T: Display>(values: &[T]) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+
+    format!("[{}]", values.join(","))
+}
+
+impl RecordsDiff {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"old\":{},\"new\":{},\"changed\":{}}}",
+            self.old,
+            self.new,
+            json_list(&self.changed)
+        )
+    }
+}
+
+impl ChangeDetail {
+    /// Get the detail as a JSON object
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Size { old, new } => {
+                format!("{{\"type\":\"size\",\"old\":{},\"new\":{}}}", old, new)
+            }
+            Self::Dimensions { old, new } => format!(
+                "{{\"type\":\"dimensions\",\"old\":[{},{}],\"new\":[{},{}]}}",
+                old.0, old.1, new.0, new.1
+            ),
+            Self::Offsets { old, new } => format!(
+                "{{\"type\":\"offsets\",\"old\":[{},{}],\"new\":[{},{}]}}",
+                old.0, old.1, new.0, new.1
+            ),
+            Self::Pixels {
+                changed,
+                width,
+                height,
+                ..
+            } => format!(
+                "{{\"type\":\"pixels\",\"changed\":{},\"width\":{},\"height\":{}}}",
+                changed, width, height
+            ),
+            Self::PaletteEntries { palette, entries } => format!(
+                "{{\"type\":\"palette\",\"palette\":{},\"entries\":{}}}",
+                palette,
+                json_list(entries)
+            ),
+            Self::Things(records) => {
+                format!("{{\"type\":\"things\",\"records\":{}}}", records.to_json())
+            }
+            Self::Linedefs(records) => format!(
+                "{{\"type\":\"linedefs\",\"records\":{}}}",
+                records.to_json()
+            ),
+        }
+    }
+}
+
+impl Display for ChangeDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), Error> {
+        match self {
+            Self::Size { old, new } => write!(f, "size {} -> {}", old, new),
+            Self::Dimensions { old, new } => {
+                write!(f, "dimensions {}x{} -> {}x{}", old.0, old.1, new.0, new.1)
+            }
+            Self::Offsets { old, new } => {
+                write!(f, "offsets {},{} -> {},{}", old.0, old.1, new.0, new.1)
+            }
+            Self::Pixels { changed, .. } => write!(f, "{} pixels changed", changed),
+            Self::PaletteEntries { palette, entries } => {
+                write!(f, "palette {}: {} entries changed", palette, entries.len())
+            }
+            Self::Things(records) => write!(
+                f,
+                "things {} -> {}, {} changed",
+                records.old,
+                records.new,
+                records.changed.len()
+            ),
+            Self::Linedefs(records) => write!(
+                f,
+                "linedefs {} -> {}, {} changed",
+                records.old,
+                records.new,
+                records.changed.len()
+            ),
+        }
+    }
+}
+
+impl LumpChange {
+    /// Get the change as a JSON object
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Added { name, index } => format!(
+                "{{\"change\":\"added\",\"name\":{},\"index\":{}}}",
+                json_str(name),
+                index
+            ),
+            Self::Removed { name, index } => format!(
+                "{{\"change\":\"removed\",\"name\":{},\"index\":{}}}",
+                json_str(name),
+                index
+            ),
+            Self::Renamed { old, new, index } => format!(
+                "{{\"change\":\"renamed\",\"old\":{},\"new\":{},\"index\":{}}}",
+                json_str(old),
+                json_str(new),
+                index
+            ),
+            Self::Moved { name, old, new } => format!(
+                "{{\"change\":\"moved\",\"name\":{},\"old\":{},\"new\":{}}}",
+                json_str(name),
+                old,
+                new
+            ),
+            Self::Modified {
+                name,
+                index,
+                details,
+            } => {
+                let details: Vec<String> = details.iter().map(|d| d.to_json()).collect();
+
+                format!(
+                    "{{\"change\":\"modified\",\"name\":{},\"index\":{},\"details\":[{}]}}",
+                    json_str(name),
+                    index,
+                    details.join(",")
+                )
+            }
+        }
+    }
+}
+
+impl Display for LumpChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), Error> {
+        match self {
+            Self::Added { name, index } => write!(f, "+ {} ({})", name, index),
+            Self::Removed { name, index } => write!(f, "- {} ({})", name, index),
+            Self::Renamed { old, new, index } => write!(f, "> {} -> {} ({})", old, new, index),
+            Self::Moved { name, old, new } => write!(f, "^ {} ({} -> {})", name, old, new),
+            Self::Modified {
+                name,
+                index,
+                details,
+            } => {
+                let details: Vec<String> = details.iter().map(|d| d.to_string()).collect();
+
+                write!(f, "~ {} ({}): {}", name, index, details.join(", "))
+            }
+        }
+    }
+}
+
+impl Display for WadDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), Error> {
+        for change in self.changes.iter() {
+            writeln!(f, "{}", change)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,29 +1,34 @@
-use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::{HashMap, LinkedList};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use crate::{
+    address::LumpAddress,
+    classifier::{Classification, ClassifyInput, Confidence, LumpClassifier},
     error::WadError,
-    lump::{LumpAddKind, LumpData, LumpInfo, LumpKind, LumpState},
+    jaguar,
+    lump::{
+        LumpAdd, LumpAddKind, LumpBuffer, LumpData, LumpHandle, LumpInfo, LumpKind, LumpPosition,
+        LumpState, WadSource,
+    },
     lumps::{
-        flat::Flat, music::lump::DoomMusic, palette::Palettes, patch::DoomImage, unknown::Unknown,
+        flat::Flat, music::lump::DoomMusic, palette::Palettes, patch::DoomImage, png::Png,
+        sound::DoomSound, unknown::Unknown,
     },
     models::lump::Lump,
+    namespace::{Marker, MarkerEdge, Namespace, NamespaceStack},
+    registry::LumpRegistry,
     wad::WadInfo,
 };
 
-lazy_static! {
-    /// Flat start lump name
-    static ref RE_F_START: Regex = Regex::new("F[0-9]+_START").unwrap();
-    /// Flat end lump name
-    static ref RE_F_END: Regex = Regex::new("F[0-9]+_END").unwrap();
-    /// Patch/Sprite start lump name
-    static ref RE_S_START: Regex = Regex::new("S[0-9]+_START").unwrap();
-    /// Patch/Sprite end lump name
-    static ref RE_S_END: Regex = Regex::new("S[0-9]+_END").unwrap();
-    /// DOOM games lump name
-    static ref RE_DOOM_MUSIC: Regex = Regex::new("^D_").unwrap();
-}
+/// Lumps that can follow a map marker
+pub const MAP_LUMPS: &[&str] = &[
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES", "SECTORS", "REJECT",
+    "BLOCKMAP", "BEHAVIOR", "SCRIPTS", "TEXTMAP", "ZNODES", "DIALOGUE", "ENDMAP", "LEAFS",
+    "LIGHTS", "MACROS", "GL_VERT", "GL_SEGS", "GL_SSECT", "GL_NODES", "GL_PVS",
+];
 
 /// DOOM Palette max value
 pub const MAX_PAL: usize = 13;
@@ -34,8 +39,79 @@ pub struct LumpsDirectory {
     pub lumps: Vec<Box<dyn Lump>>,
     /// Palette
     pub pal: Palettes,
+    /// Used to find the lumps kind
+    pub classifier: LumpClassifier,
+    /// Caller decoders, tried before the built-in lump types
+    pub registry: LumpRegistry,
     /// Used during the directory parsing
-    marker: LinkedList<LumpKind>,
+    marker: NamespaceStack,
+    /// Names counter, used during the directory parsing
+    names: HashMap<String, usize>,
+    /// Generation of the handles, it changes on every (re)load
+    generation: u32,
+    /// Next handle slot
+    next_slot: usize,
+}
+
+/// Last directory generation, so the handles of a WAD never match another one
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+
+/// Get a new directory generation
+fn next_generation() -> u32 {
+    GENERATION.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Returns the map name every lump belongs to, following the order of `names`
+///
+/// A map is a marker followed by `THINGS` or `TEXTMAP` then by the map lumps
+pub fn maps_of(names: &[String]) -> Vec<Option<String>> {
+    let mut current: Option<String> = None;
+    let mut ret = Vec::with_capacity(names.len());
+
+    for (i, name) in names.iter().enumerate() {
+        let next = names.get(i + 1).map(|n| n.as_str());
+
+        if matches!(next, Some("THINGS") | Some("TEXTMAP")) && !MAP_LUMPS.contains(&&**name) {
+            current = Some(name.clone());
+        } else if !MAP_LUMPS.contains(&&**name) {
+            current = None;
+        }
+
+        ret.push(current.clone());
+
+        // UDMF maps end with their own marker
+        if name == "ENDMAP" {
+            current = None;
+        }
+    }
+
+    ret
+}
+
+/// Find the index of the entry at `address` (`LumpAddress`) among `entries`
+///
+/// The legacy unique IDs (`THINGS1`) are still accepted
+/// when no alive entry has this name
+pub fn find_address(entries: &[LumpInfo], address: &str) -> Option<usize> {
+    let names: Vec<String> = entries.iter().map(|e| e.name_ascii()).collect();
+    let maps = match address.contains('/') {
+        true => maps_of(&names),
+        false => vec![None; names.len()],
+    };
+    let alive = |i: &usize| entries[*i].state.is_alive();
+    let found = LumpAddress::parse(address).and_then(|value| {
+        value.find(
+            (0..entries.len())
+                .filter(alive)
+                .map(|i| (i, names[i].as_str(), maps[i].as_deref())),
+        )
+    });
+
+    found.or_else(|| {
+        (0..entries.len())
+            .filter(alive)
+            .find(|i| entries[*i].id_ascii() == address)
+    })
 }
 
 impl Default for LumpsDirectory {
@@ -43,7 +119,12 @@ impl Default for LumpsDirectory {
         Self {
             lumps: Vec::new(),
             pal: Palettes::default(),
-            marker: LinkedList::new(),
+            classifier: LumpClassifier::default(),
+            registry: LumpRegistry::default(),
+            marker: NamespaceStack::new(),
+            names: HashMap::new(),
+            generation: next_generation(),
+            next_slot: 0,
         }
     }
 }
@@ -55,7 +136,7 @@ impl LumpsDirectory {
     /// Iterate then calling a function on the matching lumps
     pub fn callback_lumps<F: Fn(&Box<dyn Lump>)>(&self, re: Regex, f: F) {
         for lump in self.lumps.iter() {
-            let name = lump.data().metadata.name_ascii();
+            let name = lump.metadata().name_ascii();
 
             if re.is_match(&name) {
                 f(lump);
@@ -66,7 +147,7 @@ impl LumpsDirectory {
     /// Iterate then calling a function on the matching mutable lumps
     pub fn callback_lumps_mut<F: Fn(&mut Box<dyn Lump>)>(&mut self, re: Regex, f: F) {
         for lump in self.lumps.iter_mut() {
-            let name = lump.data().metadata.name_ascii();
+            let name = lump.metadata().name_ascii();
 
             if re.is_match(&name) {
                 f(lump);
@@ -78,16 +159,125 @@ impl LumpsDirectory {
     pub fn remove_lumps(&mut self, re: Regex) -> usize {
         let indexes = self.indexes(re);
 
+        self.remove_indexes(&indexes)
+    }
+
+    /// Remove the lumps at `indexes`
+    ///
+    /// Returns the amount of lumps that were alive
+    pub fn remove_indexes(&mut self, indexes: &[usize]) -> usize {
+        let mut removed = 0;
+
         for index in indexes.iter() {
-            let lump = self.lumps.get_mut(*index).unwrap();
+            let lump = match self.lumps.get_mut(*index) {
+                Some(value) => value,
+                None => continue,
+            };
             let mut data = lump.data();
 
+            if data.metadata.state.is_alive() {
+                removed += 1;
+            }
+
             data.metadata.state = LumpState::Deleted;
 
             lump.set_data(data);
         }
 
-        indexes.len()
+        removed
+    }
+
+    /// Returns the namespace of every lump, following the directory order
+    ///
+    /// The markers belong to the enclosing namespace
+    pub fn namespaces(&self) -> Vec<Option<Namespace>> {
+        let mut stack = NamespaceStack::new();
+        let mut ret = Vec::with_capacity(self.lumps.len());
+
+        for lump in self.lumps.iter() {
+            let name = lump.metadata().name_ascii();
+            let outer = stack.current().cloned();
+            let marker = stack.update(&name).unwrap_or_default();
+
+            match marker {
+                Some(m) if m.edge == MarkerEdge::Start => ret.push(outer),
+                _ => ret.push(stack.current().cloned()),
+            }
+        }
+
+        ret
+    }
+
+    /// Check the markers nesting of the alive lumps
+    ///
+    /// Sub markers (`F1_START`) are only allowed inside their namespace
+    pub fn validate_namespaces(&self) -> Result<(), WadError> {
+        let mut stack = NamespaceStack::new();
+
+        for lump in self.lumps.iter() {
+            let metadata = lump.metadata();
+
+            if metadata.state.is_alive() {
+                stack.update(&metadata.name_ascii())?;
+            }
+        }
+
+        match stack.current() {
+            Some(namespace) => Err(WadError::InvalidNamespace(format!(
+                "{} is not closed",
+                namespace.start_marker()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Find the index of the end marker closing the last `namespace` block
+    pub fn namespace_end(&self, namespace: &Namespace) -> Option<usize> {
+        self.lumps.iter().rposition(|lump| {
+            let metadata = lump.metadata();
+            let marker = Marker::parse(&metadata.name_ascii());
+
+            metadata.state.is_alive()
+                && matches!(marker, Some(m) if m.namespace == *namespace
+                    && m.edge == MarkerEdge::End
+                    && !m.sub)
+        })
+    }
+
+    /// Make sure `namespace` exists, adding its markers at the end
+    /// of the directory if it is missing
+    ///
+    /// Returns the amount of created markers
+    pub fn ensure_namespace(&mut self, namespace: &Namespace) -> usize {
+        if self.namespace_end(namespace).is_some() {
+            return 0;
+        }
+
+        for name in [namespace.start_marker(), namespace.end_marker()] {
+            let data = LumpData {
+                buffer: Vec::new().into(),
+                metadata: LumpInfo::new(0, 0, LumpAdd::name_bytes(&name)),
+                kind: LumpKind::Marker,
+                confidence: Confidence::Certain,
+            };
+
+            self.lumps.push(Box::new(Unknown { data }));
+        }
+
+        2
+    }
+
+    /// Returns the map name every lump belongs to, following the directory order
+    ///
+    /// A map is a marker followed by `THINGS` or `TEXTMAP` then by the map lumps
+    pub fn maps(&self) -> Vec<Option<String>> {
+        let names: Vec<String> = self
+            .lumps
+            .iter()
+            .map(|lump| lump.metadata().name_ascii())
+            .collect();
+
+        maps_of(&names)
     }
 
     /// Returns the indexes index
@@ -95,7 +285,7 @@ impl LumpsDirectory {
         let mut ret = Vec::new();
 
         for (i, lump) in self.lumps.iter().enumerate() {
-            let name = lump.data().metadata.id_ascii();
+            let name = lump.metadata().id_ascii();
 
             if re.is_match(&name) {
                 ret.push(i);
@@ -105,48 +295,69 @@ impl LumpsDirectory {
         ret
     }
 
-    /// Returns a lump index by name
+    /// Returns a lump index by address (`NAME`, `NAME#n`, `MAP01/THINGS`)
+    ///
+    /// See `LumpAddress`, the legacy unique IDs are still accepted
     pub fn index(&self, name: &str) -> Option<usize> {
-        for (i, lump) in self.lumps.iter().enumerate() {
-            if &*lump.data().metadata.id_ascii() == name {
-                return Some(i);
-            }
-        }
+        let entries: Vec<LumpInfo> = self.lumps.iter().map(|l| l.metadata()).collect();
 
-        None
+        find_address(&entries, name)
     }
 
-    /// Find an index depending of the kind `kind`
-    ///
-    /// Mainly used for new lumps
-    pub fn index_from_kind(&self, kind: LumpAddKind) -> Result<usize, WadError> {
-        let ret = match kind {
-            LumpAddKind::After(name) => {
-                let i = self.index(name);
+    /// Get the address of the alive lump `index`, like `NAME` or `NAME#n`
+    pub fn address(&self, index: usize) -> Option<String> {
+        let metadata = self.lumps.get(index)?.metadata();
 
-                if i.is_none() {
-                    return Err(WadError::InvalidLumpName);
-                }
+        if !metadata.state.is_alive() {
+            return None;
+        }
 
-                i.unwrap() + 1
-            }
-            LumpAddKind::Before(name) => {
-                let i = self.index(name);
+        let name = metadata.name_ascii();
+        let n = self.lumps[..index]
+            .iter()
+            .map(|lump| lump.metadata())
+            .filter(|m| m.state.is_alive() && m.name_ascii() == name)
+            .count();
+        let address = LumpAddress { map: None, name, n };
 
-                if i.is_none() {
-                    return Err(WadError::InvalidLumpName);
-                }
+        Some(address.to_string())
+    }
 
-                let ret = i.unwrap();
+    /// Returns the index of the alive lump `handle`
+    pub fn index_of(&self, handle: LumpHandle) -> Option<usize> {
+        self.lumps.iter().position(|lump| {
+            let metadata = lump.metadata();
 
-                if ret <= 0 {
-                    0
-                } else {
-                    ret - 1
-                }
-            }
-            LumpAddKind::Front => 0,
-            LumpAddKind::Back => self.lumps.len(),
+            metadata.state.is_alive() && metadata.handle == Some(handle)
+        })
+    }
+
+    /// Find an index depending of the kind `kind`
+    ///
+    /// Mainly used for new lumps
+    pub fn index_from_kind(&self, kind: LumpAddKind<'_>) -> Result<usize, WadError> {
+        self.index_from_position(&kind.into())
+    }
+
+    /// Find an index depending of the position `position`
+    pub fn index_from_position(&self, position: &LumpPosition) -> Result<usize, WadError> {
+        let ret = match position {
+            LumpPosition::After(name) => match self.index(name) {
+                Some(value) => value + 1,
+                None => return Err(WadError::InvalidLumpName),
+            },
+            LumpPosition::Before(name) => match self.index(name) {
+                Some(value) => value,
+                None => return Err(WadError::InvalidLumpName),
+            },
+            LumpPosition::Namespace(namespace) => match self.namespace_end(namespace) {
+                Some(value) => value,
+                None => return Err(WadError::InvalidNamespace(namespace.start_marker())),
+            },
+            LumpPosition::Front => 0,
+            LumpPosition::Back => self.lumps.len(),
+            LumpPosition::Index(index) if *index <= self.lumps.len() => *index,
+            LumpPosition::Index(_) => return Err(WadError::InvalidLump),
         };
 
         Ok(ret)
@@ -168,130 +379,303 @@ impl LumpsDirectory {
         self.pal.set_n(value % MAX_PAL);
     }
 
-    /// Update the marker, handling the 0 bytes lumps like flat/patch delimiters
-    fn set_marker(&mut self, name: &str) {
-        if RE_F_START.is_match(name) {
-            self.marker.push_back(LumpKind::Flat);
-        }
+    /// Set the palettes then rebuild the image lumps with them
+    pub fn set_palettes(&mut self, value: Palettes) -> Result<(), WadError> {
+        self.pal = value;
 
-        if RE_S_START.is_match(name) {
-            self.marker.push_back(LumpKind::Patch);
-        }
+        let namespaces = self.namespaces();
+
+        for (index, namespace) in namespaces.iter().enumerate() {
+            let data = self.lumps[index].data();
 
-        if RE_F_END.is_match(name) {
-            self.marker.pop_back();
+            if matches!(data.kind, LumpKind::Patch | LumpKind::Flat) {
+                self.lumps[index] = self.build_lump(data, namespace.as_ref())?;
+            }
         }
 
-        if RE_S_END.is_match(name) {
-            self.marker.pop_back();
+        Ok(())
+    }
+
+    /// Get the namespace opened at the last classified lump
+    pub fn namespace(&self) -> Option<&Namespace> {
+        self.marker.current()
+    }
+
+    /// Classify a lump from its name, the current markers and its content
+    ///
+    /// It must be called following the directory order
+    pub fn classify(&mut self, metadata: &LumpInfo, buffer: Option<&[u8]>) -> Classification {
+        let name = metadata.name_ascii();
+
+        // A bad nesting is not fatal when loading,
+        // only the non markers lumps returns `Ok(None)`
+        match self.marker.update(&name) {
+            Ok(None) => {}
+            _ => return Classification::new(LumpKind::Marker, Confidence::Certain),
         }
+
+        let input = ClassifyInput {
+            name: &name,
+            size: metadata.size.max(0) as usize,
+            namespace: self.marker.current(),
+            buffer,
+        };
+
+        self.classifier.classify(&input)
     }
 
-    fn pop_marker(&mut self, metadata: &LumpInfo, name: &str, mut data: LumpData) -> Box<dyn Lump> {
-        self.set_marker(name);
+    /// Set the lump unique ID, counting the lumps
+    /// with the same name already seen
+    pub fn set_id(&mut self, metadata: &mut LumpInfo) {
+        let n = self.names.entry(metadata.name_ascii()).or_default();
 
-        if metadata.size <= 0 || self.marker.len() <= 0 {
-            return Box::new(Unknown { data });
+        metadata.n = *n;
+        *n += 1;
+    }
+
+    /// Give a handle to the lump, unless it already has one from this directory
+    pub fn set_handle(&mut self, metadata: &mut LumpInfo) {
+        if metadata
+            .handle
+            .is_some_and(|h| h.generation == self.generation)
+        {
+            return;
         }
 
-        let last = self.marker.back().unwrap();
+        metadata.handle = Some(LumpHandle {
+            slot: self.next_slot,
+            generation: self.generation,
+        });
+        self.next_slot += 1;
+    }
+
+    /// Set the unique ID of every lump again, following the directory order,
+    /// the new lumps get a handle
+    ///
+    /// Needed once the lumps have been added, renamed or reordered
+    pub fn refresh_ids(&mut self) {
+        self.names.clear();
 
-        match last {
-            LumpKind::Patch => {
-                data.kind = LumpKind::Patch;
+        for index in 0..self.lumps.len() {
+            let mut metadata = self.lumps[index].metadata();
+            let (n, handle) = (metadata.n, metadata.handle);
 
-                Box::new(DoomImage::new(self.pal.clone(), data))
-            }
-            LumpKind::Flat => {
-                data.kind = LumpKind::Flat;
-                Box::new(Flat::new(self.pal.clone(), data))
+            self.set_id(&mut metadata);
+            self.set_handle(&mut metadata);
+
+            // The data is only cloned for the changed lumps
+            if metadata.n != n || metadata.handle != handle {
+                let mut data = self.lumps[index].data();
+
+                data.metadata = metadata;
+                self.lumps[index].set_data(data);
             }
-            _ => Box::new(Unknown { data }),
         }
     }
 
-    /// Iterating over the directory and filling `self.lumps`
-    pub fn parse(&mut self, info: WadInfo, buffer: &Vec<u8>) -> Result<(), WadError> {
-        self.lumps.clear();
-        self.marker.clear();
+    /// Rename the lump `name` (address), `value` must be a valid lump name
+    pub fn rename_lump(&mut self, name: &str, value: &str) -> Result<(), WadError> {
+        let value = LumpInfo::valid_name(value)?;
+        let index = self.index(name).ok_or(WadError::InvalidLumpName)?;
+        let mut data = self.lumps[index].data();
 
-        // Preventing multiple names
-        let mut names: HashMap<String, usize> = HashMap::new();
+        data.metadata.name = value;
+        self.lumps[index].set_data(data);
+        self.refresh_ids();
 
-        for lump_num in 0..(info.num_lumps as usize) {
-            let index = (info.dir_pos as usize) + (lump_num * 16);
+        Ok(())
+    }
 
-            // Get lump informations then data
-            let mut metadata = LumpInfo::from(&buffer[index..index + 16]);
-            let pos = metadata.pos as usize;
-            let size = metadata.size as usize;
-            let name = metadata.name_ascii();
+    /// Put the lumps in the order `order`, the previous index of every lump
+    pub fn reorder(&mut self, order: &[usize]) {
+        let mut lumps: Vec<Option<Box<dyn Lump>>> = self.lumps.drain(..).map(Some).collect();
 
-            let id = match names.get(&name) {
-                Some(count) => {
-                    let value = count + 1;
-                    names.insert(name.clone(), value);
+        self.lumps = order.iter().filter_map(|i| lumps[*i].take()).collect();
+        self.refresh_ids();
+    }
 
-                    format!("{}{}", name, value)
-                }
-                None => {
-                    names.insert(name.clone(), 0);
+    /// Move the lump `name` (address) to `position`
+    ///
+    /// The position is resolved before taking the lump out,
+    /// except `LumpPosition::Index` which is the final index.
+    /// Returns the previous index of every lump
+    pub fn move_lump(
+        &mut self,
+        name: &str,
+        position: &LumpPosition,
+    ) -> Result<Vec<usize>, WadError> {
+        let from = self.index(name).ok_or(WadError::InvalidLumpName)?;
+        let to = match (position, self.index_from_position(position)?) {
+            (LumpPosition::Index(_), to) => to,
+            (_, to) if to > from => to - 1,
+            (_, to) => to,
+        };
 
-                    name.clone()
-                }
-            };
+        if to >= self.lumps.len() {
+            return Err(WadError::InvalidLump);
+        }
+
+        let mut order: Vec<usize> = (0..self.lumps.len()).collect();
 
-            for (i, byte) in id.as_bytes().iter().enumerate() {
-                metadata.id[i] = *byte;
+        order.remove(from);
+        order.insert(to, from);
+        self.reorder(&order);
+
+        Ok(order)
+    }
+
+    /// Swap the lumps `a` and `b` (addresses)
+    ///
+    /// Returns the previous index of every lump
+    pub fn swap_lumps(&mut self, a: &str, b: &str) -> Result<Vec<usize>, WadError> {
+        let a = self.index(a).ok_or(WadError::InvalidLumpName)?;
+        let b = self.index(b).ok_or(WadError::InvalidLumpName)?;
+        let mut order: Vec<usize> = (0..self.lumps.len()).collect();
+
+        order.swap(a, b);
+        self.reorder(&order);
+
+        Ok(order)
+    }
+
+    /// Sort the lumps of `namespace` by name
+    ///
+    /// Every run of lumps between two markers is sorted on its own,
+    /// so the markers and the sub namespaces stay in place.
+    /// Returns the previous index of every lump
+    pub fn sort_namespace(&mut self, namespace: &Namespace) -> Vec<usize> {
+        let names: Vec<String> = self
+            .lumps
+            .iter()
+            .map(|lump| lump.metadata().name_ascii())
+            .collect();
+        let mut inside: Vec<bool> = self
+            .namespaces()
+            .iter()
+            .zip(self.lumps.iter())
+            .map(|(ns, lump)| {
+                ns.as_ref() == Some(namespace) && lump.data().kind != LumpKind::Marker
+            })
+            .collect();
+        let mut order: Vec<usize> = (0..self.lumps.len()).collect();
+        let mut start = 0;
+
+        // Closing the last run
+        inside.push(false);
+
+        for (end, value) in inside.into_iter().enumerate() {
+            if !value {
+                order[start..end].sort_by(|a, b| names[*a].cmp(&names[*b]));
+                start = end + 1;
             }
-            let mut data = LumpData {
-                buffer: buffer[pos..pos + size].to_vec(),
-                metadata,
-                kind: LumpKind::Unknown,
-            };
+        }
 
-            let mut lump: Box<dyn Lump> = match &*name {
-                "PLAYPAL" => {
-                    data.kind = LumpKind::Palette;
+        self.reorder(&order);
 
-                    self.pal.set_data(data);
-                    // Special case that must be parsed before copied
-                    self.pal.parse()?;
+        order
+    }
 
-                    Box::new(self.pal.clone())
-                }
+    /// Build the lump implementation matching `data.kind`,
+    /// unless a caller decoder handles it
+    ///
+    /// The palettes are parsed right away because the images depend on them
+    pub fn build_lump(
+        &mut self,
+        data: LumpData,
+        namespace: Option<&Namespace>,
+    ) -> Result<Box<dyn Lump>, WadError> {
+        if let Some(lump) = self.registry.decode(&data, namespace, &self.pal) {
+            return lump;
+        }
 
-                "F_START" => {
-                    self.marker.push_back(LumpKind::Flat);
+        let lump: Box<dyn Lump> = match data.kind {
+            LumpKind::Palette => {
+                self.pal.set_data(data);
+                self.pal.parse()?;
 
-                    Box::new(Unknown { data })
-                }
+                Box::new(self.pal.clone())
+            }
+            LumpKind::Patch => Box::new(DoomImage::new(self.pal.clone(), data)),
+            LumpKind::Flat => Box::new(Flat::new(self.pal.clone(), data)),
+            LumpKind::Music => Box::new(DoomMusic::new(data)),
+            LumpKind::Sound => Box::new(DoomSound::new(data)),
+            LumpKind::Png => Box::new(Png::new(data)),
+            _ => Box::new(Unknown { data }),
+        };
 
-                "S_START" | "SS_START" => {
-                    self.marker.push_back(LumpKind::Patch);
+        Ok(lump)
+    }
 
-                    Box::new(Unknown { data })
-                }
+    /// Reset the parsing state
+    pub fn clear(&mut self) {
+        self.lumps.clear();
+        self.marker.clear();
+        self.names.clear();
+        self.generation = next_generation();
+        self.next_slot = 0;
+    }
+
+    /// Iterating over the directory and filling `self.lumps`
+    ///
+    /// The lumps only keep a view into `buffer`, they are decoded
+    /// the first time their content is requested.
+    /// The palettes are the exception because the images depend on them
+    pub fn parse(&mut self, info: WadInfo, buffer: &WadSource) -> Result<(), WadError> {
+        self.clear();
+
+        let bytes = (**buffer).as_ref();
+        let invalid = || WadError::Parse(String::from("Invalid directory"));
+
+        if info.num_lumps < 0 || info.dir_pos < 0 {
+            return Err(invalid());
+        }
 
-                "F_END" | "S_END" | "SS_END" => {
-                    self.marker.pop_back();
+        // The whole directory must fit the buffer, like `WadReader` checks it
+        let (dir_pos, num_lumps) = (info.dir_pos as usize, info.num_lumps as usize);
 
-                    Box::new(Unknown { data })
-                }
+        num_lumps
+            .checked_mul(16)
+            .and_then(|size| dir_pos.checked_add(size))
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(invalid)?;
 
-                "TITLEPIC" => Box::new(DoomImage::new(self.pal.clone(), data)),
+        for lump_num in 0..num_lumps {
+            let index = dir_pos + lump_num * 16;
 
-                _ => {
-                    if RE_DOOM_MUSIC.is_match(&name) {
-                        Box::new(DoomMusic::new(data))
-                    } else {
-                        self.pop_marker(&metadata, &name, data)
-                    }
-                }
+            // Get lump informations then data
+            let mut metadata = LumpInfo::from(&bytes[index..index + 16]);
+
+            if metadata.pos < 0 || metadata.size < 0 {
+                return Err(WadError::InvalidLump);
+            }
+
+            let pos = metadata.pos as usize;
+            let size = metadata.size as usize;
+
+            self.set_id(&mut metadata);
+            self.set_handle(&mut metadata);
+
+            // The compressed data size is unknown, it ends with a marker
+            let lump_buffer = match metadata.compressed {
+                true => match bytes.get(pos..) {
+                    Some(value) => LumpBuffer::Owned(jaguar::decompress(value, size)?),
+                    None => return Err(WadError::InvalidLump),
+                },
+                false => match LumpBuffer::shared(buffer.clone(), pos, size) {
+                    Some(value) => value,
+                    None => return Err(WadError::InvalidLump),
+                },
             };
 
-            // Fetch and decode data from the WAD buffer
-            lump.parse()?;
+            let classification = self.classify(&metadata, Some(&lump_buffer));
+            let data = LumpData {
+                buffer: lump_buffer,
+                metadata,
+                kind: classification.kind,
+                confidence: classification.confidence,
+            };
+            let namespace = self.marker.current().cloned();
+            let lump = self.build_lump(data, namespace.as_ref())?;
 
             // Add the lump to the hashmap
             self.lumps.push(lump);
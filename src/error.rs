@@ -22,6 +22,12 @@ pub enum WadError {
     UnknownMusEvent,
     #[error("Invalid lump")]
     InvalidLump,
+    #[error("Invalid namespace: {0}")]
+    InvalidNamespace(String),
+    #[error("Lump conflict: {0}")]
+    Conflict(String),
+    #[error("Checksum mismatch: {0}")]
+    Checksum(&'static str),
     #[error("Unable to use this API")]
     Unknown,
 }
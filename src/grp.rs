@@ -0,0 +1,218 @@
+use std::{fs, path::Path, rc::Rc};
+
+use crate::{
+    error::WadError,
+    lump::{LumpBuffer, WadSource},
+    models::container::{move_item, ArchiveEntry, Container},
+    tree::{read_err, write_err},
+};
+
+/// GRP signature
+pub const MAGIC_GRP: &[u8] = b"KenSilverman";
+/// Header and directory entry size
+pub const GRP_ENTRY_SIZE: usize = 16;
+/// Entry name size
+pub const GRP_NAME_SIZE: usize = 12;
+
+/// Fail if `name` does not fit a GRP entry
+fn check_name(name: &str) -> Result<(), WadError> {
+    if name.is_empty() || name.len() > GRP_NAME_SIZE || !name.is_ascii() {
+        return Err(WadError::InvalidLumpName);
+    }
+
+    Ok(())
+}
+
+/// Build engine (Duke Nukem 3D) GRP archive
+///
+/// The header is followed by the entries names and sizes,
+/// then by their content in the same order
+#[derive(Default)]
+pub struct Grp {
+    /// Entries, following the directory order
+    entries: Vec<ArchiveEntry>,
+}
+
+impl Grp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a GRP buffer
+    pub fn load<T: Into<Vec<u8>>>(&mut self, buffer: T) -> Result<(), WadError> {
+        let src: WadSource = Rc::new(buffer.into());
+        let bytes = (*src).as_ref();
+
+        if bytes.len() < GRP_ENTRY_SIZE {
+            return Err(WadError::Load("The file size is too small."));
+        }
+
+        if &bytes[..GRP_NAME_SIZE] != MAGIC_GRP {
+            return Err(WadError::Type("The file is not a GRP file."));
+        }
+
+        let count = u32::from_le_bytes(bytes[12..16].try_into().unwrap_or_default()) as usize;
+        let mut pos = GRP_ENTRY_SIZE * (count + 1);
+
+        self.entries.clear();
+
+        for index in 1..=count {
+            let entry = bytes
+                .get(index * GRP_ENTRY_SIZE..(index + 1) * GRP_ENTRY_SIZE)
+                .ok_or(WadError::Parse(String::from("Invalid directory")))?;
+            let size = u32::from_le_bytes(entry[12..16].try_into().unwrap_or_default()) as usize;
+            let name = String::from_utf8_lossy(&entry[..GRP_NAME_SIZE])
+                .trim_end_matches(['\0', ' '])
+                .to_string();
+            let buffer = LumpBuffer::shared(src.clone(), pos, size).ok_or(WadError::InvalidLump)?;
+
+            self.entries.push(ArchiveEntry { name, buffer });
+            pos += size;
+        }
+
+        Ok(())
+    }
+
+    /// Read then parse the file at `path`
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        self.load(fs::read(path).map_err(read_err)?)
+    }
+
+    /// Get the entries
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// Add an entry, replacing the one with the same name
+    pub fn add(&mut self, name: &str, buffer: &[u8]) -> Result<(), WadError> {
+        check_name(name)?;
+
+        let entry = ArchiveEntry {
+            name: name.to_string(),
+            buffer: buffer.into(),
+        };
+
+        match self
+            .entries
+            .iter()
+            .position(|e| e.name.eq_ignore_ascii_case(name))
+        {
+            Some(index) => self.entries[index] = entry,
+            None => self.entries.push(entry),
+        }
+
+        Ok(())
+    }
+
+    /// Remove an entry, returns true if it existed
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+
+        self.entries
+            .retain(|entry| !entry.name.eq_ignore_ascii_case(name));
+        self.entries.len() != len
+    }
+
+    /// Build the GRP buffer
+    pub fn build(&self) -> Result<Vec<u8>, WadError> {
+        let mut ret = MAGIC_GRP.to_vec();
+
+        ret.extend((self.entries.len() as u32).to_le_bytes());
+
+        for entry in self.entries.iter() {
+            let mut name = [0; GRP_NAME_SIZE];
+            let size = u32::try_from(entry.buffer.len()).map_err(|_| WadError::Write)?;
+
+            if entry.name.len() > GRP_NAME_SIZE {
+                return Err(WadError::InvalidLumpName);
+            }
+
+            name[..entry.name.len()].copy_from_slice(entry.name.as_bytes());
+            ret.extend(name);
+            ret.extend(size.to_le_bytes());
+        }
+
+        for entry in self.entries.iter() {
+            ret.extend(&*entry.buffer);
+        }
+
+        Ok(ret)
+    }
+
+    /// Write the GRP file at `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), WadError> {
+        fs::write(path, self.build()?).map_err(write_err)
+    }
+}
+
+impl Container for Grp {
+    fn load_bytes(&mut self, buffer: Vec<u8>) -> Result<(), WadError> {
+        self.load(buffer)
+    }
+
+    fn entries_len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn entry_name(&self, index: usize) -> Option<String> {
+        self.entries.get(index).map(|entry| entry.name.clone())
+    }
+
+    fn entry_buffer(&self, index: usize) -> Option<LumpBuffer> {
+        self.entries.get(index).map(|entry| entry.buffer.clone())
+    }
+
+    fn set_entry_buffer(&mut self, index: usize, buffer: &[u8]) -> Result<(), WadError> {
+        let entry = self.entries.get_mut(index).ok_or(WadError::InvalidLump)?;
+
+        entry.buffer = buffer.into();
+
+        Ok(())
+    }
+
+    fn insert_entry(&mut self, index: usize, name: &str, buffer: &[u8]) -> Result<(), WadError> {
+        check_name(name)?;
+
+        if index > self.entries.len() {
+            return Err(WadError::InvalidLump);
+        }
+
+        self.entries.insert(
+            index,
+            ArchiveEntry {
+                name: name.to_string(),
+                buffer: buffer.into(),
+            },
+        );
+
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, index: usize) -> Result<(), WadError> {
+        if index >= self.entries.len() {
+            return Err(WadError::InvalidLump);
+        }
+
+        self.entries.remove(index);
+
+        Ok(())
+    }
+
+    fn rename_entry(&mut self, index: usize, name: &str) -> Result<(), WadError> {
+        check_name(name)?;
+
+        let entry = self.entries.get_mut(index).ok_or(WadError::InvalidLump)?;
+
+        entry.name = name.to_string();
+
+        Ok(())
+    }
+
+    fn move_entry(&mut self, from: usize, to: usize) -> Result<(), WadError> {
+        move_item(&mut self.entries, from, to)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, WadError> {
+        self.build()
+    }
+}
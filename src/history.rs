@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::WadError,
+    lump::{LumpData, LumpPosition},
+    namespace::{Marker, Namespace},
+};
+
+/// Edits log magic
+pub const LOG_MAGIC: &[u8; 4] = b"WDLG";
+/// Edits log format version
+pub const LOG_VERSION: u8 = 1;
+
+/// Deepest nesting of groups accepted when decoding a log
+const MAX_GROUP_DEPTH: usize = 64;
+
+/// A reversible WAD edit
+///
+/// The lumps are designated by their address (`LumpAddress`), so
+/// the edits can be replayed against another WAD
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edit {
+    /// Replace the raw content of a lump
+    SetRaw { id: String, buffer: Vec<u8> },
+    /// Remove a lump
+    Remove { id: String },
+    /// Add a lump like `WadOp::add_lump_raw`
+    Add {
+        position: LumpPosition,
+        name: String,
+        buffer: Vec<u8>,
+    },
+    /// Insert lumps one after the other at a position, like a map
+    Insert {
+        position: LumpPosition,
+        lumps: Vec<(String, Vec<u8>)>,
+    },
+    /// Rename a lump
+    Rename { id: String, name: String },
+    /// Move a lump
+    Move { id: String, position: LumpPosition },
+    /// Swap two lumps
+    Swap { a: String, b: String },
+    /// Sort the lumps of a namespace by name
+    Sort(Namespace),
+    /// Edits applied in order then undone at once
+    Group(Vec<Edit>),
+}
+
+/// What is needed to revert an edit
+#[derive(Clone, Debug)]
+pub(crate) enum Inverse {
+    /// Directory index and lump data before the edit
+    Data(usize, LumpData),
+    /// Directory indexes of the inserted lumps
+    Inserted(Vec<usize>),
+    /// Previous index of every lump
+    Order(Vec<usize>),
+    /// Inverses of a group, in the applied order
+    Group(Vec<Inverse>),
+}
+
+/// Applied and undone edits of a `Wad`
+#[derive(Default)]
+pub struct History {
+    /// Applied edits, in order
+    done: Vec<(Edit, Inverse)>,
+    /// Undone edits, the last one is redone first
+    undone: Vec<Edit>,
+    /// Named checkpoints, the amount of applied edits
+    checkpoints: HashMap<String, usize>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the applied edits, in order
+    pub fn edits(&self) -> Vec<&Edit> {
+        self.done.iter().map(|(edit, _)| edit).collect()
+    }
+
+    /// Returns true if an edit can be undone
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    /// Returns true if an edit can be redone
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    /// Get the checkpoint names
+    pub fn checkpoints(&self) -> Vec<&str> {
+        self.checkpoints.keys().map(|name| name.as_str()).collect()
+    }
+
+    /// Get the applied edits as a log
+    pub fn log(&self) -> EditLog {
+        EditLog {
+            edits: self.done.iter().map(|(edit, _)| edit.clone()).collect(),
+        }
+    }
+
+    /// Forget everything
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+        self.checkpoints.clear();
+    }
+
+    /// Record an applied edit, the undone ones cannot be redone anymore
+    pub(crate) fn push(&mut self, edit: Edit, inverse: Inverse) {
+        let len = self.done.len();
+
+        self.done.push((edit, inverse));
+        self.undone.clear();
+        self.checkpoints.retain(|_, value| *value <= len);
+    }
+
+    /// Take the last applied edit
+    pub(crate) fn pop_done(&mut self) -> Option<(Edit, Inverse)> {
+        self.done.pop()
+    }
+
+    /// Record an undone edit
+    pub(crate) fn push_undone(&mut self, edit: Edit) {
+        self.undone.push(edit);
+    }
+
+    /// Take the last undone edit
+    pub(crate) fn pop_undone(&mut self) -> Option<Edit> {
+        self.undone.pop()
+    }
+
+    /// Record an edit that has been redone
+    pub(crate) fn push_redone(&mut self, edit: Edit, inverse: Inverse) {
+        self.done.push((edit, inverse));
+    }
+
+    /// Name the current state
+    pub(crate) fn set_checkpoint(&mut self, name: &str) {
+        self.checkpoints.insert(name.to_string(), self.done.len());
+    }
+
+    /// Amount of applied edits at the checkpoint `name`
+    pub(crate) fn checkpoint(&self, name: &str) -> Option<usize> {
+        self.checkpoints.get(name).copied()
+    }
+
+    /// Amount of applied edits
+    pub(crate) fn done_len(&self) -> usize {
+        self.done.len()
+    }
+}
+
+/// Inverse of the order `order`
+pub(crate) fn invert_order(order: &[usize]) -> Vec<usize> {
+    let mut ret = vec![0; order.len()];
+
+    for (i, value) in order.iter().enumerate() {
+        ret[*value] = i;
+    }
+
+    ret
+}
+
+/// Serializable list of edits
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EditLog {
+    /// Edits, in order
+    pub edits: Vec<Edit>,
+}
+
+impl EditLog {
+    /// Encode the log
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::new();
+
+        ret.extend_from_slice(LOG_MAGIC);
+        ret.push(LOG_VERSION);
+        ret.extend_from_slice(&(self.edits.len() as u32).to_le_bytes());
+
+        for edit in self.edits.iter() {
+            put_edit(&mut ret, edit);
+        }
+
+        ret
+    }
+
+    /// Decode a log
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WadError> {
+        let mut cursor = LogCursor { bytes, pos: 0 };
+        let mut ret = Self::default();
+
+        if cursor.take(4)? != LOG_MAGIC || cursor.u8()? != LOG_VERSION {
+            return Err(WadError::Type("The file is not a supported edits log."));
+        }
+
+        for _ in 0..cursor.u32()? {
+            let edit = cursor.edit(0)?;
+
+            ret.edits.push(edit);
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Push a length prefixed buffer
+fn put_bytes(dest: &mut Vec<u8>, bytes: &[u8]) {
+    dest.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    dest.extend_from_slice(bytes);
+}
+
+/// Push an edit
+fn put_edit(dest: &mut Vec<u8>, edit: &Edit) {
+    match edit {
+        Edit::SetRaw { id, buffer } => {
+            dest.push(0);
+            put_bytes(dest, id.as_bytes());
+            put_bytes(dest, buffer);
+        }
+        Edit::Remove { id } => {
+            dest.push(1);
+            put_bytes(dest, id.as_bytes());
+        }
+        Edit::Add {
+            position,
+            name,
+            buffer,
+        } => {
+            dest.push(2);
+            put_position(dest, position);
+            put_bytes(dest, name.as_bytes());
+            put_bytes(dest, buffer);
+        }
+        Edit::Insert { position, lumps } => {
+            dest.push(3);
+            put_position(dest, position);
+            dest.extend_from_slice(&(lumps.len() as u32).to_le_bytes());
+
+            for (name, buffer) in lumps.iter() {
+                put_bytes(dest, name.as_bytes());
+                put_bytes(dest, buffer);
+            }
+        }
+        Edit::Rename { id, name } => {
+            dest.push(4);
+            put_bytes(dest, id.as_bytes());
+            put_bytes(dest, name.as_bytes());
+        }
+        Edit::Move { id, position } => {
+            dest.push(5);
+            put_bytes(dest, id.as_bytes());
+            put_position(dest, position);
+        }
+        Edit::Swap { a, b } => {
+            dest.push(6);
+            put_bytes(dest, a.as_bytes());
+            put_bytes(dest, b.as_bytes());
+        }
+        Edit::Sort(namespace) => {
+            dest.push(7);
+            put_bytes(dest, namespace.start_marker().as_bytes());
+        }
+        Edit::Group(edits) => {
+            dest.push(8);
+            dest.extend_from_slice(&(edits.len() as u32).to_le_bytes());
+
+            for edit in edits.iter() {
+                put_edit(dest, edit);
+            }
+        }
+    }
+}
+
+/// Push a lump position
+fn put_position(dest: &mut Vec<u8>, position: &LumpPosition) {
+    match position {
+        LumpPosition::After(name) => {
+            dest.push(0);
+            put_bytes(dest, name.as_bytes());
+        }
+        LumpPosition::Before(name) => {
+            dest.push(1);
+            put_bytes(dest, name.as_bytes());
+        }
+        LumpPosition::Front => dest.push(2),
+        LumpPosition::Back => dest.push(3),
+        LumpPosition::Index(index) => {
+            dest.push(4);
+            dest.extend_from_slice(&(*index as u32).to_le_bytes());
+        }
+        LumpPosition::Namespace(namespace) => {
+            dest.push(5);
+            put_bytes(dest, namespace.start_marker().as_bytes());
+        }
+    }
+}
+
+/// Used to decode a log
+struct LogCursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> LogCursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WadError> {
+        let ret = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or(WadError::Parse(String::from("Truncated log")))?;
+
+        self.pos += len;
+
+        Ok(ret)
+    }
+
+    fn u8(&mut self) -> Result<u8, WadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, WadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, WadError> {
+        let len = self.u32()? as usize;
+
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String, WadError> {
+        String::from_utf8(self.bytes()?).map_err(|_| WadError::Parse(String::from("Invalid name")))
+    }
+
+    fn namespace(&mut self) -> Result<Namespace, WadError> {
+        let marker = self.string()?;
+
+        match Marker::parse(&marker) {
+            Some(value) => Ok(value.namespace),
+            None => Err(WadError::InvalidNamespace(marker)),
+        }
+    }
+
+    /// Decode an edit, nested in `depth` groups
+    fn edit(&mut self, depth: usize) -> Result<Edit, WadError> {
+        let ret = match self.u8()? {
+            0 => Edit::SetRaw {
+                id: self.string()?,
+                buffer: self.bytes()?,
+            },
+            1 => Edit::Remove { id: self.string()? },
+            2 => Edit::Add {
+                position: self.position()?,
+                name: self.string()?,
+                buffer: self.bytes()?,
+            },
+            3 => {
+                let position = self.position()?;
+                let mut lumps = Vec::new();
+
+                for _ in 0..self.u32()? {
+                    lumps.push((self.string()?, self.bytes()?));
+                }
+
+                Edit::Insert { position, lumps }
+            }
+            4 => Edit::Rename {
+                id: self.string()?,
+                name: self.string()?,
+            },
+            5 => Edit::Move {
+                id: self.string()?,
+                position: self.position()?,
+            },
+            6 => Edit::Swap {
+                a: self.string()?,
+                b: self.string()?,
+            },
+            7 => Edit::Sort(self.namespace()?),
+            8 if depth >= MAX_GROUP_DEPTH => {
+                return Err(WadError::Parse(String::from(
+                    "Too deeply nested log groups",
+                )))
+            }
+            8 => {
+                let mut edits = Vec::new();
+
+                for _ in 0..self.u32()? {
+                    edits.push(self.edit(depth + 1)?);
+                }
+
+                Edit::Group(edits)
+            }
+            _ => return Err(WadError::Parse(String::from("Invalid log edit"))),
+        };
+
+        Ok(ret)
+    }
+
+    fn position(&mut self) -> Result<LumpPosition, WadError> {
+        let ret = match self.u8()? {
+            0 => LumpPosition::After(self.string()?),
+            1 => LumpPosition::Before(self.string()?),
+            2 => LumpPosition::Front,
+            3 => LumpPosition::Back,
+            4 => LumpPosition::Index(self.u32()? as usize),
+            5 => LumpPosition::Namespace(self.namespace()?),
+            _ => return Err(WadError::Parse(String::from("Invalid log position"))),
+        };
+
+        Ok(ret)
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use crate::error::WadError;
+
+/// Flag set on the first name byte of a compressed lump
+pub const COMPRESSED_FLAG: u8 = 0x80;
+/// Back references window size
+const WINDOW_SIZE: usize = 4096;
+/// Longest back reference
+const MAX_MATCH: usize = 16;
+/// Shortest back reference worth writing
+const MIN_MATCH: usize = 3;
+/// Candidates checked per position when compressing
+const MAX_CHAIN: usize = 256;
+
+/// Decompress a lump compressed with the Jaguar LZSS, `size` being
+/// the decompressed size from the directory
+///
+/// Every group of 8 items starts with a flags byte, a set bit is a
+/// 12 bits distance and 4 bits length back reference, a clear bit a literal.
+/// A back reference with a null length ends the data
+pub fn decompress(input: &[u8], size: usize) -> Result<Vec<u8>, WadError> {
+    let invalid = || WadError::Parse(String::from("Invalid compressed lump"));
+    // `size` comes from the directory, a literal per bit at most
+    let mut output = Vec::with_capacity(size.min(input.len() * 8));
+    let mut bytes = input.iter().copied();
+    let mut flags = 0u8;
+
+    for item in 0.. {
+        // The data can not be longer than the directory size
+        if output.len() > size {
+            return Err(invalid());
+        }
+
+        if item % 8 == 0 {
+            flags = bytes.next().ok_or_else(invalid)?;
+        }
+
+        let flag = flags & 1;
+
+        flags >>= 1;
+
+        if flag == 0 {
+            output.push(bytes.next().ok_or_else(invalid)?);
+            continue;
+        }
+
+        let (high, low) = (
+            bytes.next().ok_or_else(invalid)? as usize,
+            bytes.next().ok_or_else(invalid)? as usize,
+        );
+        let distance = ((high << 4) | (low >> 4)) + 1;
+        let len = (low & 0xf) + 1;
+
+        if len == 1 {
+            break;
+        }
+
+        if distance > output.len() {
+            return Err(invalid());
+        }
+
+        // The reference can overlap the bytes it writes
+        for _ in 0..len {
+            output.push(output[output.len() - distance]);
+        }
+    }
+
+    if output.len() != size {
+        return Err(invalid());
+    }
+
+    Ok(output)
+}
+
+/// Compress a lump with the Jaguar LZSS, readable by `decompress`
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    // Previous positions of each 3 bytes prefix
+    let mut chains: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    let mut flags_pos = 0;
+    let mut item = 0;
+    let mut i = 0;
+
+    // Start a new flags byte every 8 items
+    let mut next_item = |output: &mut Vec<u8>, reference: bool| {
+        if item % 8 == 0 {
+            flags_pos = output.len();
+            output.push(0);
+        }
+
+        if reference {
+            output[flags_pos] |= 1 << (item % 8);
+        }
+
+        item += 1;
+    };
+
+    while i < input.len() {
+        let mut best = (0, 0);
+
+        if i + MIN_MATCH <= input.len() {
+            let prefix = &input[i..i + MIN_MATCH];
+
+            for &start in chains
+                .get(prefix)
+                .into_iter()
+                .flatten()
+                .rev()
+                .take(MAX_CHAIN)
+            {
+                if i - start > WINDOW_SIZE {
+                    break;
+                }
+
+                let len = (0..MAX_MATCH.min(input.len() - i))
+                    .take_while(|&n| input[start + n] == input[i + n])
+                    .count();
+
+                if len > best.1 {
+                    best = (i - start, len);
+                }
+
+                if len == MAX_MATCH {
+                    break;
+                }
+            }
+        }
+
+        let step = match best {
+            (distance, len) if len >= MIN_MATCH => {
+                let value = ((distance - 1) << 4) | (len - 1);
+
+                next_item(&mut output, true);
+                output.extend([(value >> 8) as u8, value as u8]);
+                len
+            }
+            _ => {
+                next_item(&mut output, false);
+                output.push(input[i]);
+                1
+            }
+        };
+
+        for position in i..i + step {
+            if position + MIN_MATCH <= input.len() {
+                chains
+                    .entry(&input[position..position + MIN_MATCH])
+                    .or_default()
+                    .push(position);
+            }
+        }
+
+        i += step;
+    }
+
+    // End of data
+    next_item(&mut output, true);
+    output.extend([0, 0]);
+
+    output
+}
@@ -1,16 +1,56 @@
+/// Lumps addresses (`NAME#n`, `MAP01/THINGS`)
+pub mod address;
+/// Lumps classification
+pub mod classifier;
+/// Binary patches between WAD versions
+pub mod delta;
+/// DeuTex wadinfo.txt trees
+pub mod deutex;
+/// Differences between two WADs
+pub mod diff;
 /// Lumps directory
 pub mod dir;
 /// Error
 pub mod error;
+/// Build engine GRP archives
+pub mod grp;
+/// Undo/redo log of the WAD edits
+pub mod history;
+/// Jaguar compressed lumps
+pub mod jaguar;
 /// Managing lumps
 pub mod lump;
 /// The differents lump kinds
 pub mod lumps;
+/// Merge several WADs
+pub mod merge;
 /// Traits
 pub mod models;
+/// Namespaces delimited by markers
+pub mod namespace;
 /// Build a new WAD file from a source one
 pub mod output;
+/// Quake PAK archives
+pub mod pak;
+/// PK3 (zip) archives
+pub mod pk3;
 /// Some properties
 pub mod properties;
+/// Lumps queries
+pub mod query;
+/// Streaming WAD reader
+pub mod reader;
+/// Declarative WAD builds
+pub mod recipe;
+/// Caller lump decoders
+pub mod registry;
+/// IWAD and PWADs load order
+pub mod stack;
+/// Quake WAD2 and Half-Life WAD3 texture archives
+pub mod texwad;
+/// Extract to and rebuild from a directory tree
+pub mod tree;
 /// WAD
 pub mod wad;
+/// Streaming WAD writer
+pub mod writer;
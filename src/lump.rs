@@ -1,10 +1,33 @@
+use std::{fmt::Debug, ops::Deref, rc::Rc};
+
+use crate::{
+    classifier::Confidence, error::WadError, jaguar::COMPRESSED_FLAG, namespace::Namespace,
+};
+
 /// Lumps kind implementing the `Lump` trait
 #[derive(Clone, PartialEq, Copy, Debug)]
 pub enum LumpKind {
     Flat,
     Sound,
+    Music,
     Patch,
     Palette,
+    /// Namespace start or end marker
+    Marker,
+    /// Light levels tables
+    Colormap,
+    /// Textures definitions (`TEXTURE1`, `TEXTURE2`)
+    Texture,
+    /// Patches names used by the textures
+    PNames,
+    /// OPL instruments
+    Genmidi,
+    /// Text mode exit screen
+    Endoom,
+    /// Map marker or map data
+    Map,
+    /// PNG image
+    Png,
     /// Unidentified lump
     Unknown,
 }
@@ -32,6 +55,18 @@ impl LumpState {
     }
 }
 
+/// Stable reference to a lump, from `Wad::handle`
+///
+/// It stays valid while the lumps are edited, moved or renamed,
+/// until the lump is removed or the WAD reloaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LumpHandle {
+    /// Slot given to the lump, unique within a directory generation
+    pub(crate) slot: usize,
+    /// Directory generation, it changes on every (re)load
+    pub(crate) generation: u32,
+}
+
 /// Represents the lump official metadata (16 bytes)
 /// and the WAD operations state (from `WadOp`)
 #[derive(Clone, Copy, Debug)]
@@ -44,10 +79,17 @@ pub struct LumpInfo {
     pub name: [u8; 8],
     /// Lumps name are not unique but we want to extract them into files
     ///
-    /// The attribute acts like an unique ID
-    pub id: [u8; 12],
+    /// Amount of lumps with the same name before it, the unique ID
+    /// is the name suffixed by this counter for the duplicates.
+    /// Prefer the addresses (`LumpAddress`) or the handles
+    pub n: usize,
     /// Lump state
     pub state: LumpState,
+    /// Compressed in the source WAD (Jaguar), the flag
+    /// is removed from `name` and the content is decompressed
+    pub compressed: bool,
+    /// Set once the lump belongs to a directory
+    pub handle: Option<LumpHandle>,
 }
 
 impl Default for LumpInfo {
@@ -56,28 +98,38 @@ impl Default for LumpInfo {
             pos: 0,
             size: 0,
             name: [0x00; 8],
-            id: [0x00; 12],
+            n: 0,
             state: LumpState::Default,
+            compressed: false,
+            handle: None,
         }
     }
 }
 
 impl LumpInfo {
     pub fn new(pos: i32, size: i32, name: [u8; 8]) -> Self {
-        let mut id = [0x00; 12];
-
-        for i in 0..name.len() {
-            id[i] = name[i];
-        }
-
         Self {
             pos,
             size,
             name,
-            id,
+            n: 0,
             state: LumpState::Default,
+            compressed: false,
+            handle: None,
         }
     }
+    /// Convert `name` into a lump name, failing if it is not
+    /// 1 to 8 uppercase letters, digits or `[]-_\` characters
+    pub fn valid_name(name: &str) -> Result<[u8; 8], WadError> {
+        let legal = |c: char| c.is_ascii_uppercase() || c.is_ascii_digit() || "[]-_\\".contains(c);
+
+        if name.is_empty() || name.len() > 8 || !name.chars().all(legal) {
+            return Err(WadError::InvalidLumpName);
+        }
+
+        Ok(LumpAdd::name_bytes(name))
+    }
+
     /// Filter `value` with ascii characters
     fn ascii(value: String) -> String {
         value
@@ -93,7 +145,10 @@ impl LumpInfo {
 
     /// Get the lump unique ID as String
     pub fn id(&self) -> String {
-        String::from_utf8(self.id.to_vec()).unwrap()
+        match self.n {
+            0 => self.name(),
+            n => format!("{}{}", self.name_ascii(), n),
+        }
     }
 
     /// Get the lump unique ID filtered by ascii characters only
@@ -118,27 +173,31 @@ impl LumpInfo {
 
 impl From<&[u8]> for LumpInfo {
     fn from(bytes: &[u8]) -> Self {
-        let name: [u8; 8] = bytes[8..16].try_into().unwrap_or_default();
-        let mut id = [0x00; 12];
+        let mut name: [u8; 8] = bytes[8..16].try_into().unwrap_or_default();
+        let compressed = name[0] & COMPRESSED_FLAG != 0;
 
-        for i in 0..name.len() {
-            id[i] = name[i];
-        }
+        name[0] &= !COMPRESSED_FLAG;
 
         Self {
             pos: i32::from_le_bytes(bytes[0..4].try_into().unwrap_or_default()),
             size: i32::from_le_bytes(bytes[4..8].try_into().unwrap_or_default()),
             name,
-            id,
+            n: 0,
             state: LumpState::Default,
+            compressed,
+            handle: None,
         }
     }
 }
 
 impl Into<Vec<u8>> for LumpInfo {
-    fn into(self) -> Vec<u8> {
+    fn into(mut self) -> Vec<u8> {
         let mut ret = Vec::new();
 
+        if self.compressed {
+            self.name[0] |= COMPRESSED_FLAG;
+        }
+
         ret.append(&mut i32::to_le_bytes(self.pos).to_vec());
         ret.append(&mut i32::to_le_bytes(self.size).to_vec());
         ret.append(&mut self.name.to_vec());
@@ -147,15 +206,96 @@ impl Into<Vec<u8>> for LumpInfo {
     }
 }
 
+/// The WAD bytes shared by the lumps
+///
+/// It can be an owned buffer or a memory mapped file
+pub type WadSource = Rc<dyn AsRef<[u8]>>;
+
+/// Raw lump content
+///
+/// A loaded lump only keeps a view into the shared WAD source,
+/// its bytes are copied only when the lump gets updated
+#[derive(Clone)]
+pub enum LumpBuffer {
+    /// Bytes owned by the lump (updated or added lumps)
+    Owned(Vec<u8>),
+    /// Range into the WAD source buffer
+    Shared {
+        /// WAD source
+        src: WadSource,
+        /// Start position in `src`
+        pos: usize,
+        /// Size in bytes
+        size: usize,
+    },
+}
+
+impl LumpBuffer {
+    /// Build a view into `src`, checking the range bounds
+    pub fn shared(src: WadSource, pos: usize, size: usize) -> Option<Self> {
+        if pos.checked_add(size)? > (*src).as_ref().len() {
+            return None;
+        }
+
+        Some(Self::Shared { src, pos, size })
+    }
+}
+
+impl Default for LumpBuffer {
+    fn default() -> Self {
+        Self::Owned(Vec::new())
+    }
+}
+
+impl Deref for LumpBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Owned(buffer) => buffer,
+            Self::Shared { src, pos, size } => &(**src).as_ref()[*pos..*pos + *size],
+        }
+    }
+}
+
+impl AsRef<[u8]> for LumpBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Debug for LumpBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Owned(buffer) => write!(f, "Owned({} bytes)", buffer.len()),
+            Self::Shared { pos, size, .. } => write!(f, "Shared({} bytes at {})", size, pos),
+        }
+    }
+}
+
+impl From<Vec<u8>> for LumpBuffer {
+    fn from(buffer: Vec<u8>) -> Self {
+        Self::Owned(buffer)
+    }
+}
+
+impl From<&[u8]> for LumpBuffer {
+    fn from(buffer: &[u8]) -> Self {
+        Self::Owned(buffer.to_vec())
+    }
+}
+
 /// Representing the whole lump buffer as a struct
 #[derive(Clone, Debug)]
 pub struct LumpData {
     /// The raw buffer
-    pub buffer: Vec<u8>,
+    pub buffer: LumpBuffer,
     /// The lump metadata (16 bytes header)
     pub metadata: LumpInfo,
     /// The lump kind
     pub kind: LumpKind,
+    /// How sure `self.kind` is
+    pub confidence: Confidence,
 }
 
 impl Default for LumpData {
@@ -164,13 +304,14 @@ impl Default for LumpData {
             buffer: Default::default(),
             metadata: Default::default(),
             kind: LumpKind::Unknown,
+            confidence: Confidence::Low,
         }
     }
 }
 
 impl Into<Vec<u8>> for LumpData {
     fn into(self) -> Vec<u8> {
-        let mut ret = self.buffer;
+        let mut ret = self.buffer.to_vec();
 
         ret.append(&mut self.metadata.pos.to_be_bytes().to_vec());
         ret.append(&mut self.metadata.size.to_be_bytes().to_vec());
@@ -181,23 +322,75 @@ impl Into<Vec<u8>> for LumpData {
 }
 
 /// Every kind for `LumpAdd`
-pub enum LumpAddKind {
-    /// After a lump (lump name)
-    After(&'static str),
-    /// Before a lump (lump name)
-    Before(&'static str),
+///
+/// `LumpPosition` is the owned version, `LumpAddKind::from(&position)`
+/// borrows it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LumpAddKind<'a> {
+    /// After a lump (address)
+    After(&'a str),
+    /// Before a lump (address)
+    Before(&'a str),
     /// It add the lump to the start
     ///
     /// *Not recommended for an IWAD*
     Front,
     /// Add the lump to the end
     Back,
+    /// At a directory index
+    Index(usize),
+    /// Add the lump at the end of a namespace,
+    /// the markers are created if the namespace is missing
+    Namespace(Namespace),
+}
+
+/// Owned version of `LumpAddKind`, used by the edits log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LumpPosition {
+    /// After a lump (address)
+    After(String),
+    /// Before a lump (address)
+    Before(String),
+    /// At the start
+    Front,
+    /// At the end
+    Back,
+    /// At a directory index
+    Index(usize),
+    /// At the end of a namespace
+    Namespace(Namespace),
+}
+
+impl From<LumpAddKind<'_>> for LumpPosition {
+    fn from(kind: LumpAddKind<'_>) -> Self {
+        match kind {
+            LumpAddKind::After(name) => Self::After(name.to_string()),
+            LumpAddKind::Before(name) => Self::Before(name.to_string()),
+            LumpAddKind::Front => Self::Front,
+            LumpAddKind::Back => Self::Back,
+            LumpAddKind::Index(index) => Self::Index(index),
+            LumpAddKind::Namespace(namespace) => Self::Namespace(namespace),
+        }
+    }
+}
+
+impl<'a> From<&'a LumpPosition> for LumpAddKind<'a> {
+    fn from(position: &'a LumpPosition) -> Self {
+        match position {
+            LumpPosition::After(name) => Self::After(name),
+            LumpPosition::Before(name) => Self::Before(name),
+            LumpPosition::Front => Self::Front,
+            LumpPosition::Back => Self::Back,
+            LumpPosition::Index(index) => Self::Index(*index),
+            LumpPosition::Namespace(namespace) => Self::Namespace(namespace.clone()),
+        }
+    }
 }
 
 /// Metadata for an adding lump operation
 pub struct LumpAdd<'a> {
     /// Kind of adding
-    pub kind: LumpAddKind,
+    pub kind: LumpAddKind<'a>,
     /// Lump raw buffer
     pub buffer: &'a Vec<u8>,
     /// Lump name
@@ -205,7 +398,16 @@ pub struct LumpAdd<'a> {
 }
 
 impl<'a> LumpAdd<'a> {
-    pub fn new(kind: LumpAddKind, buffer: &'a Vec<u8>, name: &str) -> Self {
+    pub fn new(kind: LumpAddKind<'a>, buffer: &'a Vec<u8>, name: &str) -> Self {
+        Self {
+            kind,
+            buffer,
+            name: Self::name_bytes(name),
+        }
+    }
+
+    /// Convert `name` into a lump name, truncated to 8 bytes
+    pub fn name_bytes(name: &str) -> [u8; 8] {
         let mut array = [0; 8];
         let mut i = 0;
         let bytes = name.as_bytes();
@@ -216,10 +418,6 @@ impl<'a> LumpAdd<'a> {
             i += 1;
         }
 
-        Self {
-            kind,
-            buffer,
-            name: array,
-        }
+        array
     }
 }
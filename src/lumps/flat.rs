@@ -3,7 +3,12 @@ use std::{
     path::Path,
 };
 
-use crate::{error::WadError, lump::LumpData, lumps::palette::Palettes, models::lump::Lump};
+use crate::{
+    error::WadError,
+    lump::{LumpData, LumpInfo},
+    lumps::palette::Palettes,
+    models::lump::Lump,
+};
 
 extern crate image;
 
@@ -17,8 +22,6 @@ pub const FLAT_SIZE: usize = FLAT_W * FLAT_H;
 /// Represents a Flat
 #[derive(Clone)]
 pub struct Flat {
-    /// Array used to store the DOOM image data before converting it into bitmap
-    pixels: Vec<u8>,
     /// Attached palettes
     palettes: Palettes,
     /// Lump data
@@ -27,11 +30,36 @@ pub struct Flat {
 
 impl Flat {
     pub fn new(palettes: Palettes, data: LumpData) -> Self {
-        Self {
-            pixels: Vec::new(),
-            palettes,
-            data,
+        Self { palettes, data }
+    }
+
+    /// Get the final image buffer, structured as a RGBA format
+    ///
+    /// The flat is a raw 64x64 array of palette indexes,
+    /// so it is converted only when requested
+    fn buffer(&self) -> Result<Vec<u8>, WadError> {
+        let buffer = &*self.data.buffer;
+        let palette = match self.palettes.palette() {
+            Some(value) => value,
+            None => return Err(WadError::Parse(String::from("Invalid palette"))),
+        };
+
+        if FLAT_SIZE > buffer.len() {
+            return Err(WadError::Parse(String::from("Invalid FLAT length")));
+        }
+
+        let mut pixels = Vec::with_capacity(FLAT_SIZE * 4);
+
+        for byte in buffer.iter().take(FLAT_SIZE) {
+            let (r, g, b, _) = palette[*byte as usize].into();
+
+            pixels.push(r);
+            pixels.push(g);
+            pixels.push(b);
+            pixels.push(255);
         }
+
+        Ok(pixels)
     }
 }
 
@@ -51,35 +79,21 @@ impl Display for Flat {
 
 impl Lump for Flat {
     fn parse(&mut self) -> Result<(), WadError> {
-        let buffer = &*self.data.buffer;
-        let palette = match self.palettes.palette() {
-            Some(value) => value,
-            None => return Err(WadError::Parse(String::from("Invalid palette"))),
-        };
-
-        if FLAT_SIZE > buffer.len() {
-            return Err(WadError::Parse(String::from("Invalid FLAT length")));
-        }
-
-        for i in 0..FLAT_SIZE {
-            let byte = buffer[i];
-            let (r, g, b, _) = palette[byte as usize].into();
-
-            self.pixels.push(r);
-            self.pixels.push(g);
-            self.pixels.push(b);
-            self.pixels.push(255);
-        }
+        self.buffer()?;
 
         Ok(())
     }
 
     fn save(&self, dir: &str) {
+        let pixels = match self.buffer() {
+            Ok(value) => value,
+            Err(_) => return,
+        };
         let path = format!("{}/{}.png", dir, self.data.metadata.name_ascii());
 
         image::save_buffer(
             Path::new(&path),
-            &self.pixels,
+            &pixels,
             FLAT_W as u32,
             FLAT_H as u32,
             image::ColorType::Rgba8,
@@ -91,6 +105,10 @@ impl Lump for Flat {
         self.data.clone()
     }
 
+    fn metadata(&self) -> LumpInfo {
+        self.data.metadata
+    }
+
     fn set_data(&mut self, data: LumpData) {
         self.data = data;
     }
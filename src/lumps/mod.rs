@@ -6,5 +6,11 @@ pub mod music;
 pub mod palette;
 /// Image
 pub mod patch;
+/// PNG image
+pub mod png;
+/// Sound
+pub mod sound;
+/// TEXTURE1/TEXTURE2 and PNAMES tables
+pub mod texture;
 /// Unknown lump
 pub mod unknown;
@@ -1,31 +1,67 @@
 use std::{
+    cell::OnceCell,
     fmt::{Display, Error},
     fs,
 };
 
-use crate::{error::WadError, lump::LumpData, models::lump::Lump};
+use crate::{
+    classifier::MIDI_MAGIC,
+    error::WadError,
+    lump::{LumpData, LumpInfo},
+    models::lump::Lump,
+};
 
-use super::{mid::Midi, mus::Mus};
+use super::{
+    mid::Midi,
+    mus::{Mus, MusHeader},
+};
 
 /// Represents a DOOM music
 #[derive(Clone)]
 pub struct DoomMusic {
-    /// MUS
-    mus: Mus,
+    /// MUS header, cheap enough to be read at load time
+    header: MusHeader,
     /// Lump data
     data: LumpData,
     /// MIDI
-    midi: Option<Midi>,
+    ///
+    /// Converted the first time it is requested
+    midi: OnceCell<Midi>,
 }
 
 impl DoomMusic {
     pub fn new(data: LumpData) -> Self {
         Self {
-            mus: Mus::new(),
+            header: MusHeader::from(&*data.buffer),
             data,
-            midi: None,
+            midi: OnceCell::new(),
         }
     }
+
+    /// Get the MUS content
+    pub fn mus(&self) -> Result<Mus, WadError> {
+        let buffer: &[u8] = &self.data.buffer;
+
+        buffer.try_into()
+    }
+
+    /// Get the music as MIDI, converting it if needed
+    pub fn midi(&self) -> Result<&Midi, WadError> {
+        if let Some(midi) = self.midi.get() {
+            return Ok(midi);
+        }
+
+        let buffer: &[u8] = &self.data.buffer;
+
+        // Some PWADs store the music as MIDI
+        let midi = if buffer.starts_with(&MIDI_MAGIC) {
+            Midi::from(buffer)
+        } else {
+            Midi::try_from(&self.mus()?)?
+        };
+
+        Ok(self.midi.get_or_init(|| midi))
+    }
 }
 
 impl Display for DoomMusic {
@@ -36,29 +72,25 @@ impl Display for DoomMusic {
             self.data.metadata.id_ascii(),
             self.data.metadata.size,
             self.data.metadata.pos,
-            self.mus.header().channels,
-            self.mus.header().sec_channels,
-            self.mus.header().instr_count
+            self.header.channels,
+            self.header.sec_channels,
+            self.header.instr_count
         )
     }
 }
 
 impl Lump for DoomMusic {
     fn parse(&mut self) -> Result<(), WadError> {
-        let buffer: &[u8] = &self.data.buffer;
-
-        self.mus = buffer.try_into()?;
-        self.midi = Some(Midi::try_from(&self.mus)?);
+        self.midi()?;
 
         Ok(())
     }
 
     fn save(&self, dir: &str) {
-        if self.midi.is_none() {
-            return;
-        }
-
-        let midi = self.midi.as_ref().unwrap();
+        let midi = match self.midi() {
+            Ok(value) => value,
+            Err(_) => return,
+        };
         let path = format!("{}/{}.mid", dir, self.data.metadata.id_ascii());
 
         fs::write(path, midi.buffer()).unwrap_or_default();
@@ -68,7 +100,13 @@ impl Lump for DoomMusic {
         self.data.clone()
     }
 
+    fn metadata(&self) -> LumpInfo {
+        self.data.metadata
+    }
+
     fn set_data(&mut self, data: LumpData) {
+        self.header = MusHeader::from(&*data.buffer);
+        self.midi = OnceCell::new();
         self.data = data;
     }
 
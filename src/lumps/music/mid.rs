@@ -58,6 +58,14 @@ impl Midi {
     }
 }
 
+impl From<&[u8]> for Midi {
+    fn from(buffer: &[u8]) -> Self {
+        Self {
+            buffer: buffer.to_vec(),
+        }
+    }
+}
+
 impl TryFrom<&Mus> for Midi {
     type Error = WadError;
 
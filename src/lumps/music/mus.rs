@@ -43,6 +43,10 @@ impl Default for MusHeader {
 
 impl From<&[u8]> for MusHeader {
     fn from(value: &[u8]) -> Self {
+        if value.len() < 16 {
+            return Self::default();
+        }
+
         Self {
             magic: value[0..4].try_into().unwrap_or_default(),
             song_len: u16::from_le_bytes(value[4..6].try_into().unwrap_or_default()),
@@ -1,9 +1,15 @@
 use std::{
     fmt::{Display, Error},
     path::Path,
+    rc::Rc,
 };
 
-use crate::{error::WadError, lump::LumpData, models::lump::Lump, properties::color::ColorRgb};
+use crate::{
+    error::WadError,
+    lump::{LumpAdd, LumpData, LumpInfo},
+    models::lump::Lump,
+    properties::color::ColorRgb,
+};
 
 extern crate image;
 
@@ -19,7 +25,9 @@ pub type Palette = Vec<ColorRgb>;
 #[derive(Clone)]
 pub struct Palettes {
     /// Data a.k.a the palettes (array of 768 bytes -> 256 * 3)
-    pub palettes: Vec<Palette>,
+    ///
+    /// Shared because every image lump holds a copy of `Palettes`
+    pub palettes: Rc<Vec<Palette>>,
     /// Get the `n` palette
     n: usize,
     /// Raw file buffer
@@ -29,7 +37,7 @@ pub struct Palettes {
 impl Default for Palettes {
     fn default() -> Self {
         Self {
-            palettes: Vec::new(),
+            palettes: Rc::new(Vec::new()),
             n: 0,
             data: LumpData::default(),
         }
@@ -43,7 +51,7 @@ impl Palettes {
 
     /// Get the palettes
     pub fn palettes(&self) -> Vec<Palette> {
-        self.palettes.clone()
+        self.palettes.to_vec()
     }
 
     /// Get a palette
@@ -51,6 +59,27 @@ impl Palettes {
         self.palettes.get(self.n).cloned()
     }
 
+    /// Get the index of the nearest color within the current palette
+    pub fn nearest(&self, (r, g, b): (u8, u8, u8)) -> Option<u8> {
+        let palette = self.palettes.get(self.n)?;
+        let distance = |color: &ColorRgb| {
+            let (cr, cg, cb, _) = (*color).into();
+            let (dr, dg, db) = (
+                cr as i32 - r as i32,
+                cg as i32 - g as i32,
+                cb as i32 - b as i32,
+            );
+
+            dr * dr + dg * dg + db * db
+        };
+
+        palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, color)| distance(color))
+            .map(|(i, _)| i as u8)
+    }
+
     /// Get a palette in a byte (u8) format
     pub fn palette_as_bytes(&self, n: usize) -> Vec<u8> {
         let palette = self.palettes.get(n).cloned();
@@ -73,6 +102,22 @@ impl Palettes {
         ret
     }
 
+    /// Parse the palettes from a raw buffer, like a PLAYPAL
+    /// or a Quake `palette.lmp`
+    pub fn from_bytes(buffer: &[u8]) -> Result<Self, WadError> {
+        let size = (buffer.len() / PALETTE_SIZE * PALETTE_SIZE) as i32;
+        let mut ret = Self::new();
+
+        ret.set_data(LumpData {
+            buffer: buffer.into(),
+            metadata: LumpInfo::new(0, size, LumpAdd::name_bytes("PLAYPAL")),
+            ..Default::default()
+        });
+        ret.parse()?;
+
+        Ok(ret)
+    }
+
     /// Set the lump data
     pub fn set_data(&mut self, data: LumpData) {
         self.data = data;
@@ -102,8 +147,7 @@ impl Display for Palettes {
 impl Lump for Palettes {
     fn parse(&mut self) -> Result<(), WadError> {
         let buffer = &*self.data.buffer;
-        // Reset the vector if the method is called multiple time by mistake
-        self.palettes.clear();
+        let mut palettes = Vec::new();
 
         for i in (0..self.data.metadata.size as usize).step_by(PALETTE_SIZE) {
             let mut palette = Vec::new();
@@ -115,9 +159,11 @@ impl Lump for Palettes {
                 palette.push(ColorRgb::from((bytes[0], bytes[1], bytes[2])));
             }
 
-            self.palettes.push(palette);
+            palettes.push(palette);
         }
 
+        self.palettes = Rc::new(palettes);
+
         Ok(())
     }
 
@@ -142,6 +188,10 @@ impl Lump for Palettes {
         self.data.clone()
     }
 
+    fn metadata(&self) -> crate::lump::LumpInfo {
+        self.data.metadata
+    }
+
     fn set_data(&mut self, data: LumpData) {
         self.data = data;
     }
@@ -1,11 +1,17 @@
 use std::{
+    cell::OnceCell,
     fmt::{Display, Error},
     mem::size_of,
     ops::Mul,
     path::Path,
 };
 
-use crate::{error::WadError, lump::LumpData, lumps::palette::Palettes, models::lump::Lump};
+use crate::{
+    error::WadError,
+    lump::{LumpData, LumpInfo},
+    lumps::palette::Palettes,
+    models::lump::Lump,
+};
 
 extern crate image;
 
@@ -35,6 +41,10 @@ impl Default for DoomImageInfo {
 
 impl From<&[u8]> for DoomImageInfo {
     fn from(bytes: &[u8]) -> Self {
+        if bytes.len() < size_of::<Self>() {
+            return Self::default();
+        }
+
         Self {
             width: u16::from_le_bytes(bytes[0..2].try_into().unwrap_or_default()),
             height: u16::from_le_bytes(bytes[2..4].try_into().unwrap_or_default()),
@@ -50,7 +60,9 @@ pub struct DoomImage {
     /// Picture metadata
     pub img_info: DoomImageInfo,
     /// Array used to store the DOOM image data before converting it into bitmap
-    pixels: Vec<Option<u8>>,
+    ///
+    /// Decoded the first time it is requested
+    pixels: OnceCell<Vec<Option<u8>>>,
     /// Attached palettes
     palettes: Palettes,
     /// Lump data
@@ -60,22 +72,156 @@ pub struct DoomImage {
 impl DoomImage {
     pub fn new(palettes: Palettes, data: LumpData) -> Self {
         Self {
-            img_info: DoomImageInfo::default(),
-            pixels: Vec::new(),
+            img_info: DoomImageInfo::from(&*data.buffer),
+            pixels: OnceCell::new(),
             palettes,
             data,
         }
     }
 
+    /// Get the palette indexes of the picture, decoding it if needed
+    ///
+    /// `None` represents a transparent pixel
+    pub fn pixels(&self) -> Result<&Vec<Option<u8>>, WadError> {
+        if let Some(pixels) = self.pixels.get() {
+            return Ok(pixels);
+        }
+
+        let pixels = self.decode()?;
+
+        Ok(self.pixels.get_or_init(|| pixels))
+    }
+
+    /// Decode the DOOM picture columns
+    ///
+    /// Every offset, post and read is checked against the buffer
+    /// and the picture size
+    fn decode(&self) -> Result<Vec<Option<u8>>, WadError> {
+        let buffer = &*self.data.buffer;
+        let (width, height) = (self.img_info.width as usize, self.img_info.height as usize);
+        let byte = |pos: usize| buffer.get(pos).copied().ok_or(WadError::InvalidLump);
+
+        let img_size = width.mul(height);
+        let mut columns = Vec::with_capacity(width);
+
+        // Default background value is the last color in the palette
+        let mut pixels = vec![None; img_size];
+
+        // Filling columns
+        for i in 0..width {
+            let pos = (i * 4) + size_of::<DoomImageInfo>();
+            let value = match buffer.get(pos..pos + 4) {
+                Some(bytes) => u32::from_le_bytes(bytes.try_into().unwrap_or_default()) as usize,
+                None => return Err(WadError::InvalidLump),
+            };
+
+            if value >= buffer.len() {
+                return Err(WadError::InvalidLump);
+            }
+
+            columns.push(value);
+        }
+
+        for (i, column) in columns.into_iter().enumerate() {
+            let mut pos = column;
+
+            loop {
+                let row_start = byte(pos)? as usize;
+
+                if row_start == 0xff {
+                    break;
+                }
+
+                let pixel_count = byte(pos + 1)? as usize;
+
+                if row_start + pixel_count > height {
+                    return Err(WadError::InvalidLump);
+                }
+
+                // Row start, length then an unused byte
+                pos += 3;
+
+                for j in 0..pixel_count {
+                    pixels[(row_start + j) * width + i] = Some(byte(pos)?);
+                    pos += 1;
+                }
+
+                pos += 1;
+            }
+        }
+
+        Ok(pixels)
+    }
+
+    /// Encode palette indexes as a DOOM picture, `None` being transparent
+    ///
+    /// The pixels are stored row by row
+    pub fn encode(info: DoomImageInfo, pixels: &[Option<u8>]) -> Result<Vec<u8>, WadError> {
+        let (width, height) = (info.width as usize, info.height as usize);
+
+        // The row start of a post is stored on a single byte
+        if pixels.len() != width * height || height > 255 {
+            return Err(WadError::InvalidLump);
+        }
+
+        let mut ret = Vec::new();
+        let mut columns = Vec::with_capacity(width);
+
+        ret.extend_from_slice(&info.width.to_le_bytes());
+        ret.extend_from_slice(&info.height.to_le_bytes());
+        ret.extend_from_slice(&info.left.to_le_bytes());
+        ret.extend_from_slice(&info.top.to_le_bytes());
+        ret.resize(size_of::<DoomImageInfo>() + width * 4, 0);
+
+        for x in 0..width {
+            columns.push(ret.len() as u32);
+
+            let mut y = 0;
+
+            while y < height {
+                if pixels[y * width + x].is_none() {
+                    y += 1;
+                    continue;
+                }
+
+                let start = y;
+
+                while y < height && y - start < 128 && pixels[y * width + x].is_some() {
+                    y += 1;
+                }
+
+                // Row start, length, then the pixels between two unused bytes
+                ret.push(start as u8);
+                ret.push((y - start) as u8);
+                ret.push(0);
+                ret.extend((start..y).map(|row| pixels[row * width + x].unwrap_or_default()));
+                ret.push(0);
+            }
+
+            ret.push(0xff);
+        }
+
+        for (x, column) in columns.iter().enumerate() {
+            let pos = size_of::<DoomImageInfo>() + x * 4;
+
+            ret[pos..pos + 4].copy_from_slice(&column.to_le_bytes());
+        }
+
+        Ok(ret)
+    }
+
     /// Get the final image buffer, structured as a RGBA format
-    fn buffer(&self) -> Vec<u8> {
+    fn buffer(&self) -> Result<Vec<u8>, WadError> {
         let mut buffer: Vec<u8> = Vec::new();
 
-        let palette = self.palettes.palette().unwrap();
+        let palette = match self.palettes.palette() {
+            Some(value) => value,
+            None => return Err(WadError::Parse(String::from("Invalid palette"))),
+        };
 
         let (mut r, mut g, mut b, mut a): (u8, u8, u8, u8);
 
-        for byte in self.pixels.iter() {
+        for byte in self.pixels()?.iter() {
             if byte.is_none() {
                 (r, g, b, a) = (0, 0, 0, 0);
             } else {
@@ -88,7 +234,7 @@ impl DoomImage {
             buffer.push(a);
         }
 
-        buffer
+        Ok(buffer)
     }
 }
 
@@ -108,69 +254,21 @@ impl Display for DoomImage {
 
 impl Lump for DoomImage {
     fn parse(&mut self) -> Result<(), WadError> {
-        let buffer = &*self.data.buffer;
-        self.img_info = DoomImageInfo::from(buffer);
-
-        let img_size = (self.img_info.width as usize).mul(self.img_info.height as usize);
-        let mut columns = Vec::new();
-
-        // Default background value is the last color in the palette
-        self.pixels = vec![None; img_size];
-
-        // Filling columns
-        for i in 0..self.img_info.width as usize {
-            let pos = (i * 4) + size_of::<DoomImageInfo>();
-
-            if pos + 4 >= buffer.len() {
-                return Err(WadError::InvalidLump);
-            }
-
-            let value =
-                i32::from_le_bytes(buffer[pos..pos + 4].try_into().unwrap_or_default()) as usize;
-
-            columns.push(value);
-        }
-
-        #[allow(unused)]
-        let mut pos = 0;
-        #[allow(unused)]
-        let mut pixel_count = 0;
-
-        for i in 0..self.img_info.width as usize {
-            pos = columns[i];
-
-            let mut row_start = 0;
-
-            while row_start != 0xff {
-                row_start = buffer[pos];
-                pos += 1;
-
-                if row_start == 0xff {
-                    break;
-                }
-
-                pixel_count = buffer[pos];
-                pos += 2;
-
-                for j in 0..pixel_count as usize {
-                    let index = (((row_start as usize) + j) * self.img_info.width as usize) + i;
-                    self.pixels[index] = Some(buffer[pos]);
-                    pos += 1;
-                }
-
-                pos += 1;
-            }
-        }
+        self.pixels()?;
 
         Ok(())
     }
 
     fn save(&self, dir: &str) {
+        let buffer = match self.buffer() {
+            Ok(value) => value,
+            Err(_) => return,
+        };
         let path = format!("{}/{}.png", dir, self.data.metadata.name_ascii());
 
         image::save_buffer(
             Path::new(&path),
-            &self.buffer(),
+            &buffer,
             self.img_info.width as u32,
             self.img_info.height as u32,
             image::ColorType::Rgba8,
@@ -182,7 +280,13 @@ impl Lump for DoomImage {
         self.data.clone()
     }
 
+    fn metadata(&self) -> LumpInfo {
+        self.data.metadata
+    }
+
     fn set_data(&mut self, data: LumpData) {
+        self.img_info = DoomImageInfo::from(&*data.buffer);
+        self.pixels = OnceCell::new();
         self.data = data;
     }
 
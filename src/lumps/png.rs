@@ -0,0 +1,90 @@
+use std::{
+    fmt::{Display, Error},
+    fs,
+};
+
+use crate::{
+    classifier::PNG_MAGIC,
+    error::WadError,
+    lump::{LumpData, LumpInfo},
+    models::lump::Lump,
+};
+
+/// Represents a PNG image stored as is in the WAD (ZDoom)
+#[derive(Clone)]
+pub struct Png {
+    /// Lump data
+    data: LumpData,
+}
+
+impl Png {
+    pub fn new(data: LumpData) -> Self {
+        Self { data }
+    }
+
+    /// Get the image dimensions from the `IHDR` chunk
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        let buffer = &*self.data.buffer;
+
+        if buffer.len() < 24 || !buffer.starts_with(&PNG_MAGIC) {
+            return None;
+        }
+
+        let width = u32::from_be_bytes(buffer[16..20].try_into().ok()?);
+        let height = u32::from_be_bytes(buffer[20..24].try_into().ok()?);
+
+        Some((width, height))
+    }
+}
+
+impl Display for Png {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), Error> {
+        let (width, height) = self.dimensions().unwrap_or_default();
+
+        write!(
+            f,
+            "Name: {}, Size: {}, Offset: {}, Width: {}, Height: {}",
+            self.data.metadata.id_ascii(),
+            self.data.metadata.size,
+            self.data.metadata.pos,
+            width,
+            height
+        )
+    }
+}
+
+impl Lump for Png {
+    fn parse(&mut self) -> Result<(), WadError> {
+        match self.dimensions() {
+            Some(_) => Ok(()),
+            None => Err(WadError::InvalidLump),
+        }
+    }
+
+    fn save(&self, dir: &str) {
+        let path = format!("{}/{}.png", dir, self.data.metadata.id_ascii());
+
+        fs::write(path, &*self.data.buffer).unwrap_or_default();
+    }
+
+    fn data(&self) -> LumpData {
+        self.data.clone()
+    }
+
+    fn metadata(&self) -> LumpInfo {
+        self.data.metadata
+    }
+
+    fn set_data(&mut self, data: LumpData) {
+        self.data = data;
+    }
+
+    fn update(&mut self, buffer: &Vec<u8>) {
+        if !buffer.starts_with(&PNG_MAGIC) {
+            return;
+        }
+
+        self.data.buffer = buffer.to_vec().into();
+        self.data.metadata.size = buffer.len() as i32;
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    fmt::{Display, Error},
+    fs,
+};
+
+use crate::{
+    classifier::{is_dmx_sound, DMX_FORMAT},
+    error::WadError,
+    lump::{LumpData, LumpInfo},
+    models::lump::Lump,
+};
+
+/// DMX sound header size in bytes
+pub const DMX_HEADER_SIZE: usize = 8;
+
+/// DMX sound informations
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DoomSoundInfo {
+    /// Sound format, 3 for the digital sounds
+    pub format: u16,
+    /// Sample rate
+    pub rate: u16,
+    /// Samples amount, padding included
+    pub samples: u32,
+}
+
+impl From<&[u8]> for DoomSoundInfo {
+    fn from(bytes: &[u8]) -> Self {
+        if bytes.len() < DMX_HEADER_SIZE {
+            return Self::default();
+        }
+
+        Self {
+            format: u16::from_le_bytes([bytes[0], bytes[1]]),
+            rate: u16::from_le_bytes([bytes[2], bytes[3]]),
+            samples: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+}
+
+/// Represents a DMX digital sound (8 bits unsigned, mono)
+#[derive(Clone)]
+pub struct DoomSound {
+    /// Sound header
+    pub info: DoomSoundInfo,
+    /// Lump data
+    data: LumpData,
+}
+
+impl DoomSound {
+    pub fn new(data: LumpData) -> Self {
+        Self {
+            info: DoomSoundInfo::from(&*data.buffer),
+            data,
+        }
+    }
+
+    /// Get the 8 bits unsigned samples
+    pub fn samples(&self) -> Result<&[u8], WadError> {
+        if !is_dmx_sound(&self.data.buffer) {
+            return Err(WadError::InvalidLump);
+        }
+
+        let end = DMX_HEADER_SIZE + self.info.samples as usize;
+
+        Ok(&self.data.buffer[DMX_HEADER_SIZE..end])
+    }
+
+    /// Get the sound as a WAV file
+    pub fn wav(&self) -> Result<Vec<u8>, WadError> {
+        Ok(wav_from_samples(self.info.rate as u32, self.samples()?))
+    }
+}
+
+impl Display for DoomSound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), Error> {
+        write!(
+            f,
+            "Name: {}, Size: {}, Offset: {}, Rate: {}, Samples: {}",
+            self.data.metadata.id_ascii(),
+            self.data.metadata.size,
+            self.data.metadata.pos,
+            self.info.rate,
+            self.info.samples
+        )
+    }
+}
+
+impl Lump for DoomSound {
+    fn parse(&mut self) -> Result<(), WadError> {
+        self.samples()?;
+
+        Ok(())
+    }
+
+    fn save(&self, dir: &str) {
+        let wav = match self.wav() {
+            Ok(value) => value,
+            Err(_) => return,
+        };
+        let path = format!("{}/{}.wav", dir, self.data.metadata.id_ascii());
+
+        fs::write(path, wav).unwrap_or_default();
+    }
+
+    fn data(&self) -> LumpData {
+        self.data.clone()
+    }
+
+    fn metadata(&self) -> LumpInfo {
+        self.data.metadata
+    }
+
+    fn set_data(&mut self, data: LumpData) {
+        self.info = DoomSoundInfo::from(&*data.buffer);
+        self.data = data;
+    }
+
+    fn update(&mut self, buffer: &Vec<u8>) {
+        let dmx = match dmx_from_wav(buffer) {
+            Ok(value) => value,
+            Err(_) => return,
+        };
+        let mut data = self.data.clone();
+
+        data.metadata.size = dmx.len() as i32;
+        data.buffer = dmx.into();
+
+        self.set_data(data);
+    }
+}
+
+/// Build a WAV file (8 bits unsigned PCM, mono)
+pub fn wav_from_samples(rate: u32, samples: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(44 + samples.len());
+
+    ret.extend_from_slice(b"RIFF");
+    ret.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    ret.extend_from_slice(b"WAVEfmt ");
+    ret.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, mono
+    ret.extend_from_slice(&1u16.to_le_bytes());
+    ret.extend_from_slice(&1u16.to_le_bytes());
+    ret.extend_from_slice(&rate.to_le_bytes());
+    // Byte rate, block align, bits per sample
+    ret.extend_from_slice(&rate.to_le_bytes());
+    ret.extend_from_slice(&1u16.to_le_bytes());
+    ret.extend_from_slice(&8u16.to_le_bytes());
+    ret.extend_from_slice(b"data");
+    ret.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    ret.extend_from_slice(samples);
+
+    ret
+}
+
+/// Build a DMX sound from a WAV file (8 bits unsigned PCM, mono)
+pub fn dmx_from_wav(wav: &[u8]) -> Result<Vec<u8>, WadError> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err(WadError::Parse(String::from("Invalid WAV file")));
+    }
+
+    let mut pos = 12;
+    let mut rate = None;
+    let mut samples = None;
+
+    // Walk the RIFF chunks
+    while pos + 8 <= wav.len() {
+        let id = &wav[pos..pos + 4];
+        let size = u32::from_le_bytes(wav[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let body = &wav[pos + 8..wav.len().min(pos + 8 + size)];
+
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let format = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+
+                if format != 1 || channels != 1 || bits != 8 {
+                    return Err(WadError::Parse(String::from(
+                        "Only the 8 bits mono PCM WAV files are supported",
+                    )));
+                }
+
+                rate = Some(u32::from_le_bytes(body[4..8].try_into().unwrap()));
+            }
+            b"data" => samples = Some(body),
+            _ => {}
+        }
+
+        // Chunks are word aligned
+        pos += 8 + size + (size & 1);
+    }
+
+    let (rate, samples) = match (rate, samples) {
+        (Some(rate), Some(samples)) => (rate, samples),
+        _ => return Err(WadError::Parse(String::from("Invalid WAV file"))),
+    };
+    let rate = u16::try_from(rate).map_err(|_| WadError::Parse(String::from("Invalid rate")))?;
+
+    let mut ret = Vec::with_capacity(DMX_HEADER_SIZE + samples.len());
+
+    ret.extend_from_slice(&DMX_FORMAT.to_le_bytes());
+    ret.extend_from_slice(&rate.to_le_bytes());
+    ret.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    ret.extend_from_slice(samples);
+
+    Ok(ret)
+}
@@ -0,0 +1,170 @@
+use crate::{error::WadError, lump::LumpAdd};
+
+/// Texture header size in bytes, without the patches
+pub const TEXTURE_SIZE: usize = 22;
+/// Texture patch size in bytes
+pub const TEXTURE_PATCH_SIZE: usize = 10;
+
+/// Patch placed on a texture
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TexturePatch {
+    /// Horizontal offset
+    pub origin_x: i16,
+    /// Vertical offset
+    pub origin_y: i16,
+    /// Index within PNAMES
+    pub patch: i16,
+    /// Unused
+    pub step_dir: i16,
+    /// Unused
+    pub colormap: i16,
+}
+
+/// A TEXTURE1/TEXTURE2 entry (DOOM format)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextureDef {
+    /// Texture name
+    pub name: String,
+    /// Masked flag
+    pub masked: i32,
+    /// Width
+    pub width: i16,
+    /// Height
+    pub height: i16,
+    /// Unused
+    pub column_dir: i32,
+    /// Patches composing the texture
+    pub patches: Vec<TexturePatch>,
+}
+
+/// Read an i16 at `pos`
+fn read_i16(bytes: &[u8], pos: usize) -> Result<i16, WadError> {
+    match bytes.get(pos..pos + 2) {
+        Some(value) => Ok(i16::from_le_bytes([value[0], value[1]])),
+        None => Err(WadError::Parse(String::from("Truncated texture lump"))),
+    }
+}
+
+/// Read an i32 at `pos`
+fn read_i32(bytes: &[u8], pos: usize) -> Result<i32, WadError> {
+    match bytes.get(pos..pos + 4) {
+        Some(value) => Ok(i32::from_le_bytes(value.try_into().unwrap())),
+        None => Err(WadError::Parse(String::from("Truncated texture lump"))),
+    }
+}
+
+/// Read a name of 8 bytes at `pos`, stopped at the first null byte
+fn read_name(bytes: &[u8], pos: usize) -> Result<String, WadError> {
+    let name = match bytes.get(pos..pos + 8) {
+        Some(value) => value,
+        None => return Err(WadError::Parse(String::from("Truncated texture lump"))),
+    };
+    let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+
+    Ok(String::from_utf8_lossy(&name[..end]).to_ascii_uppercase())
+}
+
+/// Parse a PNAMES lump
+pub fn parse_pnames(bytes: &[u8]) -> Result<Vec<String>, WadError> {
+    let amount = read_i32(bytes, 0)?.max(0) as usize;
+
+    (0..amount).map(|i| read_name(bytes, 4 + i * 8)).collect()
+}
+
+/// Build a PNAMES lump
+pub fn build_pnames(names: &[String]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(4 + names.len() * 8);
+
+    ret.extend_from_slice(&(names.len() as i32).to_le_bytes());
+
+    for name in names {
+        ret.extend_from_slice(&LumpAdd::name_bytes(name));
+    }
+
+    ret
+}
+
+/// Parse a TEXTURE1/TEXTURE2 lump
+pub fn parse_textures(bytes: &[u8]) -> Result<Vec<TextureDef>, WadError> {
+    let amount = read_i32(bytes, 0)?.max(0) as usize;
+    let truncated = || WadError::Parse(String::from("Truncated texture lump"));
+
+    // The counts are checked against the lump before allocating
+    if amount > (bytes.len() - 4) / 4 {
+        return Err(truncated());
+    }
+
+    let mut ret = Vec::with_capacity(amount);
+
+    for i in 0..amount {
+        let pos = read_i32(bytes, 4 + i * 4)?.max(0) as usize;
+        let count = read_i16(bytes, pos + 20)?.max(0) as usize;
+
+        if pos + TEXTURE_SIZE + count * TEXTURE_PATCH_SIZE > bytes.len() {
+            return Err(truncated());
+        }
+
+        let mut patches = Vec::with_capacity(count);
+
+        for j in 0..count {
+            let patch_pos = pos + TEXTURE_SIZE + j * TEXTURE_PATCH_SIZE;
+
+            patches.push(TexturePatch {
+                origin_x: read_i16(bytes, patch_pos)?,
+                origin_y: read_i16(bytes, patch_pos + 2)?,
+                patch: read_i16(bytes, patch_pos + 4)?,
+                step_dir: read_i16(bytes, patch_pos + 6)?,
+                colormap: read_i16(bytes, patch_pos + 8)?,
+            });
+        }
+
+        ret.push(TextureDef {
+            name: read_name(bytes, pos)?,
+            masked: read_i32(bytes, pos + 8)?,
+            width: read_i16(bytes, pos + 12)?,
+            height: read_i16(bytes, pos + 14)?,
+            column_dir: read_i32(bytes, pos + 16)?,
+            patches,
+        });
+    }
+
+    Ok(ret)
+}
+
+/// Build a TEXTURE1/TEXTURE2 lump
+pub fn build_textures(textures: &[TextureDef]) -> Vec<u8> {
+    let mut offsets = Vec::with_capacity(textures.len());
+    let mut data = Vec::new();
+    let header_size = 4 + textures.len() * 4;
+
+    for texture in textures {
+        offsets.push((header_size + data.len()) as i32);
+
+        data.extend_from_slice(&LumpAdd::name_bytes(&texture.name));
+        data.extend_from_slice(&texture.masked.to_le_bytes());
+        data.extend_from_slice(&texture.width.to_le_bytes());
+        data.extend_from_slice(&texture.height.to_le_bytes());
+        data.extend_from_slice(&texture.column_dir.to_le_bytes());
+        data.extend_from_slice(&(texture.patches.len() as i16).to_le_bytes());
+
+        for patch in &texture.patches {
+            data.extend_from_slice(&patch.origin_x.to_le_bytes());
+            data.extend_from_slice(&patch.origin_y.to_le_bytes());
+            data.extend_from_slice(&patch.patch.to_le_bytes());
+            data.extend_from_slice(&patch.step_dir.to_le_bytes());
+            data.extend_from_slice(&patch.colormap.to_le_bytes());
+        }
+    }
+
+    let mut ret = Vec::with_capacity(header_size + data.len());
+
+    ret.extend_from_slice(&(textures.len() as i32).to_le_bytes());
+
+    for offset in offsets {
+        ret.extend_from_slice(&offset.to_le_bytes());
+    }
+
+    ret.extend(data);
+
+    ret
+}
@@ -32,6 +32,10 @@ impl Lump for Unknown {
         self.data.clone()
     }
 
+    fn metadata(&self) -> crate::lump::LumpInfo {
+        self.data.metadata
+    }
+
     fn set_data(&mut self, data: LumpData) {
         self.data = data;
     }
@@ -0,0 +1,332 @@
+use std::{collections::HashMap, io::Cursor};
+
+use crate::{
+    error::WadError,
+    lump::{LumpAdd, LumpBuffer},
+    lumps::texture::{build_pnames, build_textures, parse_pnames, parse_textures, TextureDef},
+    namespace::{Marker, Namespace},
+    wad::{Wad, WadKind},
+    writer::WadWriter,
+};
+
+/// Texture tables merged instead of overwritten
+const TEXTURE_TABLES: &[&str] = &["TEXTURE1", "TEXTURE2"];
+
+/// What to do when two WADs define the same lump
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// The last definition replaces the previous one, at its position
+    #[default]
+    LastWins,
+    /// The first definition is kept
+    FirstWins,
+    /// The merge fails
+    Error,
+    /// The new definition is renamed with a numeric suffix
+    ///
+    /// The maps and the sprites are not renamed, their name has
+    /// a meaning, so their conflicts are reported like `Error`
+    Rename,
+}
+
+/// Lumps list, the key of an item names its first lump
+struct Table<T> {
+    items: Vec<(String, T)>,
+    names: HashMap<String, usize>,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Table<T> {
+    /// Returns true if `name` is already used
+    fn contains(&self, name: &str) -> bool {
+        self.names.contains_key(name)
+    }
+
+    /// Insert `value` named `name`, following `policy` if the name is used
+    fn insert(&mut self, name: String, value: T, policy: ConflictPolicy) -> Result<(), WadError> {
+        let name = match (self.names.get(&name), policy) {
+            (None, _) => name,
+            (Some(i), ConflictPolicy::LastWins) => {
+                self.items[*i].1 = value;
+                return Ok(());
+            }
+            (Some(_), ConflictPolicy::FirstWins) => return Ok(()),
+            (Some(_), ConflictPolicy::Error) => return Err(WadError::Conflict(name)),
+            (Some(_), ConflictPolicy::Rename) => self.rename(&name)?,
+        };
+
+        self.names.insert(name.clone(), self.items.len());
+        self.items.push((name, value));
+
+        Ok(())
+    }
+
+    /// Find a free name for `name` by replacing its end with a number
+    fn rename(&self, name: &str) -> Result<String, WadError> {
+        for n in 1..10000 {
+            let suffix = n.to_string();
+            let keep = name.len().min(8 - suffix.len());
+            let candidate = format!("{}{}", &name[..keep], suffix);
+
+            if !self.contains(&candidate) {
+                return Ok(candidate);
+            }
+        }
+
+        Err(WadError::Conflict(name.to_string()))
+    }
+}
+
+/// Lumps written together, a single lump or a map with its lumps
+type Group = Vec<(String, LumpBuffer)>;
+
+/// Merge several WADs into a single one, like DeuTex or NWT
+///
+/// The namespaces are written under a single set of markers
+/// after the other lumps, the maps lumps stay together and
+/// the TEXTURE1/TEXTURE2/PNAMES tables are merged
+pub struct WadMerge<'a> {
+    /// Merged WADs, in order
+    wads: Vec<&'a Wad>,
+    /// Conflict policy
+    policy: ConflictPolicy,
+    /// Output WAD kind
+    kind: WadKind,
+}
+
+impl<'a> Default for WadMerge<'a> {
+    fn default() -> Self {
+        Self {
+            wads: Vec::new(),
+            policy: ConflictPolicy::default(),
+            kind: WadKind::Pwad,
+        }
+    }
+}
+
+impl<'a> WadMerge<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a WAD to merge, after the previous ones
+    pub fn wad(mut self, value: &'a Wad) -> Self {
+        self.wads.push(value);
+        self
+    }
+
+    /// Set the conflict policy
+    pub fn policy(mut self, value: ConflictPolicy) -> Self {
+        self.policy = value;
+        self
+    }
+
+    /// Set the output WAD kind, PWAD by default
+    pub fn kind(mut self, value: WadKind) -> Self {
+        self.kind = value;
+        self
+    }
+
+    /// Merge the WADs into a new one
+    pub fn merge(&self) -> Result<Wad, WadError> {
+        let mut wad = Wad::new();
+
+        wad.load(self.merge_raw()?)?;
+
+        Ok(wad)
+    }
+
+    /// Merge the WADs into a new WAD buffer
+    pub fn merge_raw(&self) -> Result<Vec<u8>, WadError> {
+        let mut globals = Table::<Group>::default();
+        let mut namespaces: Vec<(Namespace, Table<Group>)> = Vec::new();
+        let mut textures: HashMap<String, Table<TextureDef>> = HashMap::new();
+        let mut pnames = Table::<()>::default();
+        let mut last_pnames = Vec::new();
+
+        for wad in self.wads.iter() {
+            let groups = Self::groups(wad);
+
+            // The patches indexes refer to the WAD own PNAMES,
+            // or to the previous one if it has not any
+            if let Some((_, group)) = groups
+                .iter()
+                .rev()
+                .find(|(namespace, group)| namespace.is_none() && group[0].0 == "PNAMES")
+            {
+                last_pnames = parse_pnames(&group[0].1)?;
+
+                // Every patch name is kept, even the unreferenced ones
+                for patch_name in last_pnames.iter() {
+                    if !pnames.contains(patch_name) {
+                        pnames.insert(patch_name.clone(), (), self.policy)?;
+                    }
+                }
+            }
+
+            for (namespace, group) in groups {
+                let name = group[0].0.clone();
+
+                if let Some(namespace) = namespace {
+                    let position = match namespaces.iter().position(|(n, _)| *n == namespace) {
+                        Some(value) => value,
+                        None => {
+                            namespaces.push((namespace, Table::default()));
+                            namespaces.len() - 1
+                        }
+                    };
+
+                    let policy = self.policy_for(Some(&namespaces[position].0), &group);
+
+                    namespaces[position].1.insert(name, group, policy)?;
+                    continue;
+                }
+
+                let is_table = TEXTURE_TABLES.contains(&name.as_str());
+
+                if !is_table && name != "PNAMES" {
+                    let policy = self.policy_for(None, &group);
+
+                    globals.insert(name, group, policy)?;
+                    continue;
+                }
+
+                // Placeholder, the content is built at the end
+                if !globals.contains(&name) {
+                    globals.insert(name.clone(), Vec::new(), self.policy)?;
+                }
+
+                if !is_table {
+                    continue;
+                }
+
+                let table = textures.entry(name).or_default();
+
+                for mut texture in parse_textures(&group[0].1)? {
+                    for patch in texture.patches.iter_mut() {
+                        let patch_name = match last_pnames.get(patch.patch.max(0) as usize) {
+                            Some(value) => value.clone(),
+                            None => {
+                                return Err(WadError::Parse(String::from("Invalid patch index")))
+                            }
+                        };
+
+                        if !pnames.contains(&patch_name) {
+                            pnames.insert(patch_name.clone(), (), self.policy)?;
+                        }
+
+                        patch.patch = pnames.names[&patch_name] as i16;
+                    }
+
+                    table.insert(texture.name.clone(), texture, self.policy)?;
+                }
+            }
+        }
+
+        let mut writer = WadWriter::new(Cursor::new(Vec::new()), self.kind)?;
+
+        for (name, group) in globals.items.iter() {
+            let buffer = match name.as_str() {
+                "PNAMES" => {
+                    let names: Vec<String> = pnames.items.iter().map(|(n, _)| n.clone()).collect();
+
+                    build_pnames(&names)
+                }
+                _ if group.is_empty() => {
+                    let defs: Vec<TextureDef> = textures[name]
+                        .items
+                        .iter()
+                        .map(|(n, texture)| TextureDef {
+                            name: n.clone(),
+                            ..texture.clone()
+                        })
+                        .collect();
+
+                    build_textures(&defs)
+                }
+                _ => {
+                    Self::write_group(&mut writer, name, group)?;
+                    continue;
+                }
+            };
+
+            writer.write_lump(LumpAdd::name_bytes(name), &buffer)?;
+        }
+
+        for (namespace, table) in namespaces.iter() {
+            writer.write_marker(LumpAdd::name_bytes(&namespace.start_marker()));
+
+            for (name, group) in table.items.iter() {
+                Self::write_group(&mut writer, name, group)?;
+            }
+
+            writer.write_marker(LumpAdd::name_bytes(&namespace.end_marker()));
+        }
+
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// Conflict policy of `group`, the maps and the sprites are not renamed
+    fn policy_for(&self, namespace: Option<&Namespace>, group: &Group) -> ConflictPolicy {
+        // Only a map groups several lumps
+        let fixed = group.len() > 1 || namespace == Some(&Namespace::Sprites);
+
+        match self.policy {
+            ConflictPolicy::Rename if fixed => ConflictPolicy::Error,
+            value => value,
+        }
+    }
+
+    /// Write `group`, its first lump being named `name`
+    fn write_group(
+        writer: &mut WadWriter<Cursor<Vec<u8>>>,
+        name: &str,
+        group: &Group,
+    ) -> Result<(), WadError> {
+        for (i, (lump_name, buffer)) in group.iter().enumerate() {
+            let lump_name = if i == 0 { name } else { lump_name };
+
+            writer.write_lump(LumpAdd::name_bytes(lump_name), buffer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Split the WAD lumps into groups, without the markers
+    fn groups(wad: &Wad) -> Vec<(Option<Namespace>, Group)> {
+        let dir = wad.dir();
+        let namespaces = dir.namespaces();
+        let maps = dir.maps();
+        let mut ret: Vec<(Option<Namespace>, Group)> = Vec::new();
+
+        for (i, lump) in dir.lumps.iter().enumerate() {
+            let data = lump.data();
+            let name = data.metadata.name_ascii();
+
+            if !data.metadata.state.is_alive() || Marker::parse(&name).is_some() {
+                continue;
+            }
+
+            // The lumps following a map marker join its group
+            let in_map = match &maps[i] {
+                Some(map) => *map != name && namespaces[i].is_none(),
+                None => false,
+            };
+
+            match ret.last_mut() {
+                Some((None, group)) if in_map => group.push((name, data.buffer)),
+                _ => ret.push((namespaces[i].clone(), vec![(name, data.buffer)])),
+            }
+        }
+
+        ret
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{
+    error::WadError,
+    lump::LumpBuffer,
+    tree::{read_err, write_err},
+};
+
+/// A named entry of a simple archive (GRP, PAK)
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry {
+    /// Name or path
+    pub name: String,
+    /// Raw content
+    pub buffer: LumpBuffer,
+}
+
+/// Archive of named entries, like a WAD, a PK3, a GRP or a PAK
+///
+/// The entries are indexed following the archive order, so the
+/// select/extract/replace operations work whatever the format
+pub trait Container {
+    /// Parse the archive file content, replacing the entries
+    fn load_bytes(&mut self, buffer: Vec<u8>) -> Result<(), WadError>;
+    /// Number of entries
+    fn entries_len(&self) -> usize;
+    /// Name or path of the entry `index`
+    fn entry_name(&self, index: usize) -> Option<String>;
+    /// Raw content of the entry `index`
+    fn entry_buffer(&self, index: usize) -> Option<LumpBuffer>;
+    /// Replace the raw content of the entry `index`
+    fn set_entry_buffer(&mut self, index: usize, buffer: &[u8]) -> Result<(), WadError>;
+    /// Insert an entry at `index`, the name must fit the format
+    fn insert_entry(&mut self, index: usize, name: &str, buffer: &[u8]) -> Result<(), WadError>;
+    /// Remove the entry `index`
+    fn remove_entry(&mut self, index: usize) -> Result<(), WadError>;
+    /// Rename the entry `index`, the name must fit the format
+    fn rename_entry(&mut self, index: usize, name: &str) -> Result<(), WadError>;
+    /// Move the entry `from` so it ends at the index `to`
+    fn move_entry(&mut self, from: usize, to: usize) -> Result<(), WadError>;
+    /// Build the archive file content
+    fn to_bytes(&self) -> Result<Vec<u8>, WadError>;
+
+    /// Read then parse the archive file at `path`
+    fn load_archive<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        self.load_bytes(fs::read(path).map_err(read_err)?)
+    }
+
+    /// Append an entry
+    fn add_entry(&mut self, name: &str, buffer: &[u8]) -> Result<(), WadError> {
+        self.insert_entry(self.entries_len(), name, buffer)
+    }
+
+    /// Remove the entries `indexes`
+    fn remove_entries(&mut self, indexes: &[usize]) -> Result<(), WadError> {
+        let mut indexes = indexes.to_vec();
+
+        // The last ones first, so the other indexes stay valid
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        for index in indexes.into_iter().rev() {
+            self.remove_entry(index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the indexes of the entries whose name matches `re`
+    fn find(&self, re: &str) -> Result<Vec<usize>, WadError> {
+        let re = Regex::new(re).map_err(|_| WadError::InvalidRegex)?;
+
+        Ok((0..self.entries_len())
+            .filter(|index| {
+                self.entry_name(*index)
+                    .is_some_and(|name| re.is_match(&name))
+            })
+            .collect())
+    }
+
+    /// Extract the raw content of the entries `indexes` in `dir`
+    ///
+    /// The paths keep their folders, a duplicated name gets a `~n` suffix
+    fn extract_entries<P: AsRef<Path>>(&self, indexes: &[usize], dir: P) -> Result<(), WadError> {
+        let mut used = HashSet::new();
+
+        for index in indexes {
+            let (name, buffer) = match (self.entry_name(*index), self.entry_buffer(*index)) {
+                (Some(name), Some(buffer)) => (name, buffer),
+                _ => return Err(WadError::InvalidLump),
+            };
+            let mut path = entry_path(dir.as_ref(), &name);
+            let mut n = 1;
+
+            while !used.insert(path.clone()) {
+                path = entry_path(dir.as_ref(), &format!("{}~{}", name, n));
+                n += 1;
+            }
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(write_err)?;
+            }
+
+            fs::write(path, &*buffer).map_err(write_err)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the raw content of the entries `indexes` with `buffer`
+    fn replace_entries(&mut self, indexes: &[usize], buffer: &[u8]) -> Result<(), WadError> {
+        for index in indexes {
+            self.set_entry_buffer(*index, buffer)?;
+        }
+
+        Ok(())
+    }
+
+    /// `self.replace_entries` wrapper
+    fn replace_entries_from_file<P: AsRef<Path>>(
+        &mut self,
+        indexes: &[usize],
+        path: P,
+    ) -> Result<(), WadError> {
+        let buffer = fs::read(path).map_err(read_err)?;
+
+        self.replace_entries(indexes, &buffer)
+    }
+
+    /// Write the archive at `path`
+    fn save_archive<P: AsRef<Path>>(&self, path: P) -> Result<(), WadError> {
+        fs::write(path, self.to_bytes()?).map_err(write_err)
+    }
+}
+
+/// Move the item `from` of `items` so it ends at the index `to`
+pub(crate) fn move_item<T>(items: &mut Vec<T>, from: usize, to: usize) -> Result<(), WadError> {
+    if from >= items.len() || to >= items.len() {
+        return Err(WadError::InvalidLump);
+    }
+
+    let item = items.remove(from);
+
+    items.insert(to, item);
+
+    Ok(())
+}
+
+/// Path of an entry within `dir`, without the components leaving it
+fn entry_path(dir: &Path, name: &str) -> PathBuf {
+    let mut ret = dir.to_path_buf();
+
+    for component in Path::new(name).components() {
+        if let Component::Normal(value) = component {
+            ret.push(value);
+        }
+    }
+
+    ret
+}
@@ -1,6 +1,9 @@
 use std::fmt::Display;
 
-use crate::{error::WadError, lump::LumpData};
+use crate::{
+    error::WadError,
+    lump::{LumpData, LumpInfo},
+};
 
 pub trait Lump: Display {
     /// parse the lump as a file if parseable
@@ -9,6 +12,10 @@ pub trait Lump: Display {
     fn save(&self, dir: &str);
     /// Get the lump data
     fn data(&self) -> LumpData;
+    /// Get the lump metadata, without cloning the buffer
+    fn metadata(&self) -> LumpInfo {
+        self.data().metadata
+    }
     /// Set the lump data
     fn set_data(&mut self, data: LumpData);
     /// Update the lump buffer from a buffer based on its original format
@@ -1,3 +1,5 @@
+/// Archives of named entries (WAD, PK3, GRP, PAK)
+pub mod container;
 /// Lump features (dump, parse, etc..)
 pub mod lump;
 /// WAD operations, contains the tinywad features
@@ -1,35 +1,70 @@
-use regex::Error;
+use regex::{Error, Regex};
 use std::path::Path;
 
-use crate::{error::WadError, lump::LumpAdd, properties::file::PathWrap};
+use crate::{
+    error::WadError,
+    lump::{LumpAdd, LumpAddKind},
+    models::container::Container,
+    namespace::Namespace,
+    properties::file::PathWrap,
+};
 
 /// Operate on the matching lumps
-pub trait WadOp {
+///
+/// The operations on the raw entries are built on `Container`
+pub trait WadOp: Container {
+    /// Indexes of the entries matching the current selection
+    fn selected(&self) -> Vec<usize>;
     /// Dump
     fn dump(&self);
     /// Extract as file(s) in the directory `dir`
     fn save_lumps<P: AsRef<Path>>(&self, dir: P);
     /// Extract the raw content in the directory `dir`
-    fn save_lumps_raw<P: AsRef<Path>>(&self, dir: P);
+    fn save_lumps_raw<P: AsRef<Path>>(&self, dir: P) {
+        self.extract_entries(&self.selected(), dir)
+            .unwrap_or_default();
+    }
+    /// Unique ID of the entry `index`, matched by `self.remove_by_name`
+    ///
+    /// The entry name by default, a WAD suffixes the duplicated
+    /// names with a counter (`THINGS1`)
+    fn entry_id(&self, index: usize) -> Option<String> {
+        self.entry_name(index)
+    }
     /// Remove a lump
     ///
     /// Only affects `self.save`
-    fn remove(&mut self);
-    /// Remove lump(s) by its name
+    fn remove(&mut self) {
+        let indexes = self.selected();
+
+        self.remove_entries(&indexes).unwrap_or_default();
+    }
+    /// Remove lump(s) by its unique ID (`self.entry_id`)
     ///
     /// Only affects `self.save`
-    fn remove_by_name(&mut self, re: &str) -> Result<(), Error>;
+    fn remove_by_name(&mut self, re: &str) -> Result<(), Error> {
+        let re = Regex::new(re)?;
+        let indexes: Vec<usize> = (0..self.entries_len())
+            .filter(|index| self.entry_id(*index).is_some_and(|id| re.is_match(&id)))
+            .collect();
+
+        self.remove_entries(&indexes).unwrap_or_default();
+
+        Ok(())
+    }
     /// Build then output the WAD content as a new file
-    fn save<P: AsRef<Path>>(&mut self, path: P);
+    fn save<P: AsRef<Path>>(&mut self, path: P) {
+        self.save_archive(path).unwrap_or_default();
+    }
     /// Update a lump buffer
-    fn update_lumps_raw(&mut self, buffer: &Vec<u8>);
+    fn update_lumps_raw(&mut self, buffer: &Vec<u8>) {
+        let indexes = self.selected();
+
+        self.replace_entries(&indexes, buffer).unwrap_or_default();
+    }
     /// `self.update_lumps_raw` wrapper
-    fn update_lumps_raw_from_file<P: Into<PathWrap<&'static str>>>(
-        &mut self,
-        path: P,
-    ) -> Result<(), WadError> {
-        let path = path.into();
-        let buffer = path.try_into()?;
+    fn update_lumps_raw_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        let buffer = PathWrap::from(path).try_into()?;
 
         self.update_lumps_raw(&buffer);
 
@@ -41,12 +76,8 @@ pub trait WadOp {
     /// pass a `buffer` of an image (png, jpg, etc..) file
     fn update_lumps(&mut self, buffer: &Vec<u8>);
     /// `self.update_lumps` wrapper
-    fn update_lumps_from_file<P: Into<PathWrap<&'static str>>>(
-        &mut self,
-        path: P,
-    ) -> Result<(), WadError> {
-        let path = path.into();
-        let buffer = path.try_into()?;
+    fn update_lumps_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        let buffer = PathWrap::from(path).try_into()?;
 
         self.update_lumps(&buffer);
 
@@ -55,7 +86,17 @@ pub trait WadOp {
 
     /// Add lump from a raw buffer
     ///
-    /// We assume this method will be used only for build a new WAD
-    /// and not for dump or extract
+    /// The lump is classified from its name, its namespace and its content,
+    /// so a new WAD can be built from nothing
     fn add_lump_raw(&mut self, add: LumpAdd) -> Result<(), WadError>;
+    /// Rename the lump `name` (address)
+    ///
+    /// `value` must be 1 to 8 uppercase letters, digits or `[]-_\` characters
+    fn rename_lump(&mut self, name: &str, value: &str) -> Result<(), WadError>;
+    /// Move the lump `name` (address) to an index or relative to another lump
+    fn move_lump(&mut self, name: &str, kind: LumpAddKind<'_>) -> Result<(), WadError>;
+    /// Swap the lumps `a` and `b` (addresses)
+    fn swap_lumps(&mut self, a: &str, b: &str) -> Result<(), WadError>;
+    /// Sort the lumps of `namespace` by name, the markers stay in place
+    fn sort_namespace(&mut self, namespace: &Namespace);
 }
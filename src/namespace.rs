@@ -0,0 +1,229 @@
+use std::fmt::{Display, Error};
+
+use crate::error::WadError;
+
+/// Lumps group delimited by a `<prefix>_START` and a `<prefix>_END` marker
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    /// `S_START`, `SS_START`
+    Sprites,
+    /// `F_START`, `FF_START`
+    Flats,
+    /// `P_START`, `PP_START`
+    Patches,
+    /// `C_START`, Boom colormaps
+    Colormaps,
+    /// `TX_START`, ZDoom textures
+    Textures,
+    /// `HI_START`, ZDoom high resolution textures
+    HiRes,
+    /// `VX_START`, ZDoom voxels
+    Voxels,
+    /// `A_START`, ZDoom ACS libraries
+    Acs,
+    /// Any other `<prefix>_START`
+    Other(String),
+}
+
+/// Marker side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkerEdge {
+    Start,
+    End,
+}
+
+/// A namespace marker lump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    /// Delimited namespace
+    pub namespace: Namespace,
+    /// Start or end
+    pub edge: MarkerEdge,
+    /// Sub marker, like `F1_START` inside `F_START`
+    pub sub: bool,
+}
+
+impl Namespace {
+    /// Get the namespace from a marker prefix (`S`, `SS`, `F1`, `TX`, etc..)
+    ///
+    /// Returns the namespace and if the prefix is a sub marker
+    fn from_prefix(prefix: &str) -> Option<(Self, bool)> {
+        let letters = prefix.trim_end_matches(|c: char| c.is_ascii_digit());
+        let sub = letters.len() != prefix.len();
+
+        if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_uppercase()) {
+            return None;
+        }
+
+        let namespace = match letters {
+            "S" | "SS" => Self::Sprites,
+            "F" | "FF" => Self::Flats,
+            "P" | "PP" => Self::Patches,
+            "C" => Self::Colormaps,
+            "TX" => Self::Textures,
+            "HI" => Self::HiRes,
+            "VX" => Self::Voxels,
+            "A" => Self::Acs,
+            _ if sub => return None,
+            _ => Self::Other(letters.to_string()),
+        };
+
+        Some((namespace, sub))
+    }
+
+    /// Marker prefix written for a new namespace
+    pub fn prefix(&self) -> &str {
+        match self {
+            Self::Sprites => "S",
+            Self::Flats => "F",
+            Self::Patches => "P",
+            Self::Colormaps => "C",
+            Self::Textures => "TX",
+            Self::HiRes => "HI",
+            Self::Voxels => "VX",
+            Self::Acs => "A",
+            Self::Other(prefix) => prefix,
+        }
+    }
+
+    /// Start marker name written for a new namespace
+    pub fn start_marker(&self) -> String {
+        format!("{}_START", self.prefix())
+    }
+
+    /// End marker name written for a new namespace
+    pub fn end_marker(&self) -> String {
+        format!("{}_END", self.prefix())
+    }
+}
+
+impl Display for Namespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), Error> {
+        let name = match self {
+            Self::Sprites => "sprites",
+            Self::Flats => "flats",
+            Self::Patches => "patches",
+            Self::Colormaps => "colormaps",
+            Self::Textures => "textures",
+            Self::HiRes => "hires",
+            Self::Voxels => "voxels",
+            Self::Acs => "acs",
+            Self::Other(prefix) => prefix,
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl Marker {
+    /// Parse a lump name as a namespace marker
+    pub fn parse(name: &str) -> Option<Self> {
+        let (prefix, edge) = if let Some(prefix) = name.strip_suffix("_START") {
+            (prefix, MarkerEdge::Start)
+        } else if let Some(prefix) = name.strip_suffix("_END") {
+            (prefix, MarkerEdge::End)
+        } else {
+            return None;
+        };
+
+        let (namespace, sub) = Namespace::from_prefix(prefix)?;
+
+        Some(Self {
+            namespace,
+            edge,
+            sub,
+        })
+    }
+}
+
+/// Tracks the opened namespaces while walking a directory
+#[derive(Debug, Clone, Default)]
+pub struct NamespaceStack {
+    /// Opened start markers
+    stack: Vec<Marker>,
+}
+
+impl NamespaceStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the namespace of the next lumps
+    pub fn current(&self) -> Option<&Namespace> {
+        self.stack.last().map(|marker| &marker.namespace)
+    }
+
+    /// Returns true if no namespace is opened
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Reset the stack
+    pub fn clear(&mut self) {
+        self.stack.clear();
+    }
+
+    /// Update the stack with the lump `name`
+    ///
+    /// Returns the marker if `name` is one. The stack is always updated,
+    /// the error only reports a bad nesting (`S_START` inside `F_START`,
+    /// unbalanced end marker, etc..)
+    pub fn update(&mut self, name: &str) -> Result<Option<Marker>, WadError> {
+        let marker = match Marker::parse(name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        match marker.edge {
+            MarkerEdge::Start => {
+                let valid = match (self.stack.last(), marker.sub) {
+                    (None, _) => true,
+                    (Some(last), true) => last.namespace == marker.namespace,
+                    (Some(_), false) => false,
+                };
+
+                self.stack.push(marker.clone());
+
+                if !valid {
+                    return Err(WadError::InvalidNamespace(format!(
+                        "{} opened inside another namespace",
+                        name
+                    )));
+                }
+            }
+            MarkerEdge::End => {
+                let index = self
+                    .stack
+                    .iter()
+                    .rposition(|m| m.namespace == marker.namespace && m.sub == marker.sub)
+                    .or_else(|| {
+                        self.stack
+                            .iter()
+                            .rposition(|m| m.namespace == marker.namespace)
+                    });
+
+                let index = match index {
+                    Some(value) => value,
+                    None => {
+                        return Err(WadError::InvalidNamespace(format!(
+                            "{} without start marker",
+                            name
+                        )))
+                    }
+                };
+                let unclosed = index + 1 != self.stack.len();
+
+                self.stack.truncate(index);
+
+                if unclosed {
+                    return Err(WadError::InvalidNamespace(format!(
+                        "{} closes an unclosed namespace",
+                        name
+                    )));
+                }
+            }
+        }
+
+        Ok(Some(marker))
+    }
+}
@@ -1,27 +1,66 @@
-use std::collections::HashMap;
-
-use lazy_static::lazy_static;
-use regex::Regex;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io::{Cursor, Seek, Write},
+};
+
+use crate::{
+    dir::LumpsDirectory,
+    error::WadError,
+    lump::{LumpBuffer, LumpInfo, LumpState},
+    namespace::Marker,
+    wad::WadInfo,
+    writer::{DirPosition, WadWriter},
+};
+
+/// Options used to build the new WAD file
+#[derive(Debug, Clone, Copy)]
+pub struct OutputOptions {
+    /// Directory position
+    pub dir_position: DirPosition,
+    /// Lumps data alignment in bytes (1 means no alignment)
+    pub align: usize,
+    /// Store the lumps with an identical content only once
+    pub dedup: bool,
+    /// Compress again the lumps compressed in the source WAD (Jaguar)
+    pub compress: bool,
+}
 
-use crate::{dir::LumpsDirectory, lump::LumpState, wad::WadInfo};
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            dir_position: DirPosition::Start,
+            align: 1,
+            dedup: false,
+            compress: false,
+        }
+    }
+}
 
-lazy_static! {
-    /// str representing a regex matching a virtual lump (markers)
-    static ref VLUMP_RE: Regex = Regex::new(
-        r"^([FPS]{1,2}_(?:START|END))|([FPS]{1}_?\d{0,2}_(?:START|END))$"
-    ).unwrap();
+/// Summary of the last build
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputStats {
+    /// Directory entries written
+    pub lumps: usize,
+    /// Lumps data bytes written
+    pub data_size: u64,
+    /// Lumps pointing to the data of a previous lump
+    pub shared: usize,
+    /// Bytes not written thanks to the shared lumps
+    pub saved: u64,
+    /// Bytes written to align the lumps data
+    pub padding: u64,
 }
 
 /// Manage the build of a new WAD file
 pub struct WadOutput<'a> {
     /// The WAD controller
     info: WadInfo,
-    /// Lumps offsets
-    ///
-    /// Used because some lumps can have the same position (references)
-    /// or virtual lumps
-    offsets: HashMap<i32, i32>,
     dir: &'a LumpsDirectory,
+    /// Build options
+    options: OutputOptions,
+    /// Last build summary
+    stats: OutputStats,
     /// Destination buffer
     ///
     /// It represents the final raw WAD file
@@ -29,89 +68,159 @@ pub struct WadOutput<'a> {
 }
 
 impl<'a> WadOutput<'a> {
-    pub fn new(mut info: WadInfo, dir: &'a LumpsDirectory) -> WadOutput<'a> {
-        // Skip the WAD metadata size
-        info.dir_pos = 12;
-
+    pub fn new(info: WadInfo, dir: &'a LumpsDirectory) -> WadOutput<'a> {
         Self {
             info,
-            offsets: HashMap::new(),
             dir,
+            options: OutputOptions::default(),
+            stats: OutputStats::default(),
             dest: Vec::new(),
         }
     }
 
-    /// Computes every lump offset
-    ///
-    /// Used because more than one lump can have the same offset
-    fn build_offsets(&mut self) {
-        let mut offset = self.info.dir_pos + 16 * self.info.num_lumps;
+    /// Set the build options
+    pub fn set_options(&mut self, options: OutputOptions) {
+        self.options = options;
+    }
 
-        for lump in self.dir.lumps.iter() {
-            let metadata = lump.data().metadata;
+    /// Get the last build summary
+    pub fn stats(&self) -> OutputStats {
+        self.stats
+    }
 
-            if metadata.state == LumpState::Deleted {
-                continue;
+    /// Find a previously written lump with the same content
+    ///
+    /// `written` maps a content hash to the lumps index and entry,
+    /// the content is compared to avoid the hash collisions
+    fn find_duplicate(
+        &self,
+        written: &HashMap<u64, Vec<(usize, LumpInfo)>>,
+        hash: u64,
+        buffer: &[u8],
+    ) -> Option<LumpInfo> {
+        written.get(&hash)?.iter().find_map(|(index, metadata)| {
+            let other = self.dir.lumps[*index].data().buffer;
+
+            if *other == *buffer {
+                Some(*metadata)
+            } else {
+                None
             }
+        })
+    }
 
-            if self.offsets.get(&metadata.pos).is_none() {
-                self.offsets.insert(metadata.pos, offset);
+    /// Stream the new WAD file into `inner`
+    ///
+    /// The lumps still pointing to the same source data
+    /// (references) are written only once, like the identical
+    /// lumps if `self.options.dedup` is set
+    pub fn write<W: Write + Seek>(&mut self, inner: W) -> Result<W, WadError> {
+        let alive = self
+            .dir
+            .lumps
+            .iter()
+            .enumerate()
+            .filter(|(_, lump)| lump.metadata().state != LumpState::Deleted);
+
+        let mut writer = match self.options.dir_position {
+            DirPosition::Start => {
+                WadWriter::with_dir_start(inner, self.info.kind, alive.clone().count())?
             }
+            DirPosition::End => WadWriter::new(inner, self.info.kind)?,
+        };
 
-            offset += metadata.size;
-        }
-    }
+        writer.set_align(self.options.align);
 
-    /// Write the file entries into `self.dest`
-    fn build_file_entries(&mut self) {
-        for lump in self.dir.lumps.iter() {
-            let mut metadata = lump.data().metadata;
+        let mut stats = OutputStats::default();
+        // Source ranges already written
+        let mut offsets: HashMap<(usize, usize), LumpInfo> = HashMap::new();
+        // Content hashes already written
+        let mut written: HashMap<u64, Vec<(usize, LumpInfo)>> = HashMap::new();
 
-            if metadata.state == LumpState::Deleted {
+        for (index, lump) in alive {
+            let data = lump.data();
+            let name = data.metadata.name;
+
+            // Virtual lumps, a marker name holding data keeps it
+            if data.buffer.is_empty() && Marker::parse(&data.metadata.name_ascii()).is_some() {
+                writer.write_marker(name);
                 continue;
             }
 
-            let offset = self.offsets.get(&metadata.pos).unwrap();
-            if VLUMP_RE.is_match(&metadata.name_ascii()) {
-                metadata.pos = 0;
+            let range = match data.buffer {
+                LumpBuffer::Shared { pos, size, .. } if size > 0 => Some((pos, size)),
+                _ => None,
+            };
+            let hash = if self.options.dedup && !data.buffer.is_empty() {
+                let mut hasher = DefaultHasher::new();
+
+                data.buffer.hash(&mut hasher);
+                Some(hasher.finish())
             } else {
-                metadata.pos = *offset;
-            }
+                None
+            };
 
-            // Write the lump metadata
-            self.dest.append(&mut metadata.into());
-        }
-    }
+            let shared = range
+                .and_then(|range| offsets.get(&range).copied())
+                .or_else(|| self.find_duplicate(&written, hash?, &data.buffer));
 
-    /// Write the lumps content into `self.dest`
-    ///
-    /// Running 2 loops instead of one has better performance,
-    /// because one loop implies to use `self.dest.insert` and it copies
-    /// values instead of using references
-    fn build_raw_lumps(&mut self) {
-        for lump in self.dir.lumps.iter() {
-            if lump.data().metadata.state != LumpState::Deleted {
-                // Write the raw lump content
-                self.dest.append(&mut lump.data().buffer);
+            let metadata = match shared {
+                Some(metadata) => {
+                    let mut entry = LumpInfo::new(metadata.pos, metadata.size, name);
+
+                    entry.compressed = metadata.compressed;
+                    writer.write_entry(entry);
+
+                    stats.shared += 1;
+                    stats.saved += metadata.size as u64;
+
+                    metadata
+                }
+                None => {
+                    let metadata = match self.options.compress && data.metadata.compressed {
+                        true => writer.write_lump_compressed(name, &data.buffer)?,
+                        false => writer.write_lump(name, &data.buffer)?,
+                    };
+
+                    if let Some(hash) = hash {
+                        written.entry(hash).or_default().push((index, metadata));
+                    }
+
+                    stats.data_size += metadata.size as u64;
+
+                    metadata
+                }
+            };
+
+            if let Some(range) = range {
+                offsets.entry(range).or_insert(metadata);
             }
         }
+
+        stats.lumps = writer.entries().len();
+        stats.padding = writer.padding();
+        self.stats = stats;
+
+        writer.finish()
     }
 
     /// Build the new WAD file into `self.dest`
     ///
     /// It requires a source WAD abstraction
     /// aka `self.dir` and the metadatas aka `self.info`
-    pub fn build(&mut self) {
-        // Write the WAD metadata
-        self.dest.append(&mut self.info.into());
+    pub fn build(&mut self) -> Result<(), WadError> {
+        self.dest = self.write(Cursor::new(Vec::new()))?.into_inner();
 
-        self.build_offsets();
-        self.build_file_entries();
-        self.build_raw_lumps();
+        Ok(())
     }
 
     /// Returns the WAD output
-    pub fn buffer(&self) -> Vec<u8> {
-        self.dest.clone()
+    pub fn buffer(&self) -> &Vec<u8> {
+        &self.dest
+    }
+
+    /// Consume the output and returns the WAD buffer
+    pub fn into_buffer(self) -> Vec<u8> {
+        self.dest
     }
 }
@@ -0,0 +1,222 @@
+use std::{fs, path::Path, rc::Rc};
+
+use crate::{
+    error::WadError,
+    lump::{LumpBuffer, WadSource},
+    models::container::{move_item, ArchiveEntry, Container},
+    tree::{read_err, write_err},
+};
+
+/// PAK signature
+pub const MAGIC_PAK: &[u8] = b"PACK";
+/// Header size
+pub const PAK_HEADER_SIZE: usize = 12;
+/// Directory entry size
+pub const PAK_ENTRY_SIZE: usize = 64;
+/// Entry path size, null terminated
+pub const PAK_NAME_SIZE: usize = 56;
+
+/// Read a little endian `u32` at `pos`
+fn u32_at(bytes: &[u8], pos: usize) -> usize {
+    u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap_or_default()) as usize
+}
+
+/// Fail if `name` does not fit a PAK entry
+fn check_name(name: &str) -> Result<(), WadError> {
+    if name.is_empty() || name.len() >= PAK_NAME_SIZE || !name.is_ascii() {
+        return Err(WadError::InvalidLumpName);
+    }
+
+    Ok(())
+}
+
+/// Quake PAK archive
+///
+/// The entries are paths (`maps/e1m1.bsp`), the
+/// directory is written after their content
+#[derive(Default)]
+pub struct Pak {
+    /// Entries, following the directory order
+    entries: Vec<ArchiveEntry>,
+}
+
+impl Pak {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a PAK buffer
+    pub fn load<T: Into<Vec<u8>>>(&mut self, buffer: T) -> Result<(), WadError> {
+        let src: WadSource = Rc::new(buffer.into());
+        let bytes = (*src).as_ref();
+
+        if bytes.len() < PAK_HEADER_SIZE {
+            return Err(WadError::Load("The file size is too small."));
+        }
+
+        if &bytes[..4] != MAGIC_PAK {
+            return Err(WadError::Type("The file is not a PAK file."));
+        }
+
+        let dir_pos = u32_at(bytes, 4);
+        let dir = bytes
+            .get(dir_pos..dir_pos + u32_at(bytes, 8))
+            .ok_or(WadError::Parse(String::from("Invalid directory")))?;
+
+        self.entries.clear();
+
+        for entry in dir.chunks_exact(PAK_ENTRY_SIZE) {
+            let name = entry[..PAK_NAME_SIZE]
+                .iter()
+                .take_while(|b| **b != 0)
+                .map(|b| *b as char)
+                .collect();
+            let buffer = LumpBuffer::shared(src.clone(), u32_at(entry, 56), u32_at(entry, 60))
+                .ok_or(WadError::InvalidLump)?;
+
+            self.entries.push(ArchiveEntry { name, buffer });
+        }
+
+        Ok(())
+    }
+
+    /// Read then parse the file at `path`
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        self.load(fs::read(path).map_err(read_err)?)
+    }
+
+    /// Get the entries
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// Add an entry, replacing the one at the same path
+    pub fn add(&mut self, path: &str, buffer: &[u8]) -> Result<(), WadError> {
+        check_name(path)?;
+
+        let entry = ArchiveEntry {
+            name: path.to_string(),
+            buffer: buffer.into(),
+        };
+
+        match self.entries.iter().position(|e| e.name == path) {
+            Some(index) => self.entries[index] = entry,
+            None => self.entries.push(entry),
+        }
+
+        Ok(())
+    }
+
+    /// Remove an entry, returns true if it existed
+    pub fn remove(&mut self, path: &str) -> bool {
+        let len = self.entries.len();
+
+        self.entries.retain(|entry| entry.name != path);
+        self.entries.len() != len
+    }
+
+    /// Build the PAK buffer
+    pub fn build(&self) -> Result<Vec<u8>, WadError> {
+        let mut ret = MAGIC_PAK.to_vec();
+        let mut dir = Vec::with_capacity(self.entries.len() * PAK_ENTRY_SIZE);
+        let data_size: usize = self.entries.iter().map(|e| e.buffer.len()).sum();
+
+        ret.extend(((PAK_HEADER_SIZE + data_size) as u32).to_le_bytes());
+        ret.extend(((self.entries.len() * PAK_ENTRY_SIZE) as u32).to_le_bytes());
+
+        for entry in self.entries.iter() {
+            let mut name = [0; PAK_NAME_SIZE];
+
+            if entry.name.len() >= PAK_NAME_SIZE {
+                return Err(WadError::InvalidLumpName);
+            }
+
+            name[..entry.name.len()].copy_from_slice(entry.name.as_bytes());
+            dir.extend(name);
+            dir.extend((ret.len() as u32).to_le_bytes());
+            dir.extend((entry.buffer.len() as u32).to_le_bytes());
+            ret.extend(&*entry.buffer);
+        }
+
+        ret.extend(dir);
+
+        Ok(ret)
+    }
+
+    /// Write the PAK file at `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), WadError> {
+        fs::write(path, self.build()?).map_err(write_err)
+    }
+}
+
+impl Container for Pak {
+    fn load_bytes(&mut self, buffer: Vec<u8>) -> Result<(), WadError> {
+        self.load(buffer)
+    }
+
+    fn entries_len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn entry_name(&self, index: usize) -> Option<String> {
+        self.entries.get(index).map(|entry| entry.name.clone())
+    }
+
+    fn entry_buffer(&self, index: usize) -> Option<LumpBuffer> {
+        self.entries.get(index).map(|entry| entry.buffer.clone())
+    }
+
+    fn set_entry_buffer(&mut self, index: usize, buffer: &[u8]) -> Result<(), WadError> {
+        let entry = self.entries.get_mut(index).ok_or(WadError::InvalidLump)?;
+
+        entry.buffer = buffer.into();
+
+        Ok(())
+    }
+
+    fn insert_entry(&mut self, index: usize, name: &str, buffer: &[u8]) -> Result<(), WadError> {
+        check_name(name)?;
+
+        if index > self.entries.len() {
+            return Err(WadError::InvalidLump);
+        }
+
+        self.entries.insert(
+            index,
+            ArchiveEntry {
+                name: name.to_string(),
+                buffer: buffer.into(),
+            },
+        );
+
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, index: usize) -> Result<(), WadError> {
+        if index >= self.entries.len() {
+            return Err(WadError::InvalidLump);
+        }
+
+        self.entries.remove(index);
+
+        Ok(())
+    }
+
+    fn rename_entry(&mut self, index: usize, name: &str) -> Result<(), WadError> {
+        check_name(name)?;
+
+        let entry = self.entries.get_mut(index).ok_or(WadError::InvalidLump)?;
+
+        entry.name = name.to_string();
+
+        Ok(())
+    }
+
+    fn move_entry(&mut self, from: usize, to: usize) -> Result<(), WadError> {
+        move_item(&mut self.entries, from, to)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, WadError> {
+        self.build()
+    }
+}
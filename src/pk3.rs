@@ -0,0 +1,644 @@
+use std::{
+    fs,
+    io::{Cursor, Read, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{
+    classifier::{Classification, ClassifyInput, MIDI_MAGIC},
+    delta::crc32,
+    deutex::{file_name, lump_name},
+    dir::LumpsDirectory,
+    error::WadError,
+    lump::{LumpAdd, LumpBuffer, LumpData, LumpInfo, LumpKind, WadSource},
+    models::{
+        container::{move_item, Container},
+        lump::Lump,
+    },
+    namespace::{Marker, Namespace},
+    tree::{read_err, write_err},
+    wad::{Wad, WadKind},
+    writer::WadWriter,
+};
+
+/// Local file header signature
+const LOCAL_SIGNATURE: u32 = 0x04034b50;
+/// Central directory entry signature
+const CENTRAL_SIGNATURE: u32 = 0x02014b50;
+/// End of central directory signature
+const END_SIGNATURE: u32 = 0x06054b50;
+/// Local file header size
+const LOCAL_SIZE: usize = 30;
+/// Central directory entry size
+const CENTRAL_SIZE: usize = 46;
+/// End of central directory size
+const END_SIZE: usize = 22;
+/// Stored entry
+const METHOD_STORED: u16 = 0;
+/// Deflated entry
+const METHOD_DEFLATED: u16 = 8;
+/// Version needed to extract (2.0)
+const ZIP_VERSION: u16 = 20;
+/// UTF-8 names flag
+const FLAG_UTF8: u16 = 0x0800;
+/// MS-DOS date of 1980-01-01, written for every entry
+const DOS_DATE: u16 = 0x0021;
+/// Folder of the maps, each one being a single map WAD
+const MAPS_FOLDER: &str = "maps";
+/// Folder of the musics outside of any namespace
+const MUSIC_FOLDER: &str = "music";
+/// Folder of the sounds outside of any namespace
+const SOUNDS_FOLDER: &str = "sounds";
+/// Namespaces written as folders
+const FOLDER_NAMESPACES: [Namespace; 8] = [
+    Namespace::Sprites,
+    Namespace::Flats,
+    Namespace::Patches,
+    Namespace::Colormaps,
+    Namespace::Textures,
+    Namespace::HiRes,
+    Namespace::Voxels,
+    Namespace::Acs,
+];
+
+/// Options used to write a PK3 file
+#[derive(Debug, Clone, Copy)]
+pub struct Pk3Options {
+    /// Deflate level from 0 to 9, 0 means the entries are stored
+    pub level: u32,
+}
+
+impl Default for Pk3Options {
+    fn default() -> Self {
+        Self { level: 6 }
+    }
+}
+
+/// Read a little endian `u16` at `pos`
+fn u16_at(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+/// Read a little endian `u32` at `pos`
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+/// Namespace of the top folder of `path`
+fn path_namespace(path: &str) -> Option<Namespace> {
+    let (folder, _) = path.split_once('/')?;
+
+    FOLDER_NAMESPACES
+        .into_iter()
+        .find(|namespace| namespace.to_string() == folder.to_ascii_lowercase())
+}
+
+/// Lump name of `path`, its file name until the first dot
+fn path_name(path: &str) -> String {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    let stem = file.split('.').next().unwrap_or(file);
+
+    lump_name(stem)
+}
+
+/// Returns true if `path` is a map WAD
+fn is_map_path(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+
+    lower.starts_with("maps/") && lower.ends_with(".wad")
+}
+
+/// A PK3 (zip) archive, source ports resources
+///
+/// Every file is exposed as a lump named after it, the
+/// top folder giving its namespace (`sprites/`, `flats/`, etc..)
+pub struct Pk3 {
+    /// Entries paths, following `dir.lumps`
+    paths: Vec<String>,
+    /// Entries lumps
+    dir: LumpsDirectory,
+}
+
+impl Default for Pk3 {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            dir: LumpsDirectory::new(),
+        }
+    }
+}
+
+impl Pk3 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a zip buffer
+    ///
+    /// The stored entries keep a view into `buffer`,
+    /// the deflated ones are decompressed
+    pub fn load<T: Into<Vec<u8>>>(&mut self, buffer: T) -> Result<(), WadError> {
+        let src: WadSource = Rc::new(buffer.into());
+        let bytes = (*src).as_ref();
+        let invalid = || WadError::Parse(String::from("Invalid zip file"));
+
+        self.paths.clear();
+        self.dir = LumpsDirectory::new();
+
+        if bytes.len() < END_SIZE {
+            return Err(invalid());
+        }
+
+        // The end of central directory is followed by a comment
+        let end = (0..=bytes.len() - END_SIZE)
+            .rev()
+            .take(u16::MAX as usize + 1)
+            .find(|&pos| u32_at(bytes, pos) == END_SIGNATURE)
+            .ok_or_else(invalid)?;
+        let count = u16_at(bytes, end + 10) as usize;
+        let mut pos = u32_at(bytes, end + 16) as usize;
+
+        for _ in 0..count {
+            if pos + CENTRAL_SIZE > bytes.len() || u32_at(bytes, pos) != CENTRAL_SIGNATURE {
+                return Err(invalid());
+            }
+
+            let method = u16_at(bytes, pos + 10);
+            let crc = u32_at(bytes, pos + 16);
+            let compressed = u32_at(bytes, pos + 20) as usize;
+            let size = u32_at(bytes, pos + 24) as usize;
+            let name_len = u16_at(bytes, pos + 28) as usize;
+            let extra_len = u16_at(bytes, pos + 30) as usize;
+            let comment_len = u16_at(bytes, pos + 32) as usize;
+            let local = u32_at(bytes, pos + 42) as usize;
+            let path = bytes
+                .get(pos + CENTRAL_SIZE..pos + CENTRAL_SIZE + name_len)
+                .ok_or_else(invalid)?;
+            let path = String::from_utf8_lossy(path).replace('\\', "/");
+
+            pos += CENTRAL_SIZE + name_len + extra_len + comment_len;
+
+            // Folders
+            if path.ends_with('/') {
+                continue;
+            }
+
+            // The local header can have another extra field
+            if local + LOCAL_SIZE > bytes.len() || u32_at(bytes, local) != LOCAL_SIGNATURE {
+                return Err(invalid());
+            }
+
+            let start = local + LOCAL_SIZE + u16_at(bytes, local + 26) as usize;
+            let start = start + u16_at(bytes, local + 28) as usize;
+            let buffer = match method {
+                METHOD_STORED => LumpBuffer::shared(src.clone(), start, size),
+                METHOD_DEFLATED => {
+                    let data = bytes.get(start..start + compressed).ok_or_else(invalid)?;
+                    let mut out = Vec::with_capacity(size);
+
+                    DeflateDecoder::new(data)
+                        .read_to_end(&mut out)
+                        .map_err(read_err)?;
+                    Some(out.into())
+                }
+                _ => {
+                    return Err(WadError::Parse(format!(
+                        "Unsupported compression method {}",
+                        method
+                    )))
+                }
+            }
+            .ok_or(WadError::InvalidLump)?;
+
+            if crc32(&buffer) != crc {
+                return Err(WadError::Checksum("zip entry"));
+            }
+
+            self.push(path, buffer)?;
+        }
+
+        self.rebuild_images()
+    }
+
+    /// Read then parse the zip file at `path`
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        self.load(fs::read(path).map_err(read_err)?)
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Returns true if there is no entry
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Iterate over the entries paths and lumps, in the archive order
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &dyn Lump)> + '_ {
+        self.paths
+            .iter()
+            .zip(self.dir.lumps.iter())
+            .map(|(path, lump)| (path.as_str(), lump.as_ref()))
+    }
+
+    /// Iterate over the lumps, in the archive order
+    pub fn lumps(&self) -> impl Iterator<Item = &dyn Lump> + '_ {
+        self.dir.lumps.iter().map(|lump| lump.as_ref())
+    }
+
+    /// Get the lump at `path`
+    pub fn lump(&self, path: &str) -> Option<&dyn Lump> {
+        let index = self.paths.iter().position(|p| p == path)?;
+
+        Some(self.dir.lumps[index].as_ref())
+    }
+
+    /// Add an entry, replacing the one at the same path
+    pub fn add(&mut self, path: &str, buffer: &[u8]) -> Result<(), WadError> {
+        self.remove(path);
+        self.push(path.to_string(), buffer.into())?;
+        self.rebuild_images()
+    }
+
+    /// Remove the entry at `path`, returns true if it existed
+    pub fn remove(&mut self, path: &str) -> bool {
+        match self.paths.iter().position(|p| p == path) {
+            Some(index) => {
+                self.paths.remove(index);
+                self.dir.lumps.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Classify the content of the entry at `path`
+    fn classify(&self, path: &str, name: &str, buffer: &[u8]) -> Classification {
+        let namespace = path_namespace(path);
+        let is_map = is_map_path(path);
+
+        self.dir.classifier.classify(&ClassifyInput {
+            name,
+            size: buffer.len(),
+            namespace: if is_map { None } else { namespace.as_ref() },
+            buffer: if is_map { None } else { Some(buffer) },
+        })
+    }
+
+    /// Classify then append an entry
+    fn push(&mut self, path: String, buffer: LumpBuffer) -> Result<(), WadError> {
+        self.insert(self.len(), path, buffer)
+    }
+
+    /// Classify then insert an entry at `index`
+    fn insert(&mut self, index: usize, path: String, buffer: LumpBuffer) -> Result<(), WadError> {
+        let name = path_name(&path);
+        let namespace = path_namespace(&path);
+        let mut metadata = LumpInfo::new(0, buffer.len() as i32, LumpAdd::name_bytes(&name));
+
+        self.dir.set_id(&mut metadata);
+
+        let classification = self.classify(&path, &name, &buffer);
+        let data = LumpData {
+            buffer,
+            metadata,
+            kind: classification.kind,
+            confidence: classification.confidence,
+        };
+        let lump = self.dir.build_lump(data, namespace.as_ref())?;
+
+        self.dir.lumps.insert(index, lump);
+        self.paths.insert(index, path);
+
+        Ok(())
+    }
+
+    /// Rebuild the images with the palettes, wherever the PLAYPAL is
+    fn rebuild_images(&mut self) -> Result<(), WadError> {
+        for index in 0..self.paths.len() {
+            let data = self.dir.lumps[index].data();
+
+            if matches!(data.kind, LumpKind::Patch | LumpKind::Flat) {
+                let namespace = path_namespace(&self.paths[index]);
+
+                self.dir.lumps[index] = self.dir.build_lump(data, namespace.as_ref())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the zip buffer
+    pub fn build(&self, options: &Pk3Options) -> Result<Vec<u8>, WadError> {
+        let mut out = Vec::new();
+        let mut central = Vec::new();
+
+        for (path, lump) in self.entries() {
+            let data = lump.data();
+            let crc = crc32(&data.buffer);
+            let (method, content) = match options.level {
+                0 => (METHOD_STORED, data.buffer.to_vec()),
+                level => {
+                    let mut encoder =
+                        DeflateEncoder::new(Vec::new(), Compression::new(level.min(9)));
+
+                    encoder.write_all(&data.buffer).map_err(write_err)?;
+                    (METHOD_DEFLATED, encoder.finish().map_err(write_err)?)
+                }
+            };
+            let offset = out.len() as u32;
+
+            // Fields shared by the local header and the central directory
+            let mut fields = Vec::new();
+
+            fields.extend(ZIP_VERSION.to_le_bytes());
+            fields.extend(FLAG_UTF8.to_le_bytes());
+            fields.extend(method.to_le_bytes());
+            fields.extend(0u16.to_le_bytes());
+            fields.extend(DOS_DATE.to_le_bytes());
+            fields.extend(crc.to_le_bytes());
+            fields.extend((content.len() as u32).to_le_bytes());
+            fields.extend((data.buffer.len() as u32).to_le_bytes());
+            fields.extend((path.len() as u16).to_le_bytes());
+            fields.extend(0u16.to_le_bytes());
+
+            out.extend(LOCAL_SIGNATURE.to_le_bytes());
+            out.extend(&fields);
+            out.extend(path.as_bytes());
+            out.extend(content);
+
+            central.extend(CENTRAL_SIGNATURE.to_le_bytes());
+            central.extend(ZIP_VERSION.to_le_bytes());
+            central.extend(&fields);
+            // Comment, disk, internal and external attributes
+            central.extend([0; 10]);
+            central.extend(offset.to_le_bytes());
+            central.extend(path.as_bytes());
+        }
+
+        let count = self.len() as u16;
+        let central_pos = out.len() as u32;
+
+        out.extend(&central);
+        out.extend(END_SIGNATURE.to_le_bytes());
+        out.extend([0; 4]);
+        out.extend(count.to_le_bytes());
+        out.extend(count.to_le_bytes());
+        out.extend((central.len() as u32).to_le_bytes());
+        out.extend(central_pos.to_le_bytes());
+        out.extend([0; 2]);
+
+        Ok(out)
+    }
+
+    /// Write the zip file at `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P, options: &Pk3Options) -> Result<(), WadError> {
+        fs::write(path, self.build(options)?).map_err(write_err)
+    }
+
+    /// Convert a WAD, the namespaces become folders and
+    /// each map is written as a single map WAD in `maps/`
+    ///
+    /// The lumps of the other namespaces stay at the root with their markers
+    pub fn from_wad(wad: &Wad) -> Result<Self, WadError> {
+        let dir = wad.dir();
+        let namespaces = dir.namespaces();
+        let maps = dir.maps();
+        let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut level: Option<(String, WadWriter<Cursor<Vec<u8>>>)> = None;
+
+        for (i, lump) in dir.lumps.iter().enumerate() {
+            let data = lump.data();
+            let name = data.metadata.name_ascii();
+
+            if !data.metadata.state.is_alive() {
+                continue;
+            }
+
+            // The map ends with the first lump out of it
+            let in_map = match (&maps[i], &namespaces[i]) {
+                (Some(map), None) => level.as_ref().is_some_and(|(current, _)| current == map),
+                _ => false,
+            };
+
+            if !in_map || name == *maps[i].as_ref().unwrap() {
+                if let Some((map, writer)) = level.take() {
+                    entries.push((
+                        format!("{}/{}.wad", MAPS_FOLDER, file_name(&map)),
+                        writer.finish()?.into_inner(),
+                    ));
+                }
+            }
+
+            if let (Some(map), None) = (&maps[i], &namespaces[i]) {
+                let (_, writer) = level.get_or_insert(
+                    WadWriter::new(Cursor::new(Vec::new()), WadKind::Pwad)
+                        .map(|writer| (map.clone(), writer))?,
+                );
+
+                writer.write_lump(data.metadata.name, &data.buffer)?;
+                continue;
+            }
+
+            let folder_namespace = namespaces[i]
+                .as_ref()
+                .filter(|namespace| FOLDER_NAMESPACES.contains(namespace));
+
+            // The folder namespaces markers are not written
+            if let Some(marker) = Marker::parse(&name) {
+                if FOLDER_NAMESPACES.contains(&marker.namespace) {
+                    continue;
+                }
+            }
+
+            let ext = match data.kind {
+                LumpKind::Png => "png",
+                LumpKind::Music if data.buffer.starts_with(&MIDI_MAGIC) => "mid",
+                LumpKind::Music => "mus",
+                _ => "lmp",
+            };
+            let folder = match (folder_namespace, data.kind) {
+                (Some(namespace), _) => format!("{}/", namespace),
+                (None, LumpKind::Music) => format!("{}/", MUSIC_FOLDER),
+                (None, LumpKind::Sound) => format!("{}/", SOUNDS_FOLDER),
+                _ => String::new(),
+            };
+
+            // A duplicated name gets a second extension
+            let mut path = format!("{}{}.{}", folder, file_name(&name), ext);
+            let mut n = 1;
+
+            while entries.iter().any(|(p, _)| *p == path) {
+                path = format!("{}{}.{}.{}", folder, file_name(&name), n, ext);
+                n += 1;
+            }
+
+            entries.push((path, data.buffer.to_vec()));
+        }
+
+        if let Some((map, writer)) = level.take() {
+            entries.push((
+                format!("{}/{}.wad", MAPS_FOLDER, file_name(&map)),
+                writer.finish()?.into_inner(),
+            ));
+        }
+
+        let mut pk3 = Self::new();
+
+        pk3.dir.pal = wad.palettes().clone();
+
+        for (path, buffer) in entries {
+            pk3.push(path, buffer.into())?;
+        }
+
+        pk3.rebuild_images()?;
+
+        Ok(pk3)
+    }
+
+    /// Convert the archive into a WAD buffer
+    ///
+    /// The folder namespaces lumps are written under markers after
+    /// the other lumps, the maps WADs are inlined
+    pub fn to_wad_raw(&self, kind: WadKind) -> Result<Vec<u8>, WadError> {
+        let mut writer = WadWriter::new(Cursor::new(Vec::new()), kind)?;
+        let mut namespaces: Vec<(Namespace, Vec<usize>)> = Vec::new();
+
+        for (index, (path, lump)) in self.entries().enumerate() {
+            let data = lump.data();
+
+            if let Some(namespace) = path_namespace(path) {
+                match namespaces.iter_mut().find(|(n, _)| *n == namespace) {
+                    Some((_, indexes)) => indexes.push(index),
+                    None => namespaces.push((namespace, vec![index])),
+                }
+
+                continue;
+            }
+
+            if !is_map_path(path) {
+                writer.write_lump(data.metadata.name, &data.buffer)?;
+                continue;
+            }
+
+            let mut level = Wad::new();
+
+            level.load(data.buffer.to_vec())?;
+
+            // The map marker is named after the file
+            for (i, map_lump) in level.lumps().enumerate() {
+                let map_data = map_lump.data();
+                let name = match i {
+                    0 => data.metadata.name,
+                    _ => map_data.metadata.name,
+                };
+
+                writer.write_lump(name, &map_data.buffer)?;
+            }
+        }
+
+        for (namespace, indexes) in namespaces.iter() {
+            writer.write_marker(LumpAdd::name_bytes(&namespace.start_marker()));
+
+            for index in indexes {
+                let data = self.dir.lumps[*index].data();
+
+                writer.write_lump(data.metadata.name, &data.buffer)?;
+            }
+
+            writer.write_marker(LumpAdd::name_bytes(&namespace.end_marker()));
+        }
+
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// Convert the archive into a WAD
+    pub fn to_wad(&self, kind: WadKind) -> Result<Wad, WadError> {
+        let mut wad = Wad::new();
+
+        wad.load(self.to_wad_raw(kind)?)?;
+
+        Ok(wad)
+    }
+}
+
+impl Container for Pk3 {
+    fn load_bytes(&mut self, buffer: Vec<u8>) -> Result<(), WadError> {
+        self.load(buffer)
+    }
+
+    fn entries_len(&self) -> usize {
+        self.len()
+    }
+
+    fn entry_name(&self, index: usize) -> Option<String> {
+        self.paths.get(index).cloned()
+    }
+
+    fn entry_buffer(&self, index: usize) -> Option<LumpBuffer> {
+        self.dir.lumps.get(index).map(|lump| lump.data().buffer)
+    }
+
+    fn set_entry_buffer(&mut self, index: usize, buffer: &[u8]) -> Result<(), WadError> {
+        let path = self
+            .paths
+            .get(index)
+            .cloned()
+            .ok_or(WadError::InvalidLump)?;
+        let mut data = self.dir.lumps[index].data();
+        let classification = self.classify(&path, &data.metadata.name_ascii(), buffer);
+
+        data.buffer = buffer.into();
+        data.metadata.size = buffer.len() as i32;
+        data.kind = classification.kind;
+        data.confidence = classification.confidence;
+        self.dir.lumps[index] = self.dir.build_lump(data, path_namespace(&path).as_ref())?;
+
+        match classification.kind {
+            LumpKind::Palette => self.rebuild_images(),
+            _ => Ok(()),
+        }
+    }
+
+    fn insert_entry(&mut self, index: usize, name: &str, buffer: &[u8]) -> Result<(), WadError> {
+        if name.is_empty() || name.ends_with('/') || index > self.len() {
+            return Err(WadError::InvalidLump);
+        }
+
+        self.insert(index, name.to_string(), buffer.into())?;
+        self.rebuild_images()
+    }
+
+    fn remove_entry(&mut self, index: usize) -> Result<(), WadError> {
+        if index >= self.len() {
+            return Err(WadError::InvalidLump);
+        }
+
+        self.paths.remove(index);
+        self.dir.lumps.remove(index);
+
+        Ok(())
+    }
+
+    /// The lump is built again, its namespace following the new folder
+    fn rename_entry(&mut self, index: usize, name: &str) -> Result<(), WadError> {
+        let buffer = self.entry_buffer(index).ok_or(WadError::InvalidLump)?;
+
+        self.remove_entry(index)?;
+        self.insert_entry(index, name, &buffer)
+    }
+
+    fn move_entry(&mut self, from: usize, to: usize) -> Result<(), WadError> {
+        move_item(&mut self.paths, from, to)?;
+        move_item(&mut self.dir.lumps, from, to)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, WadError> {
+        self.build(&Pk3Options::default())
+    }
+}
@@ -3,16 +3,16 @@ use std::{fs::File, io::Read, path::Path};
 
 pub struct PathWrap<P: AsRef<Path>>(P);
 
-impl Into<PathWrap<&str>> for &'static str {
-    fn into(self) -> PathWrap<&'static str> {
-        PathWrap(self)
+impl<P: AsRef<Path>> From<P> for PathWrap<P> {
+    fn from(value: P) -> Self {
+        PathWrap(value)
     }
 }
 
-impl TryFrom<PathWrap<&str>> for Vec<u8> {
+impl<P: AsRef<Path>> TryFrom<PathWrap<P>> for Vec<u8> {
     type Error = WadError;
 
-    fn try_from(value: PathWrap<&str>) -> Result<Self, Self::Error> {
+    fn try_from(value: PathWrap<P>) -> Result<Self, Self::Error> {
         match File::open(value.0) {
             Ok(mut file) => {
                 let mut data = Vec::<u8>::new();
@@ -0,0 +1,203 @@
+use std::ops::{Bound, RangeBounds};
+
+use regex::Regex;
+
+use crate::{dir::LumpsDirectory, lump::LumpKind, namespace::Namespace};
+
+/// Composable lumps filter
+///
+/// Every criteria must match, an unset criteria matches everything
+#[derive(Clone, Debug)]
+pub struct LumpQuery {
+    /// Lump name pattern
+    name: Option<Regex>,
+    /// Accepted lump kinds
+    kinds: Vec<LumpKind>,
+    /// Enclosing namespace
+    namespace: Option<Namespace>,
+    /// Lump size in bytes
+    size: (Bound<usize>, Bound<usize>),
+    /// Lump index in the directory
+    index: (Bound<usize>, Bound<usize>),
+    /// Map the lump belongs to
+    map: Option<String>,
+}
+
+impl Default for LumpQuery {
+    fn default() -> Self {
+        Self {
+            name: None,
+            kinds: Vec::new(),
+            namespace: None,
+            size: (Bound::Unbounded, Bound::Unbounded),
+            index: (Bound::Unbounded, Bound::Unbounded),
+            map: None,
+        }
+    }
+}
+
+impl LumpQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match the lump name with a glob pattern (`*` and `?`)
+    pub fn name(mut self, glob: &str) -> Self {
+        let mut pattern = String::from("^");
+
+        for c in glob.chars() {
+            match c {
+                '*' => pattern.push_str(".*"),
+                '?' => pattern.push('.'),
+                _ => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+
+        pattern.push('$');
+
+        self.name = Regex::new(&pattern).ok();
+        self
+    }
+
+    /// Match the lump name with a regex
+    pub fn name_regex(mut self, re: Regex) -> Self {
+        self.name = Some(re);
+        self
+    }
+
+    /// Accept the lump kind `kind`, can be called multiple times
+    pub fn kind(mut self, kind: LumpKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Match the lumps inside `namespace`, between its markers
+    pub fn namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Match the lumps with a size in `range`
+    pub fn size<R: RangeBounds<usize>>(mut self, range: R) -> Self {
+        self.size = (range.start_bound().cloned(), range.end_bound().cloned());
+        self
+    }
+
+    /// Match the lumps with an index in `range`
+    pub fn index<R: RangeBounds<usize>>(mut self, range: R) -> Self {
+        self.index = (range.start_bound().cloned(), range.end_bound().cloned());
+        self
+    }
+
+    /// Match the marker and the lumps of the map `name`
+    pub fn map(mut self, name: &str) -> Self {
+        self.map = Some(name.to_string());
+        self
+    }
+
+    /// Run the query against `dir`, the removed lumps are ignored
+    pub fn run(&self, dir: &LumpsDirectory) -> LumpSet {
+        let namespaces = self.namespace.as_ref().map(|_| dir.namespaces());
+        let maps = self.map.as_ref().map(|_| dir.maps());
+        let mut indexes = Vec::new();
+
+        for (i, lump) in dir.lumps.iter().enumerate() {
+            let data = lump.data();
+
+            if !data.metadata.state.is_alive() || !self.index.contains(&i) {
+                continue;
+            }
+
+            if !self.kinds.is_empty() && !self.kinds.contains(&data.kind) {
+                continue;
+            }
+
+            if !self.size.contains(&data.buffer.len()) {
+                continue;
+            }
+
+            if let Some(re) = &self.name {
+                if !re.is_match(&data.metadata.name_ascii()) {
+                    continue;
+                }
+            }
+
+            if let Some(namespaces) = &namespaces {
+                if namespaces[i] != self.namespace {
+                    continue;
+                }
+            }
+
+            if let Some(maps) = &maps {
+                if maps[i] != self.map {
+                    continue;
+                }
+            }
+
+            indexes.push(i);
+        }
+
+        LumpSet { indexes }
+    }
+}
+
+/// Result of a `LumpQuery`, the matching lumps indexes
+///
+/// It stays valid until lumps are added or moved
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LumpSet {
+    indexes: Vec<usize>,
+}
+
+impl LumpSet {
+    /// Get the lumps indexes
+    pub fn indexes(&self) -> &[usize] {
+        &self.indexes
+    }
+
+    /// Get the matching lumps amount
+    pub fn len(&self) -> usize {
+        self.indexes.len()
+    }
+
+    /// Returns true if no lump matched
+    pub fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    /// Returns true if `index` is in the set
+    pub fn contains(&self, index: usize) -> bool {
+        self.indexes.binary_search(&index).is_ok()
+    }
+
+    /// Returns the lumps matching both sets
+    pub fn intersection(&self, other: &LumpSet) -> LumpSet {
+        let indexes = self
+            .indexes
+            .iter()
+            .filter(|i| other.contains(**i))
+            .copied()
+            .collect();
+
+        LumpSet { indexes }
+    }
+
+    /// Returns the lumps matching at least one set
+    pub fn union(&self, other: &LumpSet) -> LumpSet {
+        let mut indexes = [self.indexes.clone(), other.indexes.clone()].concat();
+
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        LumpSet { indexes }
+    }
+}
+
+impl From<Vec<usize>> for LumpSet {
+    fn from(mut indexes: Vec<usize>) -> Self {
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        Self { indexes }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    classifier::{Classification, LumpClassifier},
+    dir::{find_address, LumpsDirectory},
+    error::WadError,
+    jaguar,
+    lump::{LumpData, LumpInfo, LumpKind},
+    models::lump::Lump,
+    namespace::Namespace,
+    registry::LumpRegistry,
+    wad::{WadInfo, WadKind},
+};
+
+/// Streaming WAD reader
+///
+/// Only the header and the directory are read when opening,
+/// the lumps content is fetched on demand from `inner`
+pub struct WadReader<R: Read + Seek> {
+    /// Source stream
+    inner: R,
+    /// WAD header
+    info: WadInfo,
+    /// Stream length
+    len: u64,
+    /// Directory entries, with their unique ID
+    entries: Vec<LumpInfo>,
+    /// Classification of each entry, from the names and the markers only
+    kinds: Vec<Classification>,
+    /// Namespace of each entry
+    namespaces: Vec<Option<Namespace>>,
+    /// Used to classify the entries and build the lumps
+    dir: LumpsDirectory,
+    /// Set once the palettes have been fetched
+    pal_loaded: bool,
+}
+
+impl WadReader<BufReader<File>> {
+    /// Open the file at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, WadError> {
+        let file = File::open(path).map_err(|e| WadError::Read(e.to_string()))?;
+
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> WadReader<R> {
+    /// Read the header and the directory from `inner`
+    pub fn new(inner: R) -> Result<Self, WadError> {
+        Self::with_classifier(inner, LumpClassifier::default())
+    }
+
+    /// Read the header and the directory from `inner`,
+    /// the entries are classified with `classifier`
+    ///
+    /// The content is not fetched so it can not be sniffed
+    pub fn with_classifier(mut inner: R, classifier: LumpClassifier) -> Result<Self, WadError> {
+        let mut header = [0x00; 12];
+
+        read_at(&mut inner, 0, &mut header)
+            .map_err(|_| WadError::Load("The file size is too small."))?;
+
+        let info = WadInfo::from(&header[..]);
+
+        if info.kind == WadKind::Unknown {
+            return Err(WadError::Type("The file is not a WAD file."));
+        }
+
+        if info.kind.is_texture_wad() {
+            return Err(WadError::Type("The texture WADs are loaded by TextureWad."));
+        }
+
+        if info.num_lumps < 0 || info.dir_pos < 0 {
+            return Err(WadError::Parse(String::from("Invalid directory")));
+        }
+
+        // The directory must fit the stream before being allocated
+        let len = inner
+            .seek(SeekFrom::End(0))
+            .map_err(|e| WadError::Read(e.to_string()))?;
+        let size = (info.num_lumps as u64)
+            .checked_mul(16)
+            .filter(|size| (info.dir_pos as u64).checked_add(*size) <= Some(len))
+            .ok_or(WadError::Parse(String::from("Invalid directory")))?;
+        let mut buffer = vec![0x00; size as usize];
+
+        read_at(&mut inner, info.dir_pos as u64, &mut buffer)?;
+
+        let mut dir = LumpsDirectory::new();
+
+        dir.classifier = classifier;
+        let mut entries = Vec::new();
+        let mut kinds = Vec::new();
+        let mut namespaces = Vec::new();
+
+        for bytes in buffer.chunks(16) {
+            let mut metadata = LumpInfo::from(bytes);
+
+            dir.set_id(&mut metadata);
+            kinds.push(dir.classify(&metadata, None));
+            namespaces.push(dir.namespace().cloned());
+            entries.push(metadata);
+        }
+
+        Ok(Self {
+            inner,
+            info,
+            len,
+            entries,
+            kinds,
+            namespaces,
+            dir,
+            pal_loaded: false,
+        })
+    }
+
+    /// Get the WAD header
+    pub fn info(&self) -> WadInfo {
+        self.info
+    }
+
+    /// Get the directory entries
+    pub fn entries(&self) -> &[LumpInfo] {
+        &self.entries
+    }
+
+    /// Get the lump kind of the entry `index`
+    pub fn kind(&self, index: usize) -> Option<LumpKind> {
+        self.kinds.get(index).map(|c| c.kind)
+    }
+
+    /// Get the classification of the entry `index`
+    pub fn classification(&self, index: usize) -> Option<Classification> {
+        self.kinds.get(index).copied()
+    }
+
+    /// Get the namespace of the entry `index`
+    pub fn namespace(&self, index: usize) -> Option<&Namespace> {
+        self.namespaces.get(index)?.as_ref()
+    }
+
+    /// Get the caller lump decoders
+    pub fn registry_mut(&mut self) -> &mut LumpRegistry {
+        &mut self.dir.registry
+    }
+
+    /// Returns a lump index by address (`NAME`, `NAME#n`, `MAP01/THINGS`)
+    pub fn index(&self, name: &str) -> Option<usize> {
+        find_address(&self.entries, name)
+    }
+
+    /// Fetch the raw content of the entry `index`
+    pub fn read_raw(&mut self, index: usize) -> Result<Vec<u8>, WadError> {
+        let metadata = match self.entries.get(index) {
+            Some(value) => *value,
+            None => return Err(WadError::InvalidLumpName),
+        };
+
+        if metadata.pos < 0 || metadata.size < 0 || metadata.pos as u64 > self.len {
+            return Err(WadError::InvalidLump);
+        }
+
+        if !metadata.compressed {
+            if metadata.pos as u64 + metadata.size as u64 > self.len {
+                return Err(WadError::InvalidLump);
+            }
+
+            let mut buffer = vec![0x00; metadata.size as usize];
+
+            read_at(&mut self.inner, metadata.pos as u64, &mut buffer)?;
+
+            return Ok(buffer);
+        }
+
+        // The compressed data ends before the next lump, the directory
+        // or the end of the stream
+        let end = self
+            .entries
+            .iter()
+            .map(|entry| entry.pos as u64)
+            .chain([self.info.dir_pos as u64, self.len])
+            .filter(|pos| *pos > metadata.pos as u64)
+            .min()
+            .ok_or(WadError::InvalidLump)?;
+        let mut buffer = vec![0x00; (end - metadata.pos as u64) as usize];
+
+        read_at(&mut self.inner, metadata.pos as u64, &mut buffer)?;
+        jaguar::decompress(&buffer, metadata.size as usize)
+    }
+
+    /// Fetch then build the lump `index`
+    ///
+    /// The palettes are fetched the first time an image is requested
+    pub fn lump(&mut self, index: usize) -> Result<Box<dyn Lump>, WadError> {
+        let classification = self
+            .classification(index)
+            .ok_or(WadError::InvalidLumpName)?;
+
+        if matches!(classification.kind, LumpKind::Patch | LumpKind::Flat) {
+            self.load_palettes()?;
+        }
+
+        let data = LumpData {
+            buffer: self.read_raw(index)?.into(),
+            metadata: self.entries[index],
+            kind: classification.kind,
+            confidence: classification.confidence,
+        };
+
+        let namespace = self.namespaces[index].clone();
+
+        self.dir.build_lump(data, namespace.as_ref())
+    }
+
+    /// Fetch then build a lump by address
+    pub fn lump_by_name(&mut self, name: &str) -> Result<Box<dyn Lump>, WadError> {
+        let index = self.index(name).ok_or(WadError::InvalidLumpName)?;
+
+        self.lump(index)
+    }
+
+    /// Set the palette that will be applied on the image lumps
+    pub fn set_palette(&mut self, value: usize) {
+        self.dir.set_palette(value);
+    }
+
+    /// Fetch then parse the PLAYPAL lump if it has not been done yet
+    fn load_palettes(&mut self) -> Result<(), WadError> {
+        if self.pal_loaded {
+            return Ok(());
+        }
+
+        self.pal_loaded = true;
+
+        if let Some(index) = self.kinds.iter().position(|c| c.kind == LumpKind::Palette) {
+            self.lump(index)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read exactly `buffer.len()` bytes from `pos`
+fn read_at<R: Read + Seek>(inner: &mut R, pos: u64, buffer: &mut [u8]) -> Result<(), WadError> {
+    inner
+        .seek(SeekFrom::Start(pos))
+        .and_then(|_| inner.read_exact(buffer))
+        .map_err(|e| WadError::Read(e.to_string()))
+}
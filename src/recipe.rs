@@ -0,0 +1,503 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{
+    error::WadError,
+    history::Edit,
+    lump::{LumpInfo, LumpPosition},
+    lumps::palette::Palettes,
+    models::container::Container,
+    namespace::Marker,
+    tree::{encode_file, read_err, TreeFormat},
+    wad::{Wad, WadKind},
+    writer::WadWriter,
+};
+
+/// Recipe value, a subset of the TOML ones
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+/// Recipe table, the root one has an empty name
+struct Table {
+    /// `[name]` or `[[name]]`
+    name: String,
+    /// Keys in the file order
+    keys: Vec<(String, Value)>,
+    /// Line of the header, used by the errors
+    line: usize,
+}
+
+impl Table {
+    fn invalid(&self, key: &str) -> WadError {
+        WadError::Parse(format!(
+            "Invalid recipe key {} in the table starting line {}",
+            key, self.line
+        ))
+    }
+
+    /// Fail on the keys missing from `allowed`
+    fn check(&self, allowed: &[&str]) -> Result<(), WadError> {
+        match self.keys.iter().find(|(key, _)| !allowed.contains(&&**key)) {
+            Some((key, _)) => Err(self.invalid(key)),
+            None => Ok(()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.keys.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn str(&self, key: &str) -> Result<Option<&str>, WadError> {
+        match self.get(key) {
+            Some(Value::Str(value)) => Ok(Some(value)),
+            Some(_) => Err(self.invalid(key)),
+            None => Ok(None),
+        }
+    }
+
+    fn int(&self, key: &str) -> Result<Option<i64>, WadError> {
+        match self.get(key) {
+            Some(Value::Int(value)) => Ok(Some(*value)),
+            Some(_) => Err(self.invalid(key)),
+            None => Ok(None),
+        }
+    }
+
+    fn required(&self, key: &str) -> Result<&str, WadError> {
+        self.str(key)?.ok_or_else(|| self.invalid(key))
+    }
+
+    /// Regex checked right away, a bad one would select every lump
+    fn regex(&self, key: &str) -> Result<String, WadError> {
+        let value = self.str(key)?.unwrap_or(".*");
+
+        Regex::new(value).map_err(|_| WadError::InvalidRegex)?;
+
+        Ok(value.to_string())
+    }
+
+    /// File format and picture offsets
+    fn format(&self) -> Result<(TreeFormat, (i16, i16)), WadError> {
+        let format = match self.str("format")? {
+            Some(value) => TreeFormat::from_str(value).ok_or_else(|| self.invalid("format"))?,
+            None => TreeFormat::Raw,
+        };
+        let offset = |key| match self.int(key)? {
+            Some(value) => i16::try_from(value).map_err(|_| self.invalid(key)),
+            None => Ok(0),
+        };
+
+        Ok((format, (offset("left")?, offset("top")?)))
+    }
+
+    /// Position of an added lump, at the end by default
+    fn position(&self) -> Result<LumpPosition, WadError> {
+        let keys = ["after", "before", "namespace", "index", "position"];
+
+        if keys.iter().filter(|key| self.get(key).is_some()).count() > 1 {
+            return Err(self.invalid("position"));
+        }
+
+        if let Some(value) = self.str("after")? {
+            return Ok(LumpPosition::After(value.to_string()));
+        }
+
+        if let Some(value) = self.str("before")? {
+            return Ok(LumpPosition::Before(value.to_string()));
+        }
+
+        if let Some(value) = self.str("namespace")? {
+            let marker = Marker::parse(value).ok_or_else(|| self.invalid("namespace"))?;
+
+            return Ok(LumpPosition::Namespace(marker.namespace));
+        }
+
+        if let Some(value) = self.int("index")? {
+            let index = usize::try_from(value).map_err(|_| self.invalid("index"))?;
+
+            return Ok(LumpPosition::Index(index));
+        }
+
+        match self.str("position")? {
+            Some("front") => Ok(LumpPosition::Front),
+            Some("back") | None => Ok(LumpPosition::Back),
+            Some(_) => Err(self.invalid("position")),
+        }
+    }
+}
+
+/// Parse a quoted string, returns it with the rest of the line
+///
+/// The basic strings (`"`) handle the `\"`, `\\`, `\n` and `\t` escapes,
+/// the literal ones (`'`) are kept as is, handy for the regexes
+fn parse_string(value: &str) -> Option<(String, &str)> {
+    let mut chars = value.char_indices();
+    let quote = chars.next()?.1;
+    let mut ret = String::new();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            _ if c == quote => return Some((ret, &value[i + 1..])),
+            '\\' if quote == '"' => match chars.next()?.1 {
+                'n' => ret.push('\n'),
+                't' => ret.push('\t'),
+                c @ ('"' | '\\') => ret.push(c),
+                _ => return None,
+            },
+            _ => ret.push(c),
+        }
+    }
+
+    None
+}
+
+/// Parse a value, followed by an optional comment
+fn parse_value(value: &str) -> Option<Value> {
+    if value.starts_with(['"', '\'']) {
+        let (ret, rest) = parse_string(value)?;
+        let rest = rest.trim();
+
+        return match rest.is_empty() || rest.starts_with('#') {
+            true => Some(Value::Str(ret)),
+            false => None,
+        };
+    }
+
+    let value = value.split('#').next()?.trim();
+
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => value.replace('_', "").parse().ok().map(Value::Int),
+    }
+}
+
+/// Split the recipe text into tables
+fn parse_tables(text: &str) -> Result<Vec<Table>, WadError> {
+    let mut ret = vec![Table {
+        name: String::new(),
+        keys: Vec::new(),
+        line: 1,
+    }];
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let invalid = || WadError::Parse(format!("Invalid recipe line {}", n + 1));
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            let header = line.split('#').next().unwrap_or_default().trim();
+            let name = header
+                .strip_prefix("[[")
+                .and_then(|h| h.strip_suffix("]]"))
+                .or_else(|| header.strip_prefix('[').and_then(|h| h.strip_suffix(']')))
+                .ok_or_else(invalid)?;
+
+            ret.push(Table {
+                name: name.trim().to_string(),
+                keys: Vec::new(),
+                line: n + 1,
+            });
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+        let key = key.trim().trim_matches('"').to_string();
+        let value = parse_value(value.trim()).ok_or_else(invalid)?;
+        let table = ret.last_mut().ok_or_else(invalid)?;
+
+        if key.is_empty() || table.get(&key).is_some() {
+            return Err(invalid());
+        }
+
+        table.keys.push((key, value));
+    }
+
+    Ok(ret)
+}
+
+/// Lumps copied from a source WAD, in its order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecipeSource {
+    /// WAD file
+    pub path: PathBuf,
+    /// Names of the copied lumps (regex)
+    pub select: String,
+}
+
+/// Edit applied on the assembled WAD
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecipeStep {
+    /// Remove the matching lumps
+    Remove { select: String },
+    /// Replace the content of the matching lumps with a file
+    Replace {
+        select: String,
+        file: PathBuf,
+        format: TreeFormat,
+        offsets: (i16, i16),
+    },
+    /// Add a lump from a file
+    Add {
+        name: String,
+        file: PathBuf,
+        format: TreeFormat,
+        offsets: (i16, i16),
+        position: LumpPosition,
+    },
+}
+
+/// Declarative WAD build
+///
+/// The lumps of the sources are copied in order, then the steps
+/// are applied in order, so the output is always the same:
+///
+/// ```toml
+/// kind = "PWAD"
+/// output = "release.wad"
+/// palettes = "playpal.lmp"
+///
+/// [[source]]
+/// path = "doom2.wad"
+/// select = '^(F_START|FLOOR.*|F_END)$'
+///
+/// [[step]]
+/// replace = '^FLOOR0_1$'
+/// file = "floor.png"
+/// format = "flat"
+///
+/// [[step]]
+/// add = "CREDIT"
+/// file = "credit.lmp"
+/// after = "F_END"
+///
+/// [[step]]
+/// remove = '^FLOOR7'
+/// ```
+///
+/// The relative paths start from the recipe directory
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recipe {
+    /// Output WAD kind
+    pub kind: WadKind,
+    /// Output file
+    pub output: Option<PathBuf>,
+    /// Palette index used to convert the pictures
+    pub palette: Option<usize>,
+    /// PLAYPAL file used to convert the pictures,
+    /// otherwise the one of the sources
+    pub palettes: Option<PathBuf>,
+    /// Source WADs
+    pub sources: Vec<RecipeSource>,
+    /// Edits, in order
+    pub steps: Vec<RecipeStep>,
+}
+
+impl Recipe {
+    /// Parse the recipe `text`, the relative paths start from `root`
+    pub fn parse<P: AsRef<Path>>(text: &str, root: P) -> Result<Self, WadError> {
+        let root = root.as_ref();
+        let tables = parse_tables(text)?;
+        let mut ret = Self {
+            kind: WadKind::Pwad,
+            output: None,
+            palette: None,
+            palettes: None,
+            sources: Vec::new(),
+            steps: Vec::new(),
+        };
+
+        for table in tables.iter() {
+            match table.name.as_str() {
+                "" => {
+                    table.check(&["kind", "output", "palette", "palettes"])?;
+
+                    if let Some(kind) = table.str("kind")? {
+                        ret.kind = match WadKind::from(kind.as_bytes()) {
+                            value @ (WadKind::Iwad | WadKind::Pwad) => value,
+                            _ => return Err(table.invalid("kind")),
+                        };
+                    }
+
+                    if let Some(value) = table.int("palette")? {
+                        let value = usize::try_from(value).map_err(|_| table.invalid("palette"))?;
+
+                        ret.palette = Some(value);
+                    }
+
+                    ret.output = table.str("output")?.map(|path| root.join(path));
+                    ret.palettes = table.str("palettes")?.map(|path| root.join(path));
+                }
+                "source" => {
+                    table.check(&["path", "select"])?;
+                    ret.sources.push(RecipeSource {
+                        path: root.join(table.required("path")?),
+                        select: table.regex("select")?,
+                    });
+                }
+                "step" => ret.steps.push(Self::parse_step(table, root)?),
+                _ => return Err(table.invalid(&table.name)),
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// A step has a single action key
+    fn parse_step(table: &Table, root: &Path) -> Result<RecipeStep, WadError> {
+        let file = || Ok::<_, WadError>(root.join(table.required("file")?));
+
+        if table.get("remove").is_some() {
+            table.check(&["remove"])?;
+
+            return Ok(RecipeStep::Remove {
+                select: table.regex("remove")?,
+            });
+        }
+
+        if table.get("replace").is_some() {
+            table.check(&["replace", "file", "format", "left", "top"])?;
+
+            let (format, offsets) = table.format()?;
+
+            return Ok(RecipeStep::Replace {
+                select: table.regex("replace")?,
+                file: file()?,
+                format,
+                offsets,
+            });
+        }
+
+        table.check(&[
+            "add",
+            "file",
+            "format",
+            "left",
+            "top",
+            "after",
+            "before",
+            "namespace",
+            "index",
+            "position",
+        ])?;
+
+        let (format, offsets) = table.format()?;
+        let name = table.required("add")?;
+
+        LumpInfo::valid_name(name)?;
+
+        Ok(RecipeStep::Add {
+            name: name.to_string(),
+            file: file()?,
+            format,
+            offsets,
+            position: table.position()?,
+        })
+    }
+
+    /// Read then parse the recipe file at `path`
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, WadError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(read_err)?;
+
+        Self::parse(&text, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Assemble the sources then apply the steps
+    ///
+    /// The steps are recorded in the WAD history
+    pub fn build(&self) -> Result<Wad, WadError> {
+        let mut writer = WadWriter::new(Cursor::new(Vec::new()), self.kind)?;
+
+        for source in self.sources.iter() {
+            let mut wad = Wad::new();
+            let re = Regex::new(&source.select).map_err(|_| WadError::InvalidRegex)?;
+
+            wad.load_from_file(&source.path)?;
+
+            for lump in wad.lumps() {
+                let data = lump.data();
+
+                if re.is_match(&data.metadata.name_ascii()) {
+                    writer.write_lump(data.metadata.name, &data.buffer)?;
+                }
+            }
+        }
+
+        let mut ret = Wad::new();
+
+        ret.load(writer.finish()?.into_inner())?;
+
+        if let Some(path) = &self.palettes {
+            ret.set_palettes(Palettes::from_bytes(&fs::read(path).map_err(read_err)?)?)?;
+        }
+
+        if let Some(value) = self.palette {
+            ret.set_palette(value);
+        }
+
+        for step in self.steps.iter() {
+            match step {
+                RecipeStep::Remove { select } => {
+                    let indexes = ret.find(select)?;
+
+                    ret.remove_entries(&indexes)?;
+                }
+                RecipeStep::Replace {
+                    select,
+                    file,
+                    format,
+                    offsets,
+                } => {
+                    let file = fs::read(file).map_err(read_err)?;
+                    let buffer = encode_file(file, *format, ret.palettes(), *offsets)?;
+                    let indexes = ret.find(select)?;
+
+                    ret.replace_entries(&indexes, &buffer)?;
+                }
+                RecipeStep::Add {
+                    name,
+                    file,
+                    format,
+                    offsets,
+                    position,
+                } => {
+                    let file = fs::read(file).map_err(read_err)?;
+
+                    ret.apply(Edit::Add {
+                        position: position.clone(),
+                        name: name.clone(),
+                        buffer: encode_file(file, *format, ret.palettes(), *offsets)?,
+                    })?;
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Build the WAD then save it at `self.output`
+    pub fn run(&self) -> Result<Wad, WadError> {
+        let output = self
+            .output
+            .as_ref()
+            .ok_or(WadError::Parse(String::from("Missing recipe output")))?;
+        let ret = self.build()?;
+
+        ret.save_archive(output)?;
+
+        Ok(ret)
+    }
+}
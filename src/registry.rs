@@ -0,0 +1,98 @@
+use crate::{
+    classifier::glob_match,
+    error::WadError,
+    lump::{LumpData, LumpKind},
+    lumps::palette::Palettes,
+    models::lump::Lump,
+    namespace::Namespace,
+};
+
+/// Build a lump from its data and the current palettes
+pub type DecodeFn = Box<dyn Fn(LumpData, &Palettes) -> Result<Box<dyn Lump>, WadError>>;
+
+/// Returns true if the lump content is handled
+pub type SniffFn = Box<dyn Fn(&[u8]) -> bool>;
+
+/// Select the lumps handled by a decoder
+pub enum LumpPattern {
+    /// Lump name glob, like `DEHACKED` or `ZS*`
+    Name(String),
+    /// Every lump within a namespace
+    Namespace(Namespace),
+    /// Content sniffing
+    Content(SniffFn),
+}
+
+impl LumpPattern {
+    /// Returns true if the lump matches
+    pub fn matches(&self, name: &str, namespace: Option<&Namespace>, buffer: &[u8]) -> bool {
+        match self {
+            Self::Name(pattern) => glob_match(pattern, name),
+            Self::Namespace(value) => namespace == Some(value),
+            Self::Content(f) => f(buffer),
+        }
+    }
+}
+
+/// A registered decoder
+struct LumpDecoder {
+    pattern: LumpPattern,
+    decode: DecodeFn,
+}
+
+/// Decoders registered by the caller, tried before the built-in lump types
+///
+/// The first matching decoder, in the registration order, builds the lump.
+/// The markers are never passed to the decoders.
+#[derive(Default)]
+pub struct LumpRegistry {
+    decoders: Vec<LumpDecoder>,
+}
+
+impl LumpRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a decoder for the lumps matching `pattern`
+    pub fn register<F>(&mut self, pattern: LumpPattern, decode: F)
+    where
+        F: Fn(LumpData, &Palettes) -> Result<Box<dyn Lump>, WadError> + 'static,
+    {
+        self.decoders.push(LumpDecoder {
+            pattern,
+            decode: Box::new(decode),
+        });
+    }
+
+    /// Returns true if no decoder has been registered
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+
+    /// Remove every decoder
+    pub fn clear(&mut self) {
+        self.decoders.clear();
+    }
+
+    /// Build the lump with the first matching decoder,
+    /// returns `None` if there is not any
+    pub fn decode(
+        &self,
+        data: &LumpData,
+        namespace: Option<&Namespace>,
+        pal: &Palettes,
+    ) -> Option<Result<Box<dyn Lump>, WadError>> {
+        if data.kind == LumpKind::Marker {
+            return None;
+        }
+
+        let name = data.metadata.name_ascii();
+        let decoder = self
+            .decoders
+            .iter()
+            .find(|decoder| decoder.pattern.matches(&name, namespace, &data.buffer))?;
+
+        Some((decoder.decode)(data.clone(), pal))
+    }
+}
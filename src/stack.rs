@@ -0,0 +1,189 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    error::WadError,
+    lump::LumpKind,
+    lumps::palette::Palettes,
+    models::lump::Lump,
+    namespace::{Marker, Namespace},
+    wad::Wad,
+};
+
+/// A lump resolved from a `WadStack`
+#[derive(Clone, Copy)]
+pub struct StackLump<'a> {
+    /// The effective lump definition
+    pub lump: &'a dyn Lump,
+    /// Index of the WAD within the load order
+    pub wad: usize,
+    /// Source of the WAD, usually its path
+    pub source: &'a str,
+}
+
+/// A WAD with its source
+struct StackEntry {
+    source: String,
+    wad: Wad,
+    /// True if the WAD has its own PLAYPAL
+    has_palettes: bool,
+}
+
+/// Resources manager over an IWAD then PWADs, like the engines load them
+///
+/// The later lumps override the earlier ones and the namespaces are merged.
+/// The image lumps of every WAD use the last loaded PLAYPAL, like the engines.
+#[derive(Default)]
+pub struct WadStack {
+    entries: Vec<StackEntry>,
+}
+
+impl WadStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Push `wad` on top of the load order
+    pub fn push<S: Into<String>>(&mut self, source: S, wad: Wad) -> Result<(), WadError> {
+        let has_palettes = wad
+            .lumps()
+            .any(|lump| lump.data().kind == LumpKind::Palette);
+
+        self.entries.push(StackEntry {
+            source: source.into(),
+            wad,
+            has_palettes,
+        });
+
+        self.update_palettes()
+    }
+
+    /// Load the file at `path` then push it on top of the load order
+    pub fn push_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        let path = path.as_ref();
+        let buffer = fs::read(path).map_err(|e| WadError::Read(e.to_string()))?;
+        let mut wad = Wad::new();
+
+        wad.load(buffer)?;
+
+        self.push(path.display().to_string(), wad)
+    }
+
+    /// Get the WADs amount
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if there is not any WAD
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the WAD at `index` within the load order
+    pub fn wad(&self, index: usize) -> Option<&Wad> {
+        self.entries.get(index).map(|entry| &entry.wad)
+    }
+
+    /// Get the source of the WAD at `index`
+    pub fn source(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(|entry| entry.source.as_str())
+    }
+
+    /// Get the effective palettes, from the last WAD with a PLAYPAL
+    pub fn palettes(&self) -> Option<&Palettes> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.has_palettes)
+            .map(|entry| entry.wad.palettes())
+    }
+
+    /// Give the effective palettes to every WAD, even the ones with
+    /// their own PLAYPAL, only the top one is used by the engines
+    fn update_palettes(&mut self) -> Result<(), WadError> {
+        let source = match self.entries.iter().rposition(|entry| entry.has_palettes) {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        let palettes = self.entries[source].wad.palettes().clone();
+
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            if index != source {
+                entry.wad.set_palettes(palettes.clone())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve `name` to its effective definition,
+    /// the last one following the load order
+    pub fn resolve(&self, name: &str) -> Option<StackLump<'_>> {
+        self.find(name, |_| true)
+    }
+
+    /// Resolve `name` within `namespace`
+    pub fn resolve_in(&self, name: &str, namespace: &Namespace) -> Option<StackLump<'_>> {
+        self.find(name, |value| value == Some(namespace))
+    }
+
+    /// Find the last lump named `name` whose namespace is accepted by `f`
+    fn find<F>(&self, name: &str, f: F) -> Option<StackLump<'_>>
+    where
+        F: Fn(Option<&Namespace>) -> bool,
+    {
+        for (index, entry) in self.entries.iter().enumerate().rev() {
+            let found = entry
+                .wad
+                .lumps_with_namespace()
+                .filter(|(lump, namespace)| {
+                    lump.metadata().name_ascii() == name && f(namespace.as_ref())
+                })
+                .last();
+
+            if let Some((lump, _)) = found {
+                return Some(StackLump {
+                    lump,
+                    wad: index,
+                    source: &entry.source,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Merge `namespace` across the WADs, like the engine does
+    ///
+    /// A lump keeps the position of its first definition
+    /// but the last definition wins. The markers are skipped.
+    pub fn namespace(&self, namespace: &Namespace) -> Vec<StackLump<'_>> {
+        let mut ret: Vec<StackLump> = Vec::new();
+        let mut names = HashMap::new();
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            for (lump, value) in entry.wad.lumps_with_namespace() {
+                let name = lump.metadata().name_ascii();
+
+                if value.as_ref() != Some(namespace) || Marker::parse(&name).is_some() {
+                    continue;
+                }
+
+                let resolved = StackLump {
+                    lump,
+                    wad: index,
+                    source: &entry.source,
+                };
+
+                match names.get(&name) {
+                    Some(position) => ret[*position] = resolved,
+                    None => {
+                        names.insert(name, ret.len());
+                        ret.push(resolved);
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+}
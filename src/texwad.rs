@@ -0,0 +1,711 @@
+use std::{collections::HashMap, fs, path::Path, rc::Rc};
+
+use crate::{
+    error::WadError,
+    lump::{LumpBuffer, WadSource},
+    lumps::palette::{Palettes, PALETTE_SIZE},
+    models::container::{move_item, Container},
+    tree::{read_err, write_err},
+    wad::{WadInfo, WadKind},
+};
+
+extern crate image;
+
+/// Directory entry size
+pub const TEXWAD_ENTRY_SIZE: usize = 32;
+/// Entry and miptex name size
+pub const TEXWAD_NAME_SIZE: usize = 16;
+/// Miptex header size
+pub const MIPTEX_HEADER_SIZE: usize = 40;
+/// Mip levels of a miptex
+pub const MIP_LEVELS: usize = 4;
+/// Untyped entry
+pub const TYPE_NONE: u8 = 0x00;
+/// Palette entry type (Quake `gfx.wad`)
+pub const TYPE_PALETTE: u8 = 0x40;
+/// Status bar picture entry type
+pub const TYPE_QPIC: u8 = 0x42;
+/// Miptex entry type in a WAD3
+pub const TYPE_MIPTEX_WAD3: u8 = 0x43;
+/// Miptex entry type in a WAD2
+pub const TYPE_MIPTEX: u8 = 0x44;
+/// Pictures extensions read by `TextureWad::from_images`
+const IMAGE_EXT: &[&str] = &["png", "bmp", "gif", "tga", "ppm"];
+
+/// Fail if a texture WAD entry is compressed
+fn check_compression(entry: &TextureEntry) -> Result<(), WadError> {
+    match entry.compression {
+        0 => Ok(()),
+        _ => Err(WadError::Parse(String::from(
+            "Compressed entries are not supported",
+        ))),
+    }
+}
+
+/// Read a little endian `u32` at `pos`
+fn u32_at(bytes: &[u8], pos: usize) -> Result<u32, WadError> {
+    bytes
+        .get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(WadError::InvalidLump)
+}
+
+/// Read a null terminated name
+fn name_at(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|b| **b != 0)
+        .map(|b| *b as char)
+        .collect()
+}
+
+/// Convert `name` into a 16 bytes null terminated name
+fn name_bytes(name: &str) -> Result<[u8; TEXWAD_NAME_SIZE], WadError> {
+    let mut ret = [0; TEXWAD_NAME_SIZE];
+
+    if name.is_empty() || name.len() >= TEXWAD_NAME_SIZE || !name.is_ascii() {
+        return Err(WadError::InvalidLumpName);
+    }
+
+    ret[..name.len()].copy_from_slice(name.as_bytes());
+
+    Ok(ret)
+}
+
+/// File name of an entry, `*` being replaced by `#` like the texture editors
+fn file_name(name: &str) -> String {
+    name.replace('*', "#")
+}
+
+/// Convert palette indexes into RGBA pixels
+///
+/// The index 255 is transparent if `masked` is true
+fn indexes_rgba(indexes: &[u8], pal: &Palettes, masked: bool) -> Result<Vec<u8>, WadError> {
+    let palette = pal
+        .palette()
+        .ok_or(WadError::Parse(String::from("Invalid palette")))?;
+    let mut ret = Vec::with_capacity(indexes.len() * 4);
+
+    for index in indexes {
+        let (r, g, b, _) = palette[*index as usize].into();
+        let a = if masked && *index == 255 { 0 } else { 255 };
+
+        ret.extend([r, g, b, a]);
+    }
+
+    Ok(ret)
+}
+
+/// Save RGBA pixels as a PNG file
+fn save_png(path: &Path, rgba: &[u8], width: u32, height: u32) -> Result<(), WadError> {
+    image::save_buffer(path, rgba, width, height, image::ColorType::Rgba8)
+        .map_err(|_| WadError::Write)
+}
+
+/// A texture WAD directory entry with its content
+#[derive(Clone, Debug)]
+pub struct TextureEntry {
+    /// Name, up to 15 characters
+    pub name: String,
+    /// Entry type (`TYPE_MIPTEX`, `TYPE_QPIC`, etc..)
+    pub kind: u8,
+    /// Compression, 0 means none
+    pub compression: u8,
+    /// Raw content
+    pub buffer: LumpBuffer,
+}
+
+/// A mipmapped texture, its four levels halving the size
+#[derive(Clone)]
+pub struct Miptex {
+    /// Texture name
+    pub name: String,
+    /// Width of the first level
+    pub width: u32,
+    /// Height of the first level
+    pub height: u32,
+    /// Palette indexes of each level
+    pub mips: Vec<Vec<u8>>,
+    /// Embedded palette (WAD3)
+    pub palette: Option<Palettes>,
+}
+
+impl Miptex {
+    /// Parse a miptex, with its palette if `wad3` is true
+    pub fn parse(buffer: &[u8], wad3: bool) -> Result<Self, WadError> {
+        if buffer.len() < MIPTEX_HEADER_SIZE {
+            return Err(WadError::InvalidLump);
+        }
+
+        let name = name_at(&buffer[..TEXWAD_NAME_SIZE]);
+        let width = u32_at(buffer, 16)?;
+        let height = u32_at(buffer, 20)?;
+        let mut mips = Vec::with_capacity(MIP_LEVELS);
+        let mut end = MIPTEX_HEADER_SIZE;
+
+        for level in 0..MIP_LEVELS {
+            let offset = u32_at(buffer, 24 + level * 4)? as usize;
+            let mip_end = ((width >> level) as usize)
+                .checked_mul((height >> level) as usize)
+                .and_then(|size| offset.checked_add(size))
+                .ok_or(WadError::InvalidLump)?;
+            let mip = buffer.get(offset..mip_end).ok_or(WadError::InvalidLump)?;
+
+            mips.push(mip.to_vec());
+            end = end.max(mip_end);
+        }
+
+        // The colors count precedes the palette
+        let palette = match wad3 {
+            true => {
+                let colors = buffer
+                    .get(end..end + 2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+                    .ok_or(WadError::InvalidLump)?;
+                let mut bytes = buffer
+                    .get(end + 2..end + 2 + colors * 3)
+                    .ok_or(WadError::InvalidLump)?
+                    .to_vec();
+
+                bytes.resize(PALETTE_SIZE, 0);
+                Some(Palettes::from_bytes(&bytes)?)
+            }
+            false => None,
+        };
+
+        Ok(Self {
+            name,
+            width,
+            height,
+            mips,
+            palette,
+        })
+    }
+
+    /// Build a miptex from RGBA pixels, the levels being downscaled
+    ///
+    /// The colors are mapped to `pal`. If `embed` is true (WAD3), the
+    /// palette is embedded and it is built from the pixels when
+    /// they have at most 256 colors
+    pub fn from_rgba(
+        name: &str,
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+        pal: &Palettes,
+        embed: bool,
+    ) -> Result<Self, WadError> {
+        if width == 0 || height == 0 || !width.is_multiple_of(16) || !height.is_multiple_of(16) {
+            return Err(WadError::Parse(String::from(
+                "The texture size must be a multiple of 16",
+            )));
+        }
+
+        let size = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|size| size.checked_mul(4))
+            .ok_or(WadError::InvalidLump)?;
+
+        if rgba.len() < size {
+            return Err(WadError::InvalidLump);
+        }
+
+        name_bytes(name)?;
+
+        let mut colors: Vec<(u8, u8, u8)> = Vec::new();
+
+        if embed {
+            for pixel in rgba.chunks(4) {
+                let color = (pixel[0], pixel[1], pixel[2]);
+
+                if colors.len() <= 256 && !colors.contains(&color) {
+                    colors.push(color);
+                }
+            }
+        }
+
+        let own_palette = match colors.len() {
+            1..=256 => {
+                let mut bytes: Vec<u8> = colors.iter().flat_map(|(r, g, b)| [*r, *g, *b]).collect();
+
+                bytes.resize(PALETTE_SIZE, 0);
+                Some(Palettes::from_bytes(&bytes)?)
+            }
+            _ => None,
+        };
+        let used = own_palette.as_ref().unwrap_or(pal);
+        let mut cache: HashMap<(u8, u8, u8), u8> = HashMap::new();
+        let mut mips = Vec::with_capacity(MIP_LEVELS);
+
+        for level in 0..MIP_LEVELS {
+            let step = 1 << level;
+            let (w, h) = (width >> level, height >> level);
+            let mut mip = Vec::with_capacity(w as usize * h as usize);
+
+            for y in 0..h {
+                for x in 0..w {
+                    // Average of the covered pixels
+                    let mut sum = [0u32; 3];
+
+                    for dy in 0..step {
+                        for dx in 0..step {
+                            let (row, column) =
+                                ((y * step + dy) as usize, (x * step + dx) as usize);
+                            let pos = (row * width as usize + column) * 4;
+
+                            for (i, value) in sum.iter_mut().enumerate() {
+                                *value += rgba[pos + i] as u32;
+                            }
+                        }
+                    }
+
+                    let count = step * step;
+                    let color = (
+                        (sum[0] / count) as u8,
+                        (sum[1] / count) as u8,
+                        (sum[2] / count) as u8,
+                    );
+                    let index = match cache.get(&color) {
+                        Some(value) => *value,
+                        None => {
+                            let value = used
+                                .nearest(color)
+                                .ok_or(WadError::Parse(String::from("Invalid palette")))?;
+
+                            cache.insert(color, value);
+                            value
+                        }
+                    };
+
+                    mip.push(index);
+                }
+            }
+
+            mips.push(mip);
+        }
+
+        let palette = match embed {
+            true => Some(used.clone()),
+            false => None,
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            width,
+            height,
+            mips,
+            palette,
+        })
+    }
+
+    /// Encode the miptex, with its palette if any
+    pub fn encode(&self) -> Result<Vec<u8>, WadError> {
+        let mut ret = Vec::new();
+        let mut offset = MIPTEX_HEADER_SIZE as u32;
+
+        ret.extend(name_bytes(&self.name)?);
+        ret.extend(self.width.to_le_bytes());
+        ret.extend(self.height.to_le_bytes());
+
+        for mip in self.mips.iter() {
+            ret.extend(offset.to_le_bytes());
+            offset += mip.len() as u32;
+        }
+
+        for mip in self.mips.iter() {
+            ret.extend(mip);
+        }
+
+        // 256 colors then a padding
+        if let Some(palette) = &self.palette {
+            let colors = palette
+                .palette()
+                .ok_or(WadError::Parse(String::from("Invalid palette")))?;
+
+            ret.extend((colors.len() as u16).to_le_bytes());
+
+            for color in colors {
+                let (r, g, b, _) = color.into();
+
+                ret.extend([r, g, b]);
+            }
+
+            ret.extend([0; 2]);
+        }
+
+        Ok(ret)
+    }
+
+    /// Get the RGBA pixels of a level, with the embedded palette or `pal`
+    ///
+    /// The index 255 is transparent for the Half-Life `{` textures
+    pub fn rgba(&self, level: usize, pal: &Palettes) -> Result<Vec<u8>, WadError> {
+        let mip = self.mips.get(level).ok_or(WadError::InvalidLump)?;
+        let masked = self.palette.is_some() && self.name.starts_with('{');
+
+        indexes_rgba(mip, self.palette.as_ref().unwrap_or(pal), masked)
+    }
+}
+
+/// Quake WAD2 and Half-Life WAD3 texture archive
+///
+/// The entries have 16 bytes names, a type and a compression.
+/// A WAD2 needs the Quake palette, from its `PALETTE` entry
+/// (`gfx.wad`) or set with `set_palettes` (`gfx/palette.lmp`),
+/// the WAD3 textures embed their own palette
+pub struct TextureWad {
+    /// WAD2 or WAD3
+    kind: WadKind,
+    /// Entries, following the directory order
+    entries: Vec<TextureEntry>,
+    /// Palette of the textures without their own
+    palettes: Palettes,
+}
+
+impl TextureWad {
+    pub fn new(kind: WadKind) -> Self {
+        Self {
+            kind,
+            entries: Vec::new(),
+            palettes: Palettes::new(),
+        }
+    }
+
+    /// Parse a WAD2 or a WAD3 buffer
+    pub fn load<T: Into<Vec<u8>>>(&mut self, buffer: T) -> Result<(), WadError> {
+        let src: WadSource = Rc::new(buffer.into());
+        let bytes = (*src).as_ref();
+
+        if bytes.len() < 12 {
+            return Err(WadError::Load("The file size is too small."));
+        }
+
+        let info = WadInfo::from(&bytes[0..12]);
+
+        if !info.kind.is_texture_wad() {
+            return Err(WadError::Type("The file is not a WAD2 or WAD3 file."));
+        }
+
+        self.kind = info.kind;
+        self.entries.clear();
+
+        for num in 0..info.num_lumps.max(0) as usize {
+            let index = info.dir_pos.max(0) as usize + num * TEXWAD_ENTRY_SIZE;
+            let entry = bytes
+                .get(index..index + TEXWAD_ENTRY_SIZE)
+                .ok_or(WadError::Parse(String::from("Invalid directory")))?;
+            let pos = u32_at(entry, 0)? as usize;
+            let disk_size = u32_at(entry, 4)? as usize;
+            let buffer =
+                LumpBuffer::shared(src.clone(), pos, disk_size).ok_or(WadError::InvalidLump)?;
+
+            self.entries.push(TextureEntry {
+                name: name_at(&entry[16..]),
+                kind: entry[12],
+                compression: entry[13],
+                buffer,
+            });
+        }
+
+        // Quake gfx.wad
+        if let Some(entry) = self.entries.iter().find(|e| e.kind == TYPE_PALETTE) {
+            self.palettes = Palettes::from_bytes(&entry.buffer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read then parse the file at `path`
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        self.load(fs::read(path).map_err(read_err)?)
+    }
+
+    /// WAD2 or WAD3
+    pub fn kind(&self) -> WadKind {
+        self.kind
+    }
+
+    /// Get the entries
+    pub fn entries(&self) -> &[TextureEntry] {
+        &self.entries
+    }
+
+    /// Get an entry by its name, ignoring the case
+    pub fn entry(&self, name: &str) -> Option<&TextureEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Get the palette used by the textures without their own
+    pub fn palettes(&self) -> &Palettes {
+        &self.palettes
+    }
+
+    /// Set the palette used by the textures without their own,
+    /// like the Quake `gfx/palette.lmp`
+    pub fn set_palettes(&mut self, value: Palettes) {
+        self.palettes = value;
+    }
+
+    /// Entry type of the textures
+    fn miptex_type(&self) -> u8 {
+        match self.kind {
+            WadKind::Wad3 => TYPE_MIPTEX_WAD3,
+            _ => TYPE_MIPTEX,
+        }
+    }
+
+    /// Decode the texture `name`
+    pub fn miptex(&self, name: &str) -> Result<Miptex, WadError> {
+        let entry = self.entry(name).ok_or(WadError::InvalidLumpName)?;
+
+        if entry.kind != self.miptex_type() {
+            return Err(WadError::Type("The entry is not a texture."));
+        }
+
+        check_compression(entry)?;
+        Miptex::parse(&entry.buffer, self.kind == WadKind::Wad3)
+    }
+
+    /// Add an entry, replacing the one with the same name
+    pub fn add(&mut self, entry: TextureEntry) -> Result<(), WadError> {
+        name_bytes(&entry.name)?;
+
+        match self
+            .entries
+            .iter()
+            .position(|e| e.name.eq_ignore_ascii_case(&entry.name))
+        {
+            Some(index) => self.entries[index] = entry,
+            None => self.entries.push(entry),
+        }
+
+        Ok(())
+    }
+
+    /// Add a texture
+    pub fn add_miptex(&mut self, miptex: &Miptex) -> Result<(), WadError> {
+        self.add(TextureEntry {
+            name: miptex.name.clone(),
+            kind: self.miptex_type(),
+            compression: 0,
+            buffer: miptex.encode()?.into(),
+        })
+    }
+
+    /// Add a texture from a picture file (PNG, BMP, etc..)
+    ///
+    /// The WAD3 textures get their own palette
+    pub fn add_image(&mut self, name: &str, file: &[u8]) -> Result<(), WadError> {
+        let image = image::load_from_memory(file)
+            .map_err(|e| WadError::Parse(e.to_string()))?
+            .to_rgba8();
+        let miptex = Miptex::from_rgba(
+            name,
+            image.width(),
+            image.height(),
+            image.as_raw(),
+            &self.palettes,
+            self.kind == WadKind::Wad3,
+        )?;
+
+        self.add_miptex(&miptex)
+    }
+
+    /// Remove an entry, returns true if it existed
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+
+        self.entries
+            .retain(|entry| !entry.name.eq_ignore_ascii_case(name));
+        self.entries.len() != len
+    }
+
+    /// Build a texture WAD from the pictures in `dir`, named after the files
+    pub fn from_images<P: AsRef<Path>>(
+        kind: WadKind,
+        dir: P,
+        palettes: Palettes,
+    ) -> Result<Self, WadError> {
+        let mut ret = Self::new(kind);
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .map_err(read_err)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| IMAGE_EXT.contains(&ext.to_ascii_lowercase().as_str()))
+            })
+            .collect();
+
+        paths.sort();
+        ret.set_palettes(palettes);
+
+        for path in paths {
+            let name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or(WadError::InvalidLumpName)?
+                .replace('#', "*");
+            let file = fs::read(&path).map_err(read_err)?;
+
+            ret.add_image(&name, &file)?;
+        }
+
+        Ok(ret)
+    }
+
+    /// Extract the entries in `dir`
+    ///
+    /// The textures (first level) and the pictures are written
+    /// as PNG files, the other entries are written raw (`.lmp`)
+    pub fn extract<P: AsRef<Path>>(&self, dir: P) -> Result<(), WadError> {
+        let dir = dir.as_ref();
+
+        fs::create_dir_all(dir).map_err(write_err)?;
+
+        for entry in self.entries.iter() {
+            let path = dir.join(file_name(&entry.name));
+            let picture = match (entry.kind, entry.compression) {
+                (_, 1..) => None,
+                (TYPE_QPIC, _) => {
+                    let width = u32_at(&entry.buffer, 0)?;
+                    let height = u32_at(&entry.buffer, 4)?;
+                    let end = (width as usize)
+                        .checked_mul(height as usize)
+                        .and_then(|size| size.checked_add(8))
+                        .ok_or(WadError::InvalidLump)?;
+                    let indexes = entry.buffer.get(8..end).ok_or(WadError::InvalidLump)?;
+
+                    Some((indexes_rgba(indexes, &self.palettes, true)?, width, height))
+                }
+                (kind, _) if kind == self.miptex_type() => {
+                    let miptex = Miptex::parse(&entry.buffer, self.kind == WadKind::Wad3)?;
+
+                    Some((miptex.rgba(0, &self.palettes)?, miptex.width, miptex.height))
+                }
+                _ => None,
+            };
+
+            match picture {
+                Some((rgba, width, height)) => {
+                    save_png(&path.with_extension("png"), &rgba, width, height)?
+                }
+                None => fs::write(path.with_extension("lmp"), &*entry.buffer).map_err(write_err)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the WAD2 or WAD3 buffer
+    pub fn build(&self) -> Result<Vec<u8>, WadError> {
+        let mut ret: Vec<u8> = self.kind.into();
+        let mut dir = Vec::with_capacity(self.entries.len() * TEXWAD_ENTRY_SIZE);
+        let data_size: usize = self.entries.iter().map(|e| e.buffer.len()).sum();
+
+        ret.extend((self.entries.len() as i32).to_le_bytes());
+        ret.extend(((12 + data_size) as i32).to_le_bytes());
+
+        for entry in self.entries.iter() {
+            let size = entry.buffer.len() as i32;
+
+            dir.extend((ret.len() as i32).to_le_bytes());
+            dir.extend(size.to_le_bytes());
+            dir.extend(size.to_le_bytes());
+            dir.extend([entry.kind, entry.compression, 0, 0]);
+            dir.extend(name_bytes(&entry.name)?);
+            ret.extend(&*entry.buffer);
+        }
+
+        ret.extend(dir);
+
+        Ok(ret)
+    }
+
+    /// Write the texture WAD at `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), WadError> {
+        fs::write(path, self.build()?).map_err(write_err)
+    }
+}
+
+impl Container for TextureWad {
+    fn load_bytes(&mut self, buffer: Vec<u8>) -> Result<(), WadError> {
+        self.load(buffer)
+    }
+
+    fn entries_len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn entry_name(&self, index: usize) -> Option<String> {
+        self.entries.get(index).map(|entry| entry.name.clone())
+    }
+
+    fn entry_buffer(&self, index: usize) -> Option<LumpBuffer> {
+        self.entries.get(index).map(|entry| entry.buffer.clone())
+    }
+
+    fn set_entry_buffer(&mut self, index: usize, buffer: &[u8]) -> Result<(), WadError> {
+        let entry = self.entries.get_mut(index).ok_or(WadError::InvalidLump)?;
+
+        entry.buffer = buffer.into();
+
+        Ok(())
+    }
+
+    /// The entry is typed as a texture if it parses as one
+    fn insert_entry(&mut self, index: usize, name: &str, buffer: &[u8]) -> Result<(), WadError> {
+        name_bytes(name)?;
+
+        if index > self.entries.len() {
+            return Err(WadError::InvalidLump);
+        }
+
+        let kind = match Miptex::parse(buffer, self.kind == WadKind::Wad3) {
+            Ok(_) => self.miptex_type(),
+            Err(_) => TYPE_NONE,
+        };
+
+        self.entries.insert(
+            index,
+            TextureEntry {
+                name: name.to_string(),
+                kind,
+                compression: 0,
+                buffer: buffer.into(),
+            },
+        );
+
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, index: usize) -> Result<(), WadError> {
+        if index >= self.entries.len() {
+            return Err(WadError::InvalidLump);
+        }
+
+        self.entries.remove(index);
+
+        Ok(())
+    }
+
+    fn rename_entry(&mut self, index: usize, name: &str) -> Result<(), WadError> {
+        name_bytes(name)?;
+
+        let entry = self.entries.get_mut(index).ok_or(WadError::InvalidLump)?;
+
+        entry.name = name.to_string();
+
+        Ok(())
+    }
+
+    fn move_entry(&mut self, from: usize, to: usize) -> Result<(), WadError> {
+        move_item(&mut self.entries, from, to)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, WadError> {
+        self.build()
+    }
+}
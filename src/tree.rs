@@ -0,0 +1,449 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    classifier::MIDI_MAGIC,
+    error::WadError,
+    lump::{LumpAdd, LumpData, LumpInfo, LumpKind},
+    lumps::{
+        flat::FLAT_SIZE,
+        palette::{Palettes, PALETTE_SIZE},
+        patch::{DoomImage, DoomImageInfo},
+        sound::{dmx_from_wav, DoomSound},
+    },
+    models::lump::Lump,
+    namespace::Marker,
+    wad::{Wad, WadKind},
+    writer::WadWriter,
+};
+
+extern crate image;
+
+/// Manifest file name, within the tree root
+pub const MANIFEST: &str = "manifest.txt";
+/// Palette used by the converted pictures, within the tree root
+pub const TREE_PALETTE: &str = "palette.lmp";
+
+/// File format of a lump within the tree
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeFormat {
+    /// Raw content
+    Raw,
+    /// DOOM picture as a PNG file
+    Patch,
+    /// Flat as a PNG file
+    Flat,
+    /// DMX sound as a WAV file
+    Wav,
+}
+
+impl TreeFormat {
+    fn as_str(&self) -> &str {
+        match self {
+            Self::Raw => "raw",
+            Self::Patch => "patch",
+            Self::Flat => "flat",
+            Self::Wav => "wav",
+        }
+    }
+
+    pub(crate) fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "raw" => Some(Self::Raw),
+            "patch" => Some(Self::Patch),
+            "flat" => Some(Self::Flat),
+            "wav" => Some(Self::Wav),
+            _ => None,
+        }
+    }
+}
+
+/// Palette indexes of a picture, `None` being transparent
+pub(crate) type Pixels = Vec<Option<u8>>;
+
+/// A lump converted into a file
+pub(crate) struct TreeFile {
+    pub content: Vec<u8>,
+    pub format: TreeFormat,
+    /// Picture offsets
+    pub offsets: Option<(u16, u16)>,
+}
+
+/// Escape a lump name so it can be used as a file name and in the manifest
+fn escape(name: &[u8]) -> String {
+    name.iter()
+        .take_while(|c| **c != 0)
+        .map(|c| match *c {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' => (*c as char).to_string(),
+            _ => format!("%{:02X}", c),
+        })
+        .collect()
+}
+
+/// Reverse `escape`
+fn unescape(value: &str) -> Result<[u8; 8], WadError> {
+    let bytes = value.as_bytes();
+    let mut ret = [0x00; 8];
+    let (mut i, mut n) = (0, 0);
+
+    while i < bytes.len() && n < ret.len() {
+        if bytes[i] == b'%' {
+            let hex = value.get(i + 1..i + 3).unwrap_or_default();
+
+            ret[n] = u8::from_str_radix(hex, 16).map_err(|_| WadError::InvalidLumpName)?;
+            i += 3;
+        } else {
+            ret[n] = bytes[i];
+            i += 1;
+        }
+
+        n += 1;
+    }
+
+    Ok(ret)
+}
+
+/// Path of a manifest file within `dir`, the components
+/// leaving it (root, `..`) are rejected
+fn tree_path(dir: &Path, path: &str) -> Result<PathBuf, WadError> {
+    let mut ret = dir.to_path_buf();
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(value) => ret.push(value),
+            _ => return Err(WadError::Parse(format!("Invalid manifest path {}", path))),
+        }
+    }
+
+    Ok(ret)
+}
+
+pub(crate) fn read_err(e: std::io::Error) -> WadError {
+    WadError::Read(e.to_string())
+}
+
+pub(crate) fn write_err(_: std::io::Error) -> WadError {
+    WadError::Write
+}
+
+/// Convert a lump into a file, if the conversion is lossless
+///
+pub(crate) fn convert(data: &LumpData, pal: &Palettes) -> Option<TreeFile> {
+    let colors = pal.palette()?;
+    let rgba = |pixels: &[Option<u8>]| -> Vec<u8> {
+        pixels
+            .iter()
+            .flat_map(|p| match p {
+                Some(index) => {
+                    let (r, g, b, a) = colors[*index as usize].into();
+                    [r, g, b, a]
+                }
+                None => [0, 0, 0, 0],
+            })
+            .collect()
+    };
+    let png = |pixels: &[Option<u8>], width: u32, height: u32| -> Option<Vec<u8>> {
+        let mut ret = Vec::new();
+
+        image::write_buffer_with_format(
+            &mut Cursor::new(&mut ret),
+            &rgba(pixels),
+            width,
+            height,
+            image::ColorType::Rgba8,
+            image::ImageOutputFormat::Png,
+        )
+        .ok()?;
+
+        Some(ret)
+    };
+
+    match data.kind {
+        LumpKind::Patch => {
+            let info = DoomImageInfo::from(&*data.buffer);
+            let image = DoomImage::new(pal.clone(), data.clone());
+            let pixels = image.pixels().ok()?;
+            let file = png(pixels, info.width as u32, info.height as u32)?;
+            let (_, back) = pixels_from_png(&file, pal, true, None).ok()?;
+
+            (back == *pixels).then_some(TreeFile {
+                content: file,
+                format: TreeFormat::Patch,
+                offsets: Some((info.left, info.top)),
+            })
+        }
+        LumpKind::Flat if data.buffer.len() == FLAT_SIZE => {
+            let pixels: Pixels = data.buffer.iter().map(|p| Some(*p)).collect();
+            let file = png(&pixels, 64, 64)?;
+            let (_, back) = pixels_from_png(&file, pal, false, None).ok()?;
+
+            (back == pixels).then_some(TreeFile {
+                content: file,
+                format: TreeFormat::Flat,
+                offsets: None,
+            })
+        }
+        LumpKind::Sound => {
+            let file = DoomSound::new(data.clone()).wav().ok()?;
+
+            (dmx_from_wav(&file).ok()? == *data.buffer).then_some(TreeFile {
+                content: file,
+                format: TreeFormat::Wav,
+                offsets: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Decode a picture file into palette indexes with its dimensions
+///
+/// If `alpha` is true, the transparent pixels and
+/// the ones matching the color `key` are `None`
+pub(crate) fn pixels_from_png(
+    file: &[u8],
+    pal: &Palettes,
+    alpha: bool,
+    key: Option<(u8, u8, u8)>,
+) -> Result<((u16, u16), Pixels), WadError> {
+    let image = image::load_from_memory(file)
+        .map_err(|e| WadError::Parse(e.to_string()))?
+        .to_rgba8();
+    let mut cache: HashMap<(u8, u8, u8), Option<u8>> = HashMap::new();
+    let pixels = image
+        .pixels()
+        .map(|p| {
+            let [r, g, b, a] = p.0;
+
+            if alpha && (a < 128 || key == Some((r, g, b))) {
+                return None;
+            }
+
+            *cache
+                .entry((r, g, b))
+                .or_insert_with(|| pal.nearest((r, g, b)))
+        })
+        .collect();
+
+    Ok(((image.width() as u16, image.height() as u16), pixels))
+}
+
+/// Convert a file of the tree `format` into a lump buffer,
+/// `offsets` being the picture offsets
+pub(crate) fn encode_file(
+    file: Vec<u8>,
+    format: TreeFormat,
+    pal: &Palettes,
+    offsets: (i16, i16),
+) -> Result<Vec<u8>, WadError> {
+    let ret = match format {
+        TreeFormat::Raw => file,
+        TreeFormat::Wav => dmx_from_wav(&file)?,
+        TreeFormat::Flat => pixels_from_png(&file, pal, false, None)?
+            .1
+            .iter()
+            .map(|p| p.unwrap_or_default())
+            .collect(),
+        TreeFormat::Patch => {
+            let ((width, height), pixels) = pixels_from_png(&file, pal, true, None)?;
+            let info = DoomImageInfo {
+                width,
+                height,
+                left: offsets.0 as u16,
+                top: offsets.1 as u16,
+            };
+
+            DoomImage::encode(info, &pixels)?
+        }
+    };
+
+    Ok(ret)
+}
+
+/// Default file extension of a raw lump
+fn extension(data: &LumpData) -> &str {
+    match data.kind {
+        LumpKind::Png => "png",
+        LumpKind::Music if data.buffer.starts_with(&MIDI_MAGIC) => "mid",
+        LumpKind::Music => "mus",
+        _ => "lmp",
+    }
+}
+
+/// Extract `wad` into the directory tree `dir`
+///
+/// The lumps are written in a folder per namespace or map,
+/// converted if `convert` is true and the conversion is lossless
+pub fn extract(wad: &Wad, dir: &Path, convert_files: bool) -> Result<(), WadError> {
+    let wad_dir = wad.dir();
+    let namespaces = wad_dir.namespaces();
+    let maps = wad_dir.maps();
+    let pal = wad.palettes();
+    let mut manifest = String::from("# tinywad tree manifest\n");
+    let mut used = HashSet::new();
+
+    fs::create_dir_all(dir).map_err(write_err)?;
+
+    let kind = match wad.info().kind {
+        WadKind::Iwad => "IWAD",
+        _ => "PWAD",
+    };
+
+    manifest.push_str(&format!("kind {}\n", kind));
+
+    // The current palette is needed to convert the pictures back
+    if let (true, Some(colors)) = (convert_files, pal.palette()) {
+        let bytes: Vec<u8> = colors
+            .iter()
+            .flat_map(|color| {
+                let (r, g, b, _) = (*color).into();
+                [r, g, b]
+            })
+            .collect();
+
+        fs::write(dir.join(TREE_PALETTE), bytes).map_err(write_err)?;
+        manifest.push_str(&format!("palette {}\n", TREE_PALETTE));
+    }
+
+    for (i, lump) in wad_dir.lumps.iter().enumerate() {
+        let data = lump.data();
+
+        if !data.metadata.state.is_alive() {
+            continue;
+        }
+
+        let name = escape(&data.metadata.name);
+
+        if Marker::parse(&data.metadata.name_ascii()).is_some() {
+            manifest.push_str(&format!("marker {}\n", name));
+            continue;
+        }
+
+        let folder = match (&namespaces[i], &maps[i]) {
+            (Some(namespace), _) => namespace.to_string(),
+            (None, Some(map)) => format!("maps/{}", map),
+            (None, None) => String::from("lumps"),
+        };
+
+        let converted = match convert_files {
+            true => convert(&data, pal),
+            false => None,
+        };
+        let TreeFile {
+            content,
+            format,
+            offsets,
+        } = converted.unwrap_or_else(|| TreeFile {
+            content: data.buffer.to_vec(),
+            format: TreeFormat::Raw,
+            offsets: None,
+        });
+        let ext = match format {
+            TreeFormat::Raw => extension(&data),
+            TreeFormat::Patch | TreeFormat::Flat => "png",
+            TreeFormat::Wav => "wav",
+        };
+
+        // Duplicated names within a folder get a suffix
+        let mut path = format!("{}/{}.{}", folder, name, ext);
+        let mut n = 1;
+
+        while !used.insert(path.clone()) {
+            path = format!("{}/{}~{}.{}", folder, name, n, ext);
+            n += 1;
+        }
+
+        fs::create_dir_all(dir.join(&folder)).map_err(write_err)?;
+        fs::write(dir.join(&path), content).map_err(write_err)?;
+
+        manifest.push_str(&format!("lump {} {} {}", name, path, format.as_str()));
+
+        if let Some((left, top)) = offsets {
+            manifest.push_str(&format!(" {},{}", left as i16, top as i16));
+        }
+
+        // Jaguar lump, compressed again when building
+        if data.metadata.compressed {
+            manifest.push_str(" compressed");
+        }
+
+        manifest.push('\n');
+    }
+
+    fs::write(dir.join(MANIFEST), manifest).map_err(write_err)
+}
+
+/// Rebuild a WAD file from the directory tree `dir`
+pub fn build(dir: &Path) -> Result<Vec<u8>, WadError> {
+    let manifest = fs::read_to_string(dir.join(MANIFEST)).map_err(read_err)?;
+    let mut writer: Option<WadWriter<Cursor<Vec<u8>>>> = None;
+    let mut pal = Palettes::new();
+
+    for (n, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || WadError::Parse(format!("Invalid manifest line {}", n + 1));
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // The header comes first, once
+        if let ["kind", kind] = fields[..] {
+            let kind = match WadKind::from(kind.as_bytes()) {
+                value @ (WadKind::Iwad | WadKind::Pwad) if writer.is_none() => value,
+                _ => return Err(invalid()),
+            };
+
+            writer = Some(WadWriter::new(Cursor::new(Vec::new()), kind)?);
+            continue;
+        }
+
+        if let ["palette", path] = fields[..] {
+            let buffer = fs::read(tree_path(dir, path)?).map_err(read_err)?;
+            let size = (buffer.len() / PALETTE_SIZE * PALETTE_SIZE) as i32;
+
+            pal.set_data(LumpData {
+                buffer: buffer.into(),
+                metadata: LumpInfo::new(0, size, LumpAdd::name_bytes("PLAYPAL")),
+                ..Default::default()
+            });
+            pal.parse()?;
+            continue;
+        }
+
+        let writer = writer.as_mut().ok_or_else(invalid)?;
+
+        match fields[..] {
+            ["marker", name] => {
+                writer.write_marker(unescape(name)?);
+            }
+            ["lump", name, path, format, ref options @ ..] => {
+                let file = fs::read(tree_path(dir, path)?).map_err(read_err)?;
+                let format = TreeFormat::from_str(format).ok_or_else(invalid)?;
+                let compressed = options.contains(&"compressed");
+                let (left, top) = match options.iter().find(|o| **o != "compressed") {
+                    Some(value) => value.split_once(',').ok_or_else(invalid)?,
+                    None => ("0", "0"),
+                };
+                let offset = |v: &str| v.parse::<i16>().map_err(|_| invalid());
+                let buffer = encode_file(file, format, &pal, (offset(left)?, offset(top)?))?;
+
+                match compressed {
+                    true => writer.write_lump_compressed(unescape(name)?, &buffer)?,
+                    false => writer.write_lump(unescape(name)?, &buffer)?,
+                };
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    match writer {
+        Some(writer) => Ok(writer.finish()?.into_inner()),
+        None => Err(WadError::Parse(String::from("Missing WAD kind"))),
+    }
+}
@@ -1,19 +1,36 @@
 use std::{
-    fs::{self},
+    collections::HashMap,
+    fs::{self, File},
+    io::{Cursor, Seek, Write},
     path::Path,
+    rc::Rc,
     str::FromStr,
 };
 
-use regex::{Error, Regex};
+use memmap2::Mmap;
+use regex::Regex;
 
 use crate::{
+    address::LumpAddress,
+    classifier::{Classification, ClassifyInput, Confidence, LumpClassifier, MIDI_MAGIC},
+    deutex,
+    diff::WadDiff,
     dir::LumpsDirectory,
     error::WadError,
-    lump::{LumpAdd, LumpData, LumpInfo, LumpKind},
-    lumps::unknown::Unknown,
-    models::{lump::Lump, operation::WadOp},
-    output::WadOutput,
+    history::{invert_order, Edit, EditLog, History, Inverse},
+    lump::{
+        LumpAdd, LumpAddKind, LumpBuffer, LumpData, LumpHandle, LumpInfo, LumpKind, LumpPosition,
+        WadSource,
+    },
+    lumps::{music::mus::MUS_MAGIC, palette::Palettes},
+    models::{container::Container, lump::Lump, operation::WadOp},
+    namespace::{Marker, Namespace, NamespaceStack},
+    output::{OutputOptions, WadOutput},
     properties::file::PathWrap,
+    query::{LumpQuery, LumpSet},
+    registry::LumpRegistry,
+    tree::{self, TreeFormat},
+    writer,
 };
 
 /// Default re_name used by the `Wad` struct
@@ -22,12 +39,20 @@ pub const DEFAULT_RE_NAME: &'static str = r".*";
 pub const MAGIC_IWAD: &[u8] = &[0x49, 0x57, 0x41, 0x44];
 /// Pwad kind
 pub const MAGIC_PWAD: &[u8] = &[0x50, 0x57, 0x41, 0x44];
+/// Quake texture WAD kind
+pub const MAGIC_WAD2: &[u8] = &[0x57, 0x41, 0x44, 0x32];
+/// Half-Life texture WAD kind
+pub const MAGIC_WAD3: &[u8] = &[0x57, 0x41, 0x44, 0x33];
 
 /// WAD types
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum WadKind {
     Iwad,
     Pwad,
+    /// Quake textures, see `TextureWad`
+    Wad2,
+    /// Half-Life textures, see `TextureWad`
+    Wad3,
     Unknown,
 }
 
@@ -36,16 +61,27 @@ impl From<&[u8]> for WadKind {
         match magic {
             MAGIC_IWAD => Self::Iwad,
             MAGIC_PWAD => Self::Pwad,
+            MAGIC_WAD2 => Self::Wad2,
+            MAGIC_WAD3 => Self::Wad3,
             _ => Self::Unknown,
         }
     }
 }
 
+impl WadKind {
+    /// Returns true for the texture WADs (WAD2 and WAD3)
+    pub fn is_texture_wad(&self) -> bool {
+        matches!(self, Self::Wad2 | Self::Wad3)
+    }
+}
+
 impl Into<Vec<u8>> for WadKind {
     fn into(self) -> Vec<u8> {
         match self {
             Self::Iwad => MAGIC_IWAD.to_vec(),
             Self::Pwad => MAGIC_PWAD.to_vec(),
+            Self::Wad2 => MAGIC_WAD2.to_vec(),
+            Self::Wad3 => MAGIC_WAD3.to_vec(),
             Self::Unknown => vec![0x00; 4],
         }
     }
@@ -127,33 +163,94 @@ pub struct Wad {
     /// File type (IWAD or PWAD)
     info: WadInfo,
     /// Buffer a.k.a the source file content
-    src: Vec<u8>,
+    ///
+    /// Shared with the lumps that have not been updated
+    src: WadSource,
     /// Filter (regex)
     re_name: Regex,
     /// Lumps directory
     dir: LumpsDirectory,
+    /// Options used when building the output
+    output_options: OutputOptions,
+    /// Applied and undone edits
+    history: History,
 }
 
 impl Wad {
     pub fn new() -> Self {
         Self {
             info: WadInfo::default(),
-            src: Vec::new(),
+            src: Rc::new(Vec::<u8>::new()),
             re_name: Regex::new(DEFAULT_RE_NAME).unwrap(),
             dir: LumpsDirectory::new(),
+            output_options: OutputOptions::default(),
+            history: History::new(),
         }
     }
 
+    /// Create an empty IWAD or PWAD, built from scratch with
+    /// `WadOp::add_lump_raw` and the typed `self.add_*` methods
+    ///
+    /// The images need a palette, add a `PLAYPAL` lump first
+    /// or call `self.set_palettes`
+    pub fn new_empty(kind: WadKind) -> Self {
+        let mut ret = Self::new();
+
+        ret.set_kind(kind);
+        ret
+    }
+
     /// Set a palette that will be applied on every lump
     pub fn set_palette(&mut self, value: usize) {
         self.dir.set_palette(value);
     }
 
+    /// Get the WAD header
+    pub fn info(&self) -> WadInfo {
+        self.info
+    }
+
+    /// Get the lumps directory
+    pub(crate) fn dir(&self) -> &LumpsDirectory {
+        &self.dir
+    }
+
+    /// Get the palettes used by the image lumps
+    pub fn palettes(&self) -> &Palettes {
+        &self.dir.pal
+    }
+
+    /// Set the palettes used by the image lumps
+    ///
+    /// Useful for a PWAD without PLAYPAL, a reload gives
+    /// the priority back to its own PLAYPAL
+    pub fn set_palettes(&mut self, value: Palettes) -> Result<(), WadError> {
+        self.dir.set_palettes(value)
+    }
+
+    /// Get the classifier used to find the lumps kind
+    ///
+    /// The changes apply on the next `self.load` or `self.reload`
+    pub fn classifier_mut(&mut self) -> &mut LumpClassifier {
+        &mut self.dir.classifier
+    }
+
+    /// Get the caller lump decoders, used by the next loads
+    pub fn registry_mut(&mut self) -> &mut LumpRegistry {
+        &mut self.dir.registry
+    }
+
     /// Set the WAD kind (IWAD/PWAD/UNKOWN)
     pub fn set_kind(&mut self, value: WadKind) {
         self.info.kind = value;
     }
 
+    /// Set the options used by `self.save` and `self.write`
+    /// (directory position, alignment, deduplication)
+    pub fn set_output_options(&mut self, value: OutputOptions) {
+        self.output_options = value;
+    }
+
     /// Set `self.re_name`
     pub fn select(&mut self, value: &str) {
         let regex = Regex::new(value);
@@ -166,69 +263,786 @@ impl Wad {
 
     /// Parse a buffer into lumps entries
     pub fn load<T: Into<Vec<u8>>>(&mut self, buffer: T) -> Result<(), WadError> {
-        let buffer = buffer.into();
+        self.src = Rc::new(buffer.into());
+
+        self.parse_src()
+    }
+
+    /// Parse the header and the directory of `self.src`
+    fn parse_src(&mut self) -> Result<(), WadError> {
+        let bytes = (*self.src).as_ref();
 
         // WAD informations
         // Check if the WAD is valid
-        if buffer.len() < 12 {
+        if bytes.len() < 12 {
             return Err(WadError::Load("The file size is too small."));
         }
 
-        self.info = WadInfo::from(&buffer[0..12]);
+        self.info = WadInfo::from(&bytes[0..12]);
 
         if self.info.kind == WadKind::Unknown {
             return Err(WadError::Type("The file is not a WAD file."));
         }
 
-        self.src = buffer;
+        if self.info.kind.is_texture_wad() {
+            return Err(WadError::Type("The texture WADs are loaded by TextureWad."));
+        }
 
         // Parse lumps
+        self.history.clear();
         self.dir.parse(self.info, &self.src)
     }
 
     /// Load file content from a path
-    pub fn load_from_file<P: Into<PathWrap<&'static str>>>(
-        &mut self,
-        path: P,
-    ) -> Result<(), WadError> {
-        let path = path.into();
-        let buffer: Vec<u8> = path.try_into()?;
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        let buffer: Vec<u8> = PathWrap::from(path).try_into()?;
 
         self.load(buffer)
     }
 
+    /// Memory map the file at `path` then parse it
+    ///
+    /// Only the header, the directory and the touched lumps are
+    /// read from the disk, it fits the big WAD files.
+    ///
+    /// The file must not be truncated or rewritten in place while it is mapped
+    pub fn load_from_mmap<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WadError> {
+        let file = File::open(path).map_err(|e| WadError::Read(e.to_string()))?;
+
+        // Safety: the map is read only, the caller is in charge of not
+        // truncating the file while the `Wad` is alive
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| WadError::Read(e.to_string()))?;
+
+        self.src = Rc::new(map);
+        self.parse_src()
+    }
+
     /// Reparse the WAD
     ///
     /// Could be load after changing the palette index
     pub fn reload(&mut self) -> Result<(), WadError> {
-        self.load(self.src.clone())
+        self.parse_src()
     }
 
-    /// Build the entire WAD buffer based on its abstraction and `self.src`
+    /// Stream the entire WAD based on its abstraction and `self.src`
+    /// into `inner`
     ///
     /// This method avoids us to write the changes directly on `self.src`,
     /// we are able to update or remove lumps without any problems.
     ///
     /// It will be called each time the user will save the entire WAD buffer
-    fn dest(&mut self) -> Vec<u8> {
+    pub fn write<W: Write + Seek>(&self, inner: W) -> Result<W, WadError> {
+        self.output().write(inner)
+    }
+
+    /// Get the output builder, configured with `self.output_options`
+    ///
+    /// Useful to get the build summary with `WadOutput::stats`
+    pub fn output(&self) -> WadOutput<'_> {
         let mut output = WadOutput::new(self.info, &self.dir);
 
-        output.build();
-        output.buffer()
+        output.set_options(self.output_options);
+        output
     }
 
-    /// Get a lump by its name
+    /// Get a lump by its address (`NAME`, `NAME#n`, `MAP01/THINGS`)
     pub fn lump(&self, name: &str) -> Option<&Box<dyn Lump>> {
         self.dir.lump(name)
     }
+
+    /// Get a stable handle to the lump at `address`
+    pub fn handle(&self, address: &str) -> Option<LumpHandle> {
+        let index = self.dir.index(address)?;
+
+        self.dir.lumps[index].metadata().handle
+    }
+
+    /// Get a lump by handle, `None` once it has been removed
+    pub fn lump_by_handle(&self, handle: LumpHandle) -> Option<&dyn Lump> {
+        let index = self.dir.index_of(handle)?;
+
+        Some(self.dir.lumps[index].as_ref())
+    }
+
+    /// Get the current address of the lump `handle`
+    pub fn address(&self, handle: LumpHandle) -> Option<String> {
+        self.dir.address(self.dir.index_of(handle)?)
+    }
+
+    /// Add a DOOM picture encoded from an image file (PNG, etc..)
+    /// with the current palette, `offsets` being its left and top offsets
+    pub fn add_patch(
+        &mut self,
+        name: &str,
+        image: &[u8],
+        offsets: (i16, i16),
+        kind: LumpAddKind<'_>,
+    ) -> Result<(), WadError> {
+        let buffer = self.encode_image(image, TreeFormat::Patch, offsets)?;
+
+        self.add_typed(name, buffer, kind)
+    }
+
+    /// Add a flat encoded from an image file with the current palette
+    pub fn add_flat(
+        &mut self,
+        name: &str,
+        image: &[u8],
+        kind: LumpAddKind<'_>,
+    ) -> Result<(), WadError> {
+        let buffer = self.encode_image(image, TreeFormat::Flat, (0, 0))?;
+
+        self.add_typed(name, buffer, kind)
+    }
+
+    /// Add a DMX sound converted from a WAV file
+    pub fn add_sound(
+        &mut self,
+        name: &str,
+        wav: &[u8],
+        kind: LumpAddKind<'_>,
+    ) -> Result<(), WadError> {
+        let buffer = tree::encode_file(wav.to_vec(), TreeFormat::Wav, self.palettes(), (0, 0))?;
+
+        self.add_typed(name, buffer, kind)
+    }
+
+    /// Add a MUS or MIDI music
+    pub fn add_music(
+        &mut self,
+        name: &str,
+        music: &[u8],
+        kind: LumpAddKind<'_>,
+    ) -> Result<(), WadError> {
+        if !music.starts_with(&MUS_MAGIC) && !music.starts_with(&MIDI_MAGIC) {
+            return Err(WadError::Type("The music must be a MUS or a MIDI file."));
+        }
+
+        self.add_typed(name, music.to_vec(), kind)
+    }
+
+    /// Add a namespace marker (`S_START`, `F_END`, etc..)
+    pub fn add_marker(&mut self, name: &str, kind: LumpAddKind<'_>) -> Result<(), WadError> {
+        if Marker::parse(name).is_none() {
+            return Err(WadError::InvalidNamespace(name.to_string()));
+        }
+
+        self.add_typed(name, Vec::new(), kind)
+    }
+
+    /// Add the map `name`, its marker followed by `lumps`
+    /// (`THINGS`, `LINEDEFS`, etc..) in this order
+    pub fn add_map(
+        &mut self,
+        name: &str,
+        lumps: &[(&str, Vec<u8>)],
+        kind: LumpAddKind<'_>,
+    ) -> Result<(), WadError> {
+        LumpInfo::valid_name(name)?;
+
+        for (lump, _) in lumps.iter() {
+            LumpInfo::valid_name(lump)?;
+        }
+
+        // A single edit, so the map is replayed at its position
+        let lumps = [(name.to_string(), Vec::new())]
+            .into_iter()
+            .chain(
+                lumps
+                    .iter()
+                    .map(|(lump, buffer)| (lump.to_string(), buffer.clone())),
+            )
+            .collect();
+
+        self.apply(Edit::Insert {
+            position: kind.into(),
+            lumps,
+        })
+    }
+
+    /// Iterate over the lumps, following the directory order
+    ///
+    /// The removed lumps are skipped
+    pub fn lumps(&self) -> impl Iterator<Item = &dyn Lump> + '_ {
+        self.dir
+            .lumps
+            .iter()
+            .filter(|lump| lump.metadata().state.is_alive())
+            .map(|lump| lump.as_ref())
+    }
+
+    /// Iterate over the mutable lumps, following the directory order
+    ///
+    /// The removed lumps are skipped
+    pub fn lumps_mut(&mut self) -> impl Iterator<Item = &mut dyn Lump> + '_ {
+        self.dir
+            .lumps
+            .iter_mut()
+            .filter(|lump| lump.metadata().state.is_alive())
+            .map(|lump| lump.as_mut() as &mut dyn Lump)
+    }
+
+    /// Get the namespace of every lump, following the directory order
+    pub fn namespaces(&self) -> Vec<Option<Namespace>> {
+        self.dir.namespaces()
+    }
+
+    /// Iterate over the lumps with their namespace, following the directory order
+    ///
+    /// The removed lumps are skipped
+    pub fn lumps_with_namespace(&self) -> impl Iterator<Item = (&dyn Lump, Option<Namespace>)> {
+        self.dir
+            .lumps
+            .iter()
+            .zip(self.dir.namespaces())
+            .filter(|(lump, _)| lump.metadata().state.is_alive())
+            .map(|(lump, namespace)| (lump.as_ref(), namespace))
+    }
+
+    /// Compare the WAD with `other`, seen as its new version
+    pub fn diff(&self, other: &Wad) -> WadDiff {
+        WadDiff::new(self, other)
+    }
+
+    /// Extract the WAD as a directory tree, with a manifest
+    ///
+    /// The pictures and the sounds are converted when it is lossless
+    pub fn extract_tree<P: AsRef<Path>>(&self, dir: P) -> Result<(), WadError> {
+        tree::extract(self, dir.as_ref(), true)
+    }
+
+    /// Extract the WAD as a directory tree without any conversion,
+    /// so the lumps are rebuilt byte for byte
+    pub fn extract_tree_raw<P: AsRef<Path>>(&self, dir: P) -> Result<(), WadError> {
+        tree::extract(self, dir.as_ref(), false)
+    }
+
+    /// Rebuild a WAD from a directory tree written by `self.extract_tree`
+    pub fn from_tree<P: AsRef<Path>>(dir: P) -> Result<Self, WadError> {
+        let mut wad = Self::new();
+
+        wad.load(tree::build(dir.as_ref())?)?;
+
+        Ok(wad)
+    }
+
+    /// Extract the WAD as a DeuTex tree, with its `wadinfo.txt`
+    pub fn extract_deutex<P: AsRef<Path>>(&self, dir: P) -> Result<(), WadError> {
+        deutex::extract(self, dir.as_ref())
+    }
+
+    /// Build a WAD from a DeuTex tree, `palettes` being used
+    /// to convert the pictures if the tree has no PLAYPAL
+    pub fn from_deutex<P: AsRef<Path>>(
+        dir: P,
+        palettes: Option<&Palettes>,
+    ) -> Result<Self, WadError> {
+        let mut wad = Self::new();
+
+        wad.load(deutex::build(dir.as_ref(), palettes)?)?;
+
+        Ok(wad)
+    }
+
+    /// Check the namespaces markers nesting
+    pub fn validate_namespaces(&self) -> Result<(), WadError> {
+        self.dir.validate_namespaces()
+    }
+
+    /// Run `query` against the lumps
+    pub fn query(&self, query: &LumpQuery) -> LumpSet {
+        query.run(&self.dir)
+    }
+
+    /// Iterate over the lumps in `set`
+    pub fn lumps_in<'a>(&'a self, set: &'a LumpSet) -> impl Iterator<Item = &'a dyn Lump> + 'a {
+        set.indexes()
+            .iter()
+            .filter_map(|i| self.dir.lumps.get(*i))
+            .map(|lump| lump.as_ref())
+    }
+
+    /// Extract the lumps in `set` as file(s) in the directory `dir`
+    pub fn save_lumps_in<P: AsRef<Path>>(&self, set: &LumpSet, dir: P) {
+        let dir = dir.as_ref().to_str().unwrap();
+
+        for lump in self.lumps_in(set) {
+            lump.save(dir);
+        }
+    }
+
+    /// Extract the raw content of the lumps in `set` in the directory `dir`
+    pub fn save_lumps_raw_in<P: AsRef<Path>>(&self, set: &LumpSet, dir: P) {
+        let dir = dir.as_ref().to_str().unwrap();
+
+        for lump in self.lumps_in(set) {
+            Self::save_raw(lump, dir);
+        }
+    }
+
+    /// Remove the lumps in `set`
+    ///
+    /// Only affects `self.save`
+    pub fn remove_in(&mut self, set: &LumpSet) {
+        let indexes: Vec<usize> = set.indexes().to_vec();
+
+        self.remove_indexes(&indexes).unwrap_or_default();
+    }
+
+    /// Update the buffer of the lumps in `set`
+    pub fn update_lumps_raw_in(&mut self, set: &LumpSet, buffer: &[u8]) {
+        for i in set.indexes() {
+            if let Some(id) = self.dir.address(*i) {
+                let buffer = buffer.to_vec();
+
+                self.apply(Edit::SetRaw { id, buffer }).unwrap_or_default();
+            }
+        }
+    }
+
+    /// Update the lumps in `set` from a buffer with its original format
+    pub fn update_lumps_in(&mut self, set: &LumpSet, buffer: &Vec<u8>) {
+        self.update_indexes(set.indexes(), buffer);
+    }
+
+    /// Get the edits history
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Apply `edit` then record it, so it can be undone
+    pub fn apply(&mut self, edit: Edit) -> Result<(), WadError> {
+        let inverse = self.apply_edit(&edit)?;
+
+        self.history.push(edit, inverse);
+
+        Ok(())
+    }
+
+    /// Apply then record every edit of `log`, like on the WAD it comes from
+    pub fn replay(&mut self, log: &EditLog) -> Result<(), WadError> {
+        for edit in log.edits.iter() {
+            self.apply(edit.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// Revert the last applied edit, returns false if there is none
+    pub fn undo(&mut self) -> bool {
+        match self.history.pop_done() {
+            Some((edit, inverse)) => {
+                self.revert(inverse);
+                self.history.push_undone(edit);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Apply again the last undone edit, returns false if there is none
+    pub fn redo(&mut self) -> Result<bool, WadError> {
+        let edit = match self.history.pop_undone() {
+            Some(value) => value,
+            None => return Ok(false),
+        };
+
+        match self.apply_edit(&edit) {
+            Ok(inverse) => {
+                self.history.push_redone(edit, inverse);
+
+                Ok(true)
+            }
+            Err(e) => {
+                self.history.push_undone(edit);
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Name the current state, so it can be restored with `self.restore`
+    pub fn checkpoint(&mut self, name: &str) {
+        self.history.set_checkpoint(name);
+    }
+
+    /// Undo or redo the edits until the checkpoint `name`
+    pub fn restore(&mut self, name: &str) -> Result<(), WadError> {
+        let target = self
+            .history
+            .checkpoint(name)
+            .ok_or(WadError::InvalidOperation)?;
+
+        while self.history.done_len() > target {
+            self.undo();
+        }
+
+        while self.history.done_len() < target {
+            if !self.redo()? {
+                return Err(WadError::InvalidOperation);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply `edit`, returns what is needed to revert it
+    fn apply_edit(&mut self, edit: &Edit) -> Result<Inverse, WadError> {
+        let index = |dir: &LumpsDirectory, id: &str| dir.index(id).ok_or(WadError::InvalidLumpName);
+
+        let ret = match edit {
+            Edit::SetRaw { id, buffer } => {
+                let i = index(&self.dir, id)?;
+                let data = self.dir.lumps[i].data();
+
+                Self::set_raw(self.dir.lumps[i].as_mut(), buffer);
+                Inverse::Data(i, data)
+            }
+            Edit::Remove { id } => {
+                let i = index(&self.dir, id)?;
+                let data = self.dir.lumps[i].data();
+
+                self.info.num_lumps -= self.dir.remove_indexes(&[i]) as i32;
+                Inverse::Data(i, data)
+            }
+            Edit::Add {
+                position,
+                name,
+                buffer,
+            } => self.insert_lumps(position, &[(name, buffer)])?,
+            Edit::Insert { position, lumps } => {
+                let lumps: Vec<(&str, &[u8])> = lumps
+                    .iter()
+                    .map(|(name, buffer)| (name.as_str(), buffer.as_slice()))
+                    .collect();
+
+                self.insert_lumps(position, &lumps)?
+            }
+            Edit::Rename { id, name } => {
+                let i = index(&self.dir, id)?;
+                let data = self.dir.lumps[i].data();
+
+                self.dir.rename_lump(id, name)?;
+                Inverse::Data(i, data)
+            }
+            Edit::Move { id, position } => Inverse::Order(self.dir.move_lump(id, position)?),
+            Edit::Swap { a, b } => Inverse::Order(self.dir.swap_lumps(a, b)?),
+            Edit::Sort(namespace) => Inverse::Order(self.dir.sort_namespace(namespace)),
+            Edit::Group(edits) => {
+                let mut inverses = Vec::with_capacity(edits.len());
+
+                for edit in edits.iter() {
+                    match self.apply_edit(edit) {
+                        Ok(inverse) => inverses.push(inverse),
+                        Err(e) => {
+                            // Nothing of the group stays applied
+                            self.revert(Inverse::Group(inverses));
+
+                            return Err(e);
+                        }
+                    }
+                }
+
+                Inverse::Group(inverses)
+            }
+        };
+
+        Ok(ret)
+    }
+
+    /// Revert an applied edit
+    fn revert(&mut self, inverse: Inverse) {
+        match inverse {
+            Inverse::Data(index, data) => {
+                let alive = self.dir.lumps[index].metadata().state.is_alive();
+
+                self.info.num_lumps += data.metadata.state.is_alive() as i32 - alive as i32;
+                self.dir.lumps[index].set_data(data);
+            }
+            Inverse::Inserted(mut indexes) => {
+                indexes.sort_unstable();
+
+                for index in indexes.into_iter().rev() {
+                    let lump = self.dir.lumps.remove(index);
+
+                    if lump.metadata().state.is_alive() {
+                        self.info.num_lumps -= 1;
+                    }
+                }
+            }
+            Inverse::Order(order) => self.dir.reorder(&invert_order(&order)),
+            Inverse::Group(inverses) => {
+                for inverse in inverses.into_iter().rev() {
+                    self.revert(inverse);
+                }
+            }
+        }
+
+        self.dir.refresh_ids();
+    }
+
+    /// Insert `lumps` one after the other at `position`, classified
+    /// from their name, the namespace and their content
+    ///
+    /// Used by `Edit::Add` and `Edit::Insert`, returns the indexes
+    /// of the new lumps, including the created markers
+    fn insert_lumps(
+        &mut self,
+        position: &LumpPosition,
+        lumps: &[(&str, &[u8])],
+    ) -> Result<Inverse, WadError> {
+        if lumps
+            .iter()
+            .any(|(name, _)| name.is_empty() || name.len() > 8)
+        {
+            return Err(WadError::InvalidLumpName);
+        }
+
+        let len = self.dir.lumps.len();
+        let mut markers = 0;
+
+        if let LumpPosition::Namespace(namespace) = position {
+            markers = self.dir.ensure_namespace(namespace);
+            self.info.num_lumps += markers as i32;
+        }
+
+        let start = self.dir.index_from_position(position)?;
+        let mut indexes: Vec<usize> = (len..len + markers)
+            .map(|i| if i >= start { i + lumps.len() } else { i })
+            .collect();
+
+        for (i, (name, buffer)) in lumps.iter().enumerate() {
+            let index = start + i;
+            let namespace = self.namespace_at(index);
+            let classification = match position {
+                _ if Marker::parse(name).is_some() => {
+                    Classification::new(LumpKind::Marker, Confidence::Certain)
+                }
+                LumpPosition::Namespace(Namespace::Sprites | Namespace::Patches) => {
+                    Classification::new(LumpKind::Patch, Confidence::Certain)
+                }
+                LumpPosition::Namespace(Namespace::Flats) => {
+                    Classification::new(LumpKind::Flat, Confidence::Certain)
+                }
+                _ => self.dir.classifier.classify(&ClassifyInput {
+                    name,
+                    size: buffer.len(),
+                    namespace: namespace.as_ref(),
+                    buffer: Some(buffer),
+                }),
+            };
+
+            // Lump informations
+            let data = LumpData {
+                buffer: buffer.to_vec().into(),
+                metadata: LumpInfo::new(0, buffer.len() as i32, LumpAdd::name_bytes(name)),
+                kind: classification.kind,
+                confidence: classification.confidence,
+            };
+            let lump = match self.dir.build_lump(data, namespace.as_ref()) {
+                Ok(value) => value,
+                Err(e) => {
+                    // The markers stay, like when the position is invalid
+                    self.revert(Inverse::Inserted((start..index).collect()));
+                    return Err(e);
+                }
+            };
+
+            self.dir.lumps.insert(index, lump);
+            self.info.num_lumps += 1;
+            indexes.push(index);
+        }
+
+        self.dir.refresh_ids();
+
+        Ok(Inverse::Inserted(indexes))
+    }
+
+    /// Namespace opened at the directory index `index`
+    fn namespace_at(&self, index: usize) -> Option<Namespace> {
+        let mut stack = NamespaceStack::new();
+
+        for lump in self.dir.lumps[..index].iter() {
+            let metadata = lump.metadata();
+
+            if metadata.state.is_alive() {
+                stack.update(&metadata.name_ascii()).unwrap_or_default();
+            }
+        }
+
+        stack.current().cloned()
+    }
+
+    /// Update the lumps at `indexes` from a buffer with its original format,
+    /// recording their new raw content
+    fn update_indexes(&mut self, indexes: &[usize], buffer: &Vec<u8>) {
+        for i in indexes {
+            let id = match self.dir.address(*i) {
+                Some(value) => value,
+                None => continue,
+            };
+            let lump = &mut self.dir.lumps[*i];
+            let data = lump.data();
+
+            lump.update(buffer);
+
+            let edit = Edit::SetRaw {
+                id,
+                buffer: lump.data().buffer.to_vec(),
+            };
+
+            self.history.push(edit, Inverse::Data(*i, data));
+        }
+    }
+
+    /// Remove the lumps at the directory indexes `indexes` as a single edit
+    fn remove_indexes(&mut self, indexes: &[usize]) -> Result<(), WadError> {
+        let mut indexes = indexes.to_vec();
+
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        // The addresses are found in a single walk
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut ids = Vec::with_capacity(indexes.len());
+
+        for (i, lump) in self.dir.lumps.iter().enumerate() {
+            let metadata = lump.metadata();
+
+            if !metadata.state.is_alive() {
+                continue;
+            }
+
+            let name = metadata.name_ascii();
+            let n = counts.entry(name.clone()).or_default();
+
+            if indexes.binary_search(&i).is_ok() {
+                ids.push(
+                    LumpAddress {
+                        map: None,
+                        name,
+                        n: *n,
+                    }
+                    .to_string(),
+                );
+            }
+
+            *n += 1;
+        }
+
+        // The last ones first, so the other addresses stay valid
+        let edits: Vec<Edit> = ids
+            .into_iter()
+            .rev()
+            .map(|id| Edit::Remove { id })
+            .collect();
+
+        self.apply_group(edits)
+    }
+
+    /// Apply `edits` as a single edit
+    fn apply_group(&mut self, mut edits: Vec<Edit>) -> Result<(), WadError> {
+        match edits.len() {
+            0 => Ok(()),
+            1 => self.apply(edits.remove(0)),
+            _ => self.apply(Edit::Group(edits)),
+        }
+    }
+
+    /// Address of the alive lump `index`
+    fn alive_id(&self, index: usize) -> Result<String, WadError> {
+        self.raw_index(index)
+            .and_then(|i| self.dir.address(i))
+            .ok_or(WadError::InvalidLump)
+    }
+
+    /// Write the lump raw content as `<dir>/<id>.raw`
+    fn save_raw(lump: &dyn Lump, dir: &str) {
+        let data = lump.data();
+        let path = format!("{}/{}.raw", dir, data.metadata.id_ascii());
+
+        fs::write(path, &*data.buffer).unwrap_or_default();
+    }
+
+    /// Encode an image file into a lump buffer of `format`
+    fn encode_image(
+        &self,
+        image: &[u8],
+        format: TreeFormat,
+        offsets: (i16, i16),
+    ) -> Result<Vec<u8>, WadError> {
+        if self.palettes().palette().is_none() {
+            return Err(WadError::Type("A palette is needed to encode the images."));
+        }
+
+        tree::encode_file(image.to_vec(), format, self.palettes(), offsets)
+    }
+
+    /// Add a lump with a validated name
+    fn add_typed(
+        &mut self,
+        name: &str,
+        buffer: Vec<u8>,
+        kind: LumpAddKind<'_>,
+    ) -> Result<(), WadError> {
+        LumpInfo::valid_name(name)?;
+
+        self.apply(Edit::Add {
+            position: kind.into(),
+            name: name.to_string(),
+            buffer,
+        })
+    }
+
+    /// Directory index of the alive lump `index`
+    fn raw_index(&self, index: usize) -> Option<usize> {
+        self.dir
+            .lumps
+            .iter()
+            .enumerate()
+            .filter(|(_, lump)| lump.metadata().state.is_alive())
+            .nth(index)
+            .map(|(raw, _)| raw)
+    }
+
+    /// Replace the lump buffer then update its size
+    fn set_raw(lump: &mut dyn Lump, buffer: &[u8]) {
+        let mut data = lump.data();
+
+        data.buffer = buffer.to_vec().into();
+        data.metadata.size = data.buffer.len() as i32;
+
+        lump.set_data(data);
+    }
 }
 
 impl WadOp for Wad {
+    fn selected(&self) -> Vec<usize> {
+        self.lumps()
+            .enumerate()
+            .filter(|(_, lump)| self.re_name.is_match(&lump.metadata().name_ascii()))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
     fn dump(&self) {
         self.dir
             .callback_lumps(self.re_name.clone(), |lump| println!("{}", lump));
     }
 
+    fn entry_id(&self, index: usize) -> Option<String> {
+        self.lumps()
+            .nth(index)
+            .map(|lump| lump.metadata().id_ascii())
+    }
+
+    fn remove(&mut self) {
+        let re = self.re_name.clone();
+
+        self.remove_by_name(re.as_str()).unwrap_or_default();
+    }
+
     fn save_lumps<P: AsRef<Path>>(&self, dir: P) {
         let dir = dir.as_ref().to_str().unwrap();
 
@@ -240,77 +1054,154 @@ impl WadOp for Wad {
         let dir = dir.as_ref().to_str().unwrap();
 
         self.dir.callback_lumps(self.re_name.clone(), |lump| {
-            let data = lump.data();
-            let path = format!("{}/{}.raw", dir, data.metadata.id_ascii());
-
-            fs::write(path, data.buffer).unwrap_or_default();
+            Self::save_raw(lump.as_ref(), dir)
         });
     }
 
-    fn remove_by_name(&mut self, re: &str) -> Result<(), Error> {
-        let removed = self.dir.remove_lumps(Regex::new(re)?);
+    fn update_lumps(&mut self, buffer: &Vec<u8>) {
+        // TODO: update the metadata in the lump (size)
 
-        self.info.num_lumps -= removed as i32;
+        let indexes: Vec<usize> = (0..self.dir.lumps.len())
+            .filter(|i| {
+                let name = self.dir.lumps[*i].metadata().name_ascii();
 
-        Ok(())
+                self.re_name.is_match(&name)
+            })
+            .collect();
+
+        self.update_indexes(&indexes, buffer);
     }
 
-    fn remove(&mut self) {
-        let removed = self.dir.remove_lumps(self.re_name.clone());
+    fn add_lump_raw(&mut self, add: LumpAdd) -> Result<(), WadError> {
+        let name = LumpInfo::new(0, 0, add.name).name_ascii();
 
-        self.info.num_lumps -= removed as i32;
+        self.apply(Edit::Add {
+            position: add.kind.into(),
+            name,
+            buffer: add.buffer.clone(),
+        })
     }
 
-    fn save<P: AsRef<Path>>(&mut self, path: P) {
-        fs::write(path, self.dest()).unwrap_or_default();
+    fn rename_lump(&mut self, name: &str, value: &str) -> Result<(), WadError> {
+        self.apply(Edit::Rename {
+            id: name.to_string(),
+            name: value.to_string(),
+        })
     }
 
-    fn update_lumps_raw(&mut self, buffer: &Vec<u8>) {
-        self.dir.callback_lumps_mut(self.re_name.clone(), |lump| {
-            let mut data = lump.data();
+    fn move_lump(&mut self, name: &str, kind: LumpAddKind<'_>) -> Result<(), WadError> {
+        self.apply(Edit::Move {
+            id: name.to_string(),
+            position: kind.into(),
+        })
+    }
 
-            data.buffer = buffer.to_vec();
-            data.metadata.size = data.buffer.len() as i32;
+    fn swap_lumps(&mut self, a: &str, b: &str) -> Result<(), WadError> {
+        self.apply(Edit::Swap {
+            a: a.to_string(),
+            b: b.to_string(),
+        })
+    }
 
-            lump.set_data(data);
-        });
+    fn sort_namespace(&mut self, namespace: &Namespace) {
+        self.apply(Edit::Sort(namespace.clone()))
+            .unwrap_or_default();
     }
+}
 
-    fn update_lumps(&mut self, buffer: &Vec<u8>) {
-        // TODO: update the metadata in the lump (size)
+impl Container for Wad {
+    fn load_bytes(&mut self, buffer: Vec<u8>) -> Result<(), WadError> {
+        self.load(buffer)
+    }
 
-        self.dir
-            .callback_lumps_mut(self.re_name.clone(), |lump| lump.update(buffer));
+    fn entries_len(&self) -> usize {
+        self.lumps().count()
     }
 
-    fn add_lump_raw(&mut self, add: LumpAdd) -> Result<(), WadError> {
-        // A little bit hacky, it is just a way to get an unique position
-        // it is useful for building a new WAD
+    fn entry_name(&self, index: usize) -> Option<String> {
+        self.lumps()
+            .nth(index)
+            .map(|lump| lump.metadata().name_ascii())
+    }
 
-        let pos = self
-            .dir
-            .lumps
+    fn entry_buffer(&self, index: usize) -> Option<LumpBuffer> {
+        self.lumps().nth(index).map(|lump| lump.data().buffer)
+    }
+
+    fn set_entry_buffer(&mut self, index: usize, buffer: &[u8]) -> Result<(), WadError> {
+        let id = self.alive_id(index)?;
+
+        self.apply(Edit::SetRaw {
+            id,
+            buffer: buffer.to_vec(),
+        })
+    }
+
+    fn insert_entry(&mut self, index: usize, name: &str, buffer: &[u8]) -> Result<(), WadError> {
+        LumpInfo::valid_name(name)?;
+
+        // Before the lump at `index`, so the edit can be replayed
+        let position = match index == self.entries_len() {
+            true => LumpPosition::Back,
+            false => LumpPosition::Before(self.alive_id(index)?),
+        };
+
+        self.apply(Edit::Insert {
+            position,
+            lumps: vec![(name.to_string(), buffer.to_vec())],
+        })
+    }
+
+    fn remove_entry(&mut self, index: usize) -> Result<(), WadError> {
+        let id = self.alive_id(index)?;
+
+        self.apply(Edit::Remove { id })
+    }
+
+    /// The entries are removed as a single edit
+    fn remove_entries(&mut self, indexes: &[usize]) -> Result<(), WadError> {
+        // Directory index of every alive lump, walked once
+        let alive: Vec<usize> = (0..self.dir.lumps.len())
+            .filter(|i| self.dir.lumps[*i].metadata().state.is_alive())
+            .collect();
+        let indexes = indexes
             .iter()
-            .map(|lump| lump.data().metadata.pos)
-            .max()
-            .unwrap_or(1);
-
-        let metadata = LumpInfo::new(pos + 1, add.buffer.len() as i32, add.name);
-        let unknown = Unknown {
-            data: LumpData {
-                buffer: add.buffer.clone(),
-                metadata,
-                kind: LumpKind::Unknown,
-            },
+            .map(|index| alive.get(*index).copied().ok_or(WadError::InvalidLump))
+            .collect::<Result<Vec<usize>, WadError>>()?;
+
+        self.remove_indexes(&indexes)
+    }
+
+    fn rename_entry(&mut self, index: usize, name: &str) -> Result<(), WadError> {
+        let id = self.alive_id(index)?;
+
+        self.apply(Edit::Rename {
+            id,
+            name: name.to_string(),
+        })
+    }
+
+    fn move_entry(&mut self, from: usize, to: usize) -> Result<(), WadError> {
+        let id = self.alive_id(from)?;
+        let target = self.alive_id(to)?;
+        let position = match from < to {
+            true => LumpPosition::After(target),
+            false => LumpPosition::Before(target),
         };
 
-        // Lump informations
-        let lump: Box<dyn Lump> = Box::new(unknown);
-        let index = self.dir.index_from_kind(add.kind)?;
+        if from == to {
+            return Ok(());
+        }
 
-        self.dir.lumps.insert(index, lump);
-        self.info.num_lumps += 1;
+        self.apply(Edit::Move { id, position })
+    }
 
-        Ok(())
+    fn to_bytes(&self) -> Result<Vec<u8>, WadError> {
+        Ok(self.write(Cursor::new(Vec::new()))?.into_inner())
+    }
+
+    /// The file is replaced atomically
+    fn save_archive<P: AsRef<Path>>(&self, path: P) -> Result<(), WadError> {
+        writer::write_atomic(path, |dest| self.write(dest).map(|_| ()))
     }
 }
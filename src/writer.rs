@@ -0,0 +1,273 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    error::WadError,
+    jaguar,
+    lump::LumpInfo,
+    wad::{WadInfo, WadKind},
+};
+
+/// Where the directory is written in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DirPosition {
+    /// Right after the header
+    Start,
+    /// After the lumps data, like the vanilla tools
+    #[default]
+    End,
+}
+
+/// Streaming WAD writer
+///
+/// The lumps data is written as it comes, only the directory entries
+/// are kept in memory until `self.finish`
+pub struct WadWriter<W: Write + Seek> {
+    /// Destination stream
+    inner: W,
+    /// WAD header
+    info: WadInfo,
+    /// Directory position
+    dir_position: DirPosition,
+    /// Directory entries amount reserved after the header
+    reserved: usize,
+    /// Directory entries
+    entries: Vec<LumpInfo>,
+    /// Next data position
+    pos: u64,
+    /// Lumps data alignment in bytes
+    align: u64,
+    /// Padding bytes written so far
+    padding: u64,
+}
+
+impl<W: Write + Seek> WadWriter<W> {
+    /// Create a writer with the directory at the end of the file
+    pub fn new(inner: W, kind: WadKind) -> Result<Self, WadError> {
+        Self::build(inner, kind, DirPosition::End, 0)
+    }
+
+    /// Create a writer with the directory right after the header
+    ///
+    /// The space for `num_lumps` entries is reserved right away
+    pub fn with_dir_start(inner: W, kind: WadKind, num_lumps: usize) -> Result<Self, WadError> {
+        Self::build(inner, kind, DirPosition::Start, num_lumps)
+    }
+
+    /// Write the header and the reserved directory space
+    fn build(
+        mut inner: W,
+        kind: WadKind,
+        dir_position: DirPosition,
+        reserved: usize,
+    ) -> Result<Self, WadError> {
+        let info = WadInfo {
+            kind,
+            num_lumps: 0,
+            dir_pos: 0,
+        };
+
+        let header: Vec<u8> = info.into();
+        write_all(&mut inner, &header)?;
+        write_all(&mut inner, &vec![0x00; reserved * 16])?;
+
+        Ok(Self {
+            inner,
+            info,
+            dir_position,
+            reserved,
+            entries: Vec::new(),
+            pos: 12 + reserved as u64 * 16,
+            align: 1,
+            padding: 0,
+        })
+    }
+
+    /// Align the lumps data on `value` bytes
+    pub fn set_align(&mut self, value: usize) {
+        self.align = value.max(1) as u64;
+    }
+
+    /// Get the padding bytes written to align the lumps data
+    pub fn padding(&self) -> u64 {
+        self.padding
+    }
+
+    /// Get the position where the next lump data will be written
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Get the entries written so far
+    pub fn entries(&self) -> &[LumpInfo] {
+        &self.entries
+    }
+
+    /// Write the lump data then add its directory entry
+    pub fn write_lump(&mut self, name: [u8; 8], buffer: &[u8]) -> Result<LumpInfo, WadError> {
+        if !buffer.is_empty() {
+            self.pad()?;
+        }
+
+        let pos = i32::try_from(self.pos).map_err(|_| WadError::Write)?;
+        let size = i32::try_from(buffer.len()).map_err(|_| WadError::Write)?;
+        let metadata = LumpInfo::new(pos, size, name);
+
+        write_all(&mut self.inner, buffer)?;
+
+        self.pos += buffer.len() as u64;
+        self.entries.push(metadata);
+
+        Ok(metadata)
+    }
+
+    /// Write the lump data compressed with the Jaguar LZSS, its
+    /// directory entry keeping the decompressed size
+    pub fn write_lump_compressed(
+        &mut self,
+        name: [u8; 8],
+        buffer: &[u8],
+    ) -> Result<LumpInfo, WadError> {
+        let size = i32::try_from(buffer.len()).map_err(|_| WadError::Write)?;
+        let mut metadata = self.write_lump(name, &jaguar::compress(buffer))?;
+
+        metadata.size = size;
+        metadata.compressed = true;
+
+        // Replace the entry pushed by `write_lump`
+        self.entries.pop();
+        self.entries.push(metadata);
+
+        Ok(metadata)
+    }
+
+    /// Write zero bytes until the next data position is aligned
+    fn pad(&mut self) -> Result<(), WadError> {
+        let rest = self.pos % self.align;
+
+        if rest == 0 {
+            return Ok(());
+        }
+
+        let padding = self.align - rest;
+
+        write_all(&mut self.inner, &vec![0x00; padding as usize])?;
+
+        self.pos += padding;
+        self.padding += padding;
+
+        Ok(())
+    }
+
+    /// Add a virtual lump (marker), without data and with a null position
+    pub fn write_marker(&mut self, name: [u8; 8]) -> LumpInfo {
+        let metadata = LumpInfo::new(0, 0, name);
+
+        self.entries.push(metadata);
+
+        metadata
+    }
+
+    /// Add a directory entry that points to data already written
+    ///
+    /// Used when several lumps share the same content
+    pub fn write_entry(&mut self, metadata: LumpInfo) {
+        self.entries.push(metadata);
+    }
+
+    /// Write the directory then patch the header
+    ///
+    /// Returns the destination stream
+    pub fn finish(mut self) -> Result<W, WadError> {
+        let dir_pos = match self.dir_position {
+            DirPosition::Start => {
+                if self.entries.len() > self.reserved {
+                    return Err(WadError::Write);
+                }
+
+                12
+            }
+            DirPosition::End => self.pos,
+        };
+
+        self.info.num_lumps = i32::try_from(self.entries.len()).map_err(|_| WadError::Write)?;
+        self.info.dir_pos = i32::try_from(dir_pos).map_err(|_| WadError::Write)?;
+
+        let mut dir = Vec::with_capacity(self.entries.len() * 16);
+
+        for metadata in self.entries.iter() {
+            let mut bytes: Vec<u8> = (*metadata).into();
+
+            dir.append(&mut bytes);
+        }
+
+        let header: Vec<u8> = self.info.into();
+
+        seek(&mut self.inner, dir_pos)?;
+        write_all(&mut self.inner, &dir)?;
+        seek(&mut self.inner, 0)?;
+        write_all(&mut self.inner, &header)?;
+        seek(&mut self.inner, self.pos.max(dir_pos + dir.len() as u64))?;
+
+        self.inner.flush().map_err(|_| WadError::Write)?;
+
+        Ok(self.inner)
+    }
+}
+
+/// Write the file `path` through a temporary file in the same directory,
+/// renamed over `path` once `f` succeeded
+///
+/// So an error in the middle of the writing never corrupts `path`,
+/// even if it is the file the data is read from
+pub fn write_atomic<P, F>(path: P, f: F) -> Result<(), WadError>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), WadError>,
+{
+    let path = path.as_ref();
+    let tmp = tmp_path(path);
+
+    let result = File::create(&tmp)
+        .map_err(|_| WadError::Write)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+
+            f(&mut writer)?;
+
+            let file = writer.into_inner().map_err(|_| WadError::Write)?;
+
+            file.sync_all().map_err(|_| WadError::Write)
+        })
+        .and_then(|_| fs::rename(&tmp, path).map_err(|_| WadError::Write));
+
+    if result.is_err() {
+        fs::remove_file(&tmp).unwrap_or_default();
+    }
+
+    result
+}
+
+/// Temporary file path used by `write_atomic`
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+
+    name.push(format!(".{}.tmp", process::id()));
+
+    path.with_file_name(name)
+}
+
+fn write_all<W: Write>(inner: &mut W, buffer: &[u8]) -> Result<(), WadError> {
+    inner.write_all(buffer).map_err(|_| WadError::Write)
+}
+
+fn seek<W: Seek>(inner: &mut W, pos: u64) -> Result<(), WadError> {
+    inner
+        .seek(SeekFrom::Start(pos))
+        .map(|_| ())
+        .map_err(|_| WadError::Write)
+}